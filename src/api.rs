@@ -0,0 +1,213 @@
+//! Data of the JSON API.
+//!
+//! The API is served under `/api/v1/`:
+//!
+//! - `GET /api/v1/files/<path>` returns a file with its content and metadata, or a directory with
+//!   its entries.
+//! - `PUT /api/v1/files/<path>` (or `POST`) writes the request body to a file. The optional query
+//!   parameters are `message` (the commit message) and `revision` (the blob id the change is based
+//!   on, empty for new files). If the file has been changed since, nothing is committed.
+//! - `DELETE /api/v1/files/<path>` removes a file. It takes the same query parameters.
+//! - `GET /api/v1/history/<path>` returns the commits that changed a file or directory, newest
+//!   first. The optional `limit` query parameter defaults to 100.
+//!
+//! Errors are returned as `{"error": "message"}` with a fitting status code.
+
+use serde::Serialize;
+
+use crate::git::{GitError, GitItem, Revision};
+use crate::PathStringBuilder;
+
+/// Default number of commits returned by the history endpoint.
+pub const HISTORY_LIMIT: usize = 100;
+
+/// A file or directory.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ItemData {
+    File {
+        path: String,
+        /// Blob id of the file.
+        oid: String,
+        size: usize,
+        /// Content of the file. It is `null` for binary files, which can be downloaded from `raw`.
+        content: Option<String>,
+        /// Link to the unmodified content of the file.
+        raw: String,
+        /// The commit that changed the file last.
+        last_commit: Option<RevisionData>,
+    },
+    Dir {
+        path: String,
+        /// Tree id of the directory.
+        oid: String,
+        entries: Vec<EntryData>,
+    },
+}
+impl ItemData {
+    /// Collects the data of an existing item. Directory entries are sorted by name.
+    pub fn new(item: &GitItem) -> Result<ItemData, GitError> {
+        let path = item.path().to_string();
+        let oid = item.oid()?.to_string();
+        if item.is_dir()? {
+            let mut entries = item
+                .list()?
+                .iter()
+                .map(EntryData::new)
+                .collect::<Result<Vec<_>, _>>()?;
+            entries.sort_by(|a, b| a.name.cmp(&b.name));
+            Ok(ItemData::Dir { path, oid, entries })
+        } else {
+            let content = item.content()?;
+            Ok(ItemData::File {
+                path,
+                oid,
+                size: content.len(),
+                content: String::from_utf8(content).ok(),
+                raw: format!(
+                    "{}?raw",
+                    PathStringBuilder::new(item.path().clone())
+                        .root(true)
+                        .build_percent_encode()
+                ),
+                last_commit: item.history(1)?.pop().map(RevisionData::from),
+            })
+        }
+    }
+}
+
+/// An entry of a directory.
+#[derive(Serialize)]
+pub struct EntryData {
+    name: String,
+    path: String,
+    /// `file` or `dir`.
+    #[serde(rename = "type")]
+    kind: &'static str,
+    oid: String,
+    /// Size of files in bytes. It is `null` for directories.
+    size: Option<usize>,
+}
+impl EntryData {
+    fn new(item: &GitItem) -> Result<EntryData, GitError> {
+        let is_dir = item.is_dir()?;
+        Ok(EntryData {
+            // Entries of a directory always have a filename.
+            name: item.path().filename().unwrap().to_string(),
+            path: item.path().to_string(),
+            kind: if is_dir { "dir" } else { "file" },
+            oid: item.oid()?.to_string(),
+            size: if is_dir { None } else { Some(item.size()?) },
+        })
+    }
+}
+
+/// A commit that changed an item.
+#[derive(Serialize)]
+pub struct RevisionData {
+    commit: String,
+    /// Id of the item after the commit. It is `null` if the commit removed the item.
+    oid: Option<String>,
+    message: String,
+    author: String,
+    email: String,
+    /// Time of the commit in seconds since the epoch.
+    time: i64,
+}
+impl From<Revision> for RevisionData {
+    fn from(revision: Revision) -> Self {
+        RevisionData {
+            commit: revision.commit.to_string(),
+            oid: revision.oid.map(|oid| oid.to_string()),
+            message: revision.message,
+            author: revision.author,
+            email: revision.email,
+            time: revision.time,
+        }
+    }
+}
+
+/// Returned by writing endpoints.
+#[derive(Serialize)]
+pub struct ChangeData {
+    path: String,
+    /// Blob id of the file after the change. It is `null` if the file was removed.
+    oid: Option<String>,
+    /// Whether a commit was created.
+    changed: bool,
+}
+impl ChangeData {
+    pub fn new(item: &GitItem, changed: bool) -> Result<ChangeData, GitError> {
+        Ok(ChangeData {
+            path: item.path().to_string(),
+            oid: if item.exists()? {
+                Some(item.oid()?.to_string())
+            } else {
+                None
+            },
+            changed,
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct ErrorData {
+    pub error: String,
+}
+
+/// Returns the status code and message for errors caused by the request. All other errors are
+/// internal errors.
+pub fn error_status(err: &GitError) -> Option<(u16, &'static str)> {
+    match err {
+        &GitError::NotFound => Some((404, "The file does not exist.")),
+        &GitError::NoParent => Some((400, "The root directory has no parent.")),
+        &GitError::IsDir => Some((409, "The path is a directory.")),
+        &GitError::IsFile => Some((409, "The path is a file.")),
+        &GitError::CannotCreate => Some((409, "A file cannot be created at that location.")),
+        &GitError::NoChange => Some((409, "The file has not changed.")),
+        &GitError::Git(_) | &GitError::IO(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use crate::api::{ChangeData, ItemData};
+    use crate::{GitRepository, Path};
+
+    #[test]
+    fn item_data() {
+        let tmp = TempDir::new("smeagol").unwrap();
+        let repo = GitRepository::new(tmp.path()).unwrap();
+        for (path, content) in &[("docs/a b.md", &b"# A"[..]), ("docs/sub/c.png", b"\xff")] {
+            repo.item(Path::from(path.to_string()))
+                .unwrap()
+                .edit(content, &format!("Add {}", path))
+                .unwrap();
+        }
+
+        let file = repo.item(Path::from("docs/a b.md".to_string())).unwrap();
+        let data = serde_json::to_value(ItemData::new(&file).unwrap()).unwrap();
+        assert_eq!(data["type"], "file");
+        assert_eq!(data["content"], "# A");
+        assert_eq!(data["size"], 3);
+        assert_eq!(data["raw"], "/docs/a%20b.md?raw");
+        assert_eq!(data["last_commit"]["message"], "Add docs/a b.md");
+
+        let binary = repo.item(Path::from("docs/sub/c.png".to_string())).unwrap();
+        let data = serde_json::to_value(ItemData::new(&binary).unwrap()).unwrap();
+        assert!(data["content"].is_null());
+
+        let dir = repo.item(Path::from("docs".to_string())).unwrap();
+        let data = serde_json::to_value(ItemData::new(&dir).unwrap()).unwrap();
+        assert_eq!(data["type"], "dir");
+        assert_eq!(data["entries"][0]["name"], "a b.md");
+        assert_eq!(data["entries"][0]["size"], 3);
+        assert_eq!(data["entries"][1]["type"], "dir");
+        assert!(data["entries"][1]["size"].is_null());
+
+        let data = serde_json::to_value(ChangeData::new(&file, true).unwrap()).unwrap();
+        assert_eq!(data["oid"], file.oid().unwrap().to_string());
+    }
+}
@@ -0,0 +1,268 @@
+//! In-memory caches.
+//!
+//! Rendered pages are cached by blob id and the renderer options; tree lookups by the id of the
+//! root tree and the path; modification times by the id of the commit and the path. Git objects
+//! are immutable, so entries never have to be invalidated. All caches are bounded and evict the
+//! least recently used entries.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+
+use git2::Oid;
+
+use serde::Serialize;
+
+use crate::config::CacheConfig;
+use crate::{Config, Filetype, Path};
+
+/// A least recently used cache bounded by the total weight of its values.
+pub struct LruCache<K, V> {
+    capacity: usize,
+    weigh: fn(&V) -> usize,
+    size: usize,
+    tick: u64,
+    /// Values and the tick of their last use.
+    entries: HashMap<K, (V, u64)>,
+    /// Keys by the tick of their last use.
+    order: BTreeMap<u64, K>,
+    hits: u64,
+    misses: u64,
+}
+impl<K: Hash + Eq + Clone, V: Clone> LruCache<K, V> {
+    /// Creates a cache whose values weigh at most `capacity` in total.
+    pub fn new(capacity: usize, weigh: fn(&V) -> usize) -> LruCache<K, V> {
+        LruCache {
+            capacity,
+            weigh,
+            size: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        self.tick += 1;
+        match self.entries.get_mut(key) {
+            Some(&mut (ref value, ref mut tick)) => {
+                self.order.remove(tick);
+                *tick = self.tick;
+                self.order.insert(self.tick, key.clone());
+                self.hits += 1;
+                Some(value.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Inserts a value. Values heavier than the capacity are not cached at all.
+    pub fn insert(&mut self, key: K, value: V) {
+        let weight = (self.weigh)(&value);
+        if weight > self.capacity {
+            return;
+        }
+        self.remove(&key);
+        while self.size + weight > self.capacity {
+            // The cache cannot be empty because the value fits into an empty cache.
+            let oldest = *self.order.keys().next().unwrap();
+            let key = self.order[&oldest].clone();
+            self.remove(&key);
+        }
+        self.tick += 1;
+        self.size += weight;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (value, self.tick));
+    }
+
+    fn remove(&mut self, key: &K) {
+        if let Some((value, tick)) = self.entries.remove(key) {
+            self.order.remove(&tick);
+            self.size -= (self.weigh)(&value);
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.entries.len(),
+            size: self.size,
+            capacity: self.capacity,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    /// Total weight of the entries (bytes for rendered pages, entries for the other caches).
+    pub size: usize,
+    pub capacity: usize,
+}
+
+/// Identifies a rendered page.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RenderKey {
+    blob: Oid,
+    /// Head commit and path of pages whose rendering depends on other files and on their own
+    /// location (directives).
+    head: Option<(Oid, Path)>,
+    /// Hash of the filetype and the rendering options.
+    options: u64,
+}
+impl RenderKey {
+    pub fn new(
+        blob: Oid,
+        head: Option<(Oid, Path)>,
+        filetype: &Filetype,
+        config: &Config,
+    ) -> RenderKey {
+        let mut hasher = DefaultHasher::new();
+        format!("{:?}", filetype).hash(&mut hasher);
+        // The options are plain data and can always be serialized.
+        serde_json::to_string(&(&config.markdown, &config.sanitize))
+            .unwrap()
+            .hash(&mut hasher);
+        RenderKey {
+            blob,
+            head,
+            options: hasher.finish(),
+        }
+    }
+}
+
+/// The caches shared by all requests.
+pub struct Caches {
+    rendered: Mutex<LruCache<RenderKey, Arc<String>>>,
+    /// Objects by root tree and path. `None` if there is no object at the path.
+    trees: Mutex<LruCache<(Oid, Path), Option<Oid>>>,
+    /// The last commit that changed an item and its time, by commit and path.
+    modified: Mutex<LruCache<(Oid, Path), (Oid, i64)>>,
+}
+impl Caches {
+    pub fn new(config: &CacheConfig) -> Caches {
+        Caches {
+            rendered: Mutex::new(LruCache::new(config.rendered_size, |html| html.len())),
+            trees: Mutex::new(LruCache::new(config.tree_entries, |_| 1)),
+            modified: Mutex::new(LruCache::new(config.modified_entries, |_| 1)),
+        }
+    }
+
+    /// Returns a rendered page from the cache or renders and caches it.
+    pub fn rendered<F, E>(&self, key: RenderKey, render: F) -> Result<Arc<String>, E>
+    where
+        F: FnOnce() -> Result<String, E>,
+    {
+        // The lock is not held while rendering; concurrent misses render twice.
+        if let Some(html) = self.rendered.lock().unwrap().get(&key) {
+            return Ok(html);
+        }
+        let html = Arc::new(render()?);
+        self.rendered.lock().unwrap().insert(key, html.clone());
+        Ok(html)
+    }
+
+    pub fn tree_lookup(&self, root: Oid, path: &Path) -> Option<Option<Oid>> {
+        self.trees.lock().unwrap().get(&(root, path.clone()))
+    }
+
+    pub fn insert_tree_lookup(&self, root: Oid, path: Path, oid: Option<Oid>) {
+        self.trees.lock().unwrap().insert((root, path), oid);
+    }
+
+    pub fn last_change(&self, commit: Oid, path: &Path) -> Option<(Oid, i64)> {
+        self.modified.lock().unwrap().get(&(commit, path.clone()))
+    }
+
+    pub fn insert_last_change(&self, commit: Oid, path: Path, change: (Oid, i64)) {
+        self.modified.lock().unwrap().insert((commit, path), change);
+    }
+
+    pub fn stats(&self) -> (CacheStats, CacheStats, CacheStats) {
+        (
+            self.rendered.lock().unwrap().stats(),
+            self.trees.lock().unwrap().stats(),
+            self.modified.lock().unwrap().stats(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use crate::cache::{Caches, LruCache, RenderKey};
+    use crate::config::CacheConfig;
+    use crate::directive::Directives;
+    use crate::git::GitError;
+    use crate::{Config, Filetype, GitRepository, Path};
+
+    #[test]
+    fn lru_eviction() {
+        let mut cache = LruCache::new(6, |value: &String| value.len());
+        cache.insert(1, "aa".to_string());
+        cache.insert(2, "bb".to_string());
+        cache.insert(3, "cc".to_string());
+        // 1 is used and therefore more recent than 2.
+        assert_eq!(cache.get(&1), Some("aa".to_string()));
+        cache.insert(4, "dd".to_string());
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1), Some("aa".to_string()));
+        assert_eq!(cache.get(&3), Some("cc".to_string()));
+
+        // Too large values are not cached.
+        cache.insert(5, "eeeeeee".to_string());
+        assert_eq!(cache.get(&5), None);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (3, 2));
+        assert_eq!((stats.entries, stats.size), (3, 6));
+    }
+
+    #[test]
+    fn render_key_path() {
+        let tmp = TempDir::new("smeagol").unwrap();
+        let repo = GitRepository::new(tmp.path()).unwrap();
+        let config: Config =
+            toml::from_str("bind = \"\"\nindex = \"\"\nmax_upload_size = 0\nrepo = \"\"").unwrap();
+        for path in &["a/index.md", "a/one.md", "b/index.md", "b/two.md"] {
+            repo.item(Path::from(path.to_string()))
+                .unwrap()
+                .edit(b"{{children}}", "Add")
+                .unwrap();
+        }
+        let caches = Caches::new(&CacheConfig::default());
+        let head = repo.head_id().unwrap();
+
+        // Both index pages have the same blob, but list different children.
+        let render = |path: &str| {
+            let path = Path::from(path.to_string());
+            let item = repo.item(path.clone()).unwrap();
+            let key = RenderKey::new(
+                item.oid().unwrap(),
+                Some((head, path.clone())),
+                &Filetype::Markdown,
+                &config,
+            );
+            caches
+                .rendered(key, || -> Result<String, GitError> {
+                    Directives::new(&repo, &config).expand(&path, "{{children}}")
+                })
+                .unwrap()
+        };
+        let a = render("a/index.md");
+        let b = render("b/index.md");
+        assert!(a.contains("one.md") && !a.contains("two.md"));
+        assert!(b.contains("two.md") && !b.contains("one.md"));
+        assert_eq!(render("a/index.md"), a);
+    }
+}
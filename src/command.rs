@@ -0,0 +1,275 @@
+//! Rendering using external commands.
+//!
+//! Filetypes with a `command` in `[filetypes]` are rendered by running the command with the file
+//! content on stdin. The command has to write HTML to stdout. The output is cached on disk; the
+//! cache key consists of the blob id and the command, so cache entries never have to be
+//! invalidated.
+//!
+//! Commands run in their own process group. When a command times out or exits, the whole group is
+//! killed, so processes it started cannot keep running or keep its output open.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use git2::Oid;
+
+use log::warn;
+
+use crate::config::CommandConfig;
+
+/// Number of cache files written so far. It makes the names of temporary files unique.
+static CACHE_FILES: AtomicUsize = AtomicUsize::new(0);
+
+pub struct CommandRenderer<'a> {
+    config: &'a CommandConfig,
+}
+impl<'a> CommandRenderer<'a> {
+    pub fn new(config: &'a CommandConfig) -> CommandRenderer<'a> {
+        CommandRenderer { config }
+    }
+
+    /// Renders `content` using `command` (program and arguments). `oid` has to be the id of the
+    /// blob containing `content`.
+    pub fn render(
+        &self,
+        oid: Oid,
+        command: &[String],
+        content: &[u8],
+    ) -> Result<String, CommandError> {
+        let cache_file = self.cache_file(oid, command);
+        if let Ok(cached) = std::fs::read_to_string(&cache_file) {
+            return Ok(cached);
+        }
+
+        let output = String::from_utf8(self.run(command, content)?)
+            .map_err(|_| CommandError::InvalidUtf8)?;
+
+        // The output is still usable if it cannot be cached.
+        if let Err(err) = write_cache_file(&cache_file, output.as_bytes()) {
+            warn!("Could not cache command output {:?}: {}", cache_file, err);
+        }
+
+        Ok(output)
+    }
+
+    /// Runs the command and returns its stdout.
+    fn run(&self, command: &[String], content: &[u8]) -> Result<Vec<u8>, CommandError> {
+        // Commands are checked to be non-empty when loading the config.
+        let mut child = Command::new(&command[0]);
+        child
+            .args(&command[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut child, 0);
+        let mut child = child.spawn()?;
+
+        // Stdin and the outputs are handled in threads. Otherwise the command could block
+        // writing while we are still writing its input.
+        let mut stdin = child.stdin.take().unwrap();
+        let content = content.to_vec();
+        let writer = thread::spawn(move || {
+            // The command may exit without reading everything. This is not an error.
+            let _ = stdin.write_all(&content);
+        });
+        let max_output_size = self.config.max_output_size;
+        let mut stdout = child.stdout.take().unwrap();
+        let reader = thread::spawn(move || -> io::Result<Vec<u8>> {
+            let mut output = vec![];
+            stdout
+                .by_ref()
+                .take(max_output_size + 1)
+                .read_to_end(&mut output)?;
+            Ok(output)
+        });
+        let mut stderr = child.stderr.take().unwrap();
+        let error_reader = thread::spawn(move || {
+            let mut error = vec![];
+            let _ = stderr.by_ref().take(4096).read_to_end(&mut error);
+            String::from_utf8_lossy(&error).trim().to_string()
+        });
+
+        // Stdout is closed once the output limit is reached, which stops most commands.
+        let deadline = Instant::now() + Duration::from_secs(self.config.timeout);
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                kill(&mut child);
+                let _ = child.wait();
+                return Err(CommandError::Timeout);
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+
+        // Processes started by the command could keep its outputs open.
+        kill(&mut child);
+        let _ = writer.join();
+        // Joining only fails if the thread panicked.
+        let output = reader.join().unwrap()?;
+        let error = error_reader.join().unwrap();
+        if output.len() as u64 > max_output_size {
+            return Err(CommandError::OutputTooLarge);
+        }
+        if !status.success() {
+            return Err(CommandError::Failed(status, error));
+        }
+        Ok(output)
+    }
+
+    fn cache_file(&self, oid: Oid, command: &[String]) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        command.hash(&mut hasher);
+        PathBuf::from(&self.config.cache).join(format!("{}-{:016x}.html", oid, hasher.finish()))
+    }
+}
+
+/// Kills the process group of a command. A process group is not reused while any of its processes
+/// exists, so this is safe after the command exited.
+#[cfg(unix)]
+fn kill(child: &mut Child) {
+    // Process ids fit into `pid_t`.
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+#[cfg(not(unix))]
+fn kill(child: &mut Child) {
+    let _ = child.kill();
+}
+
+/// Writes a file of a disk cache. The data is written to a temporary file, which is then renamed,
+/// so that concurrent readers never see a partially written file.
+pub fn write_cache_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(
+        ".{}-{}.tmp",
+        std::process::id(),
+        CACHE_FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = std::fs::write(&tmp, data).and_then(|_| std::fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    result
+}
+
+#[derive(Debug)]
+pub enum CommandError {
+    IO(io::Error),
+    Timeout,
+    OutputTooLarge,
+    Failed(ExitStatus, String),
+    InvalidUtf8,
+}
+impl std::error::Error for CommandError {}
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CommandError::IO(ref err) => write!(f, "IO error: {}", err),
+            CommandError::Timeout => write!(f, "Command timed out"),
+            CommandError::OutputTooLarge => write!(f, "Command output too large"),
+            CommandError::Failed(ref status, ref error) => {
+                write!(f, "Command failed ({}): {}", status, error)
+            }
+            CommandError::InvalidUtf8 => write!(f, "Command output is not valid UTF-8"),
+        }
+    }
+}
+impl From<io::Error> for CommandError {
+    fn from(err: io::Error) -> Self {
+        CommandError::IO(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use git2::Oid;
+    use tempdir::TempDir;
+
+    use crate::command::{CommandError, CommandRenderer};
+    use crate::config::CommandConfig;
+
+    fn config(tmp: &TempDir) -> CommandConfig {
+        CommandConfig {
+            cache: tmp.path().to_str().unwrap().to_string(),
+            ..CommandConfig::default()
+        }
+    }
+
+    fn command(command: &str) -> Vec<String> {
+        vec!["sh".to_string(), "-c".to_string(), command.to_string()]
+    }
+
+    #[test]
+    fn render_and_cache() {
+        let tmp = TempDir::new("smeagol").unwrap();
+        let config = config(&tmp);
+        let renderer = CommandRenderer::new(&config);
+        let oid = Oid::from_str("0123456789012345678901234567890123456789").unwrap();
+
+        let html = renderer
+            .render(oid, &command("printf '<p>'; cat; printf '</p>'"), b"text")
+            .unwrap();
+        assert_eq!(html, "<p>text</p>");
+
+        // The cached output is used even if the content differs.
+        let html = renderer
+            .render(oid, &command("printf '<p>'; cat; printf '</p>'"), b"other")
+            .unwrap();
+        assert_eq!(html, "<p>text</p>");
+        // Only the cache file is left.
+        assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn errors() {
+        let tmp = TempDir::new("smeagol").unwrap();
+        let config = CommandConfig {
+            timeout: 1,
+            max_output_size: 10,
+            ..config(&tmp)
+        };
+        let renderer = CommandRenderer::new(&config);
+
+        match renderer.run(&command("sleep 5"), b"") {
+            Err(CommandError::Timeout) => {}
+            result => panic!("{:?}", result),
+        }
+        // Processes started by the command are killed as well.
+        let started = Instant::now();
+        match renderer.run(&command("sleep 5 & echo done"), b"") {
+            Ok(output) => assert_eq!(output, b"done\n"),
+            result => panic!("{:?}", result),
+        }
+        match renderer.run(&command("sleep 5 & sleep 5"), b"") {
+            Err(CommandError::Timeout) => {}
+            result => panic!("{:?}", result),
+        }
+        assert!(started.elapsed() < Duration::from_secs(4));
+        match renderer.run(&command("yes"), b"") {
+            Err(CommandError::OutputTooLarge) => {}
+            result => panic!("{:?}", result),
+        }
+        match renderer.run(&command("echo broken >&2; exit 3"), b"") {
+            Err(CommandError::Failed(_, ref error)) => assert_eq!(error, "broken"),
+            result => panic!("{:?}", result),
+        }
+    }
+}
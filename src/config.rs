@@ -1,9 +1,14 @@
+use std::collections::BTreeMap;
 use std::fmt;
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::path::Path;
 
 use serde::{Deserialize, Serialize};
 
+use warp::http::header::HeaderValue;
+
+use crate::Filetype;
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Config {
     pub bind: String,
@@ -12,12 +17,42 @@ pub struct Config {
     pub max_upload_size: u64,
 
     pub repo: String,
+
+    #[serde(default)]
+    pub sanitize: SanitizeConfig,
+    #[serde(default)]
+    pub markdown: MarkdownConfig,
+    #[serde(default)]
+    pub images: ImageConfig,
+    /// Overrides of the filetype detection, keyed by pattern (see `FiletypeRule`).
+    #[serde(default)]
+    pub filetypes: BTreeMap<String, FiletypeRule>,
+    #[serde(default)]
+    pub commands: CommandConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
 }
 
 impl Config {
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, ConfigError> {
         let content = std::fs::read_to_string(path)?;
-        Ok(toml::from_str(&content)?)
+        let config: Config = toml::from_str(&content)?;
+        for rule in config.filetypes.values() {
+            if let Some(ref renderer) = rule.renderer {
+                if Filetype::from_name(renderer, &crate::Path::new()).is_none() {
+                    return Err(ConfigError::UnknownRenderer(renderer.to_string()));
+                }
+            }
+            if rule.command.as_ref().is_some_and(Vec::is_empty) {
+                return Err(ConfigError::EmptyCommand);
+            }
+            if let Some(ref content_type) = rule.content_type {
+                if HeaderValue::from_str(content_type).is_err() {
+                    return Err(ConfigError::InvalidContentType(content_type.to_string()));
+                }
+            }
+        }
+        Ok(config)
     }
 
     pub fn parse_bind(&self) -> Result<SocketAddr, ConfigError> {
@@ -30,19 +65,205 @@ impl Config {
     }
 }
 
+/// Allow-list used to sanitize the HTML produced by markup renderers.
+///
+/// The lists extend the defaults of `ammonia`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SanitizeConfig {
+    /// How raw HTML in markdown is handled.
+    pub raw_html: RawHtml,
+    /// Additional allowed tags.
+    pub tags: Vec<String>,
+    /// Additional attributes allowed on all tags.
+    pub attributes: Vec<String>,
+    /// Allowed URL schemes. Replaces the default list if given.
+    pub url_schemes: Option<Vec<String>>,
+}
+impl Default for SanitizeConfig {
+    fn default() -> SanitizeConfig {
+        SanitizeConfig {
+            raw_html: RawHtml::Sanitize,
+            tags: vec![],
+            attributes: vec![],
+            url_schemes: None,
+        }
+    }
+}
+
+/// How raw HTML in markdown is handled, set with `raw_html` in `[sanitize]`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RawHtml {
+    /// Raw HTML is kept. All rendered markup is sanitized with the allow-list.
+    Sanitize,
+    /// Raw HTML is left out of rendered markdown. All rendered markup is still sanitized.
+    Omit,
+    /// Raw HTML is kept and rendered markup is not sanitized at all. Only for repositories whose
+    /// editors are trusted.
+    Trust,
+}
+
+/// Markdown options. Most of them enable an extension of `comrak`.
+///
+/// Pages can override them in their front matter, e.g. `markdown: { footnotes: false }`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct MarkdownConfig {
+    /// Render soft line breaks as hard line breaks.
+    pub hardbreaks: bool,
+    /// Convert quotes, dashes and ellipses to their typographic counterparts.
+    pub smart: bool,
+    /// Use GitHub style `<pre lang="...">` for code blocks.
+    pub github_pre_lang: bool,
+    /// Language of code blocks without one.
+    pub default_info_string: Option<String>,
+    /// `~~strikethrough~~`
+    pub strikethrough: bool,
+    /// Escape some raw HTML tags like `<script>` and `<iframe>` (GitHub style).
+    pub tagfilter: bool,
+    /// Tables.
+    pub table: bool,
+    /// Link bare URLs and email addresses.
+    pub autolink: bool,
+    /// `- [ ]` task lists.
+    pub tasklist: bool,
+    /// `e = mc^2^`
+    pub superscript: bool,
+    /// Add anchors to headings. The value is prefixed to the ids.
+    pub header_ids: Option<String>,
+    /// `[^1]` footnotes.
+    pub footnotes: bool,
+    /// Description lists (term, then `: details`).
+    pub description_lists: bool,
+}
+impl Default for MarkdownConfig {
+    fn default() -> MarkdownConfig {
+        MarkdownConfig {
+            hardbreaks: false,
+            smart: false,
+            github_pre_lang: false,
+            default_info_string: None,
+            strikethrough: true,
+            tagfilter: false,
+            table: true,
+            autolink: false,
+            tasklist: true,
+            superscript: false,
+            header_ids: None,
+            footnotes: false,
+            description_lists: false,
+        }
+    }
+}
+
+/// Settings for resized images (`?w=`, `?h=` and `?thumb`).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ImageConfig {
+    /// Size of the box thumbnails are fitted in.
+    pub thumbnail_size: u32,
+    /// Largest width or height that can be requested.
+    pub max_size: u32,
+    /// Directory resized images are cached in.
+    pub cache: String,
+}
+impl Default for ImageConfig {
+    fn default() -> ImageConfig {
+        ImageConfig {
+            thumbnail_size: 200,
+            max_size: 2048,
+            cache: "cache/images/".to_string(),
+        }
+    }
+}
+
+/// Overrides how files matching a pattern in `[filetypes]` are shown.
+///
+/// Patterns starting with `.` match an extension (`.markdown`). Patterns containing `/` are
+/// matched against the whole path, all others against the filename (`README`, `*.txt`). `*`
+/// matches anything but `/`, `**` matches anything and `?` matches a single character.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct FiletypeRule {
+    /// Name of the renderer, e.g. `markdown`, `csv`, `code:py` or `raw` (see
+    /// `Filetype::from_name`). The detected renderer is used if it is not given.
+    pub renderer: Option<String>,
+    /// Content type used when the file is served directly.
+    pub content_type: Option<String>,
+    /// Whether the file is shown in the browser (`true`) or downloaded (`false`) when it is
+    /// served directly. `false` also disables rendering.
+    pub inline: Option<bool>,
+    /// Program and arguments of an external renderer. It gets the file on stdin and has to write
+    /// HTML to stdout. Takes precedence over `renderer`.
+    pub command: Option<Vec<String>>,
+}
+
+/// Limits of external renderers (see `FiletypeRule::command`).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct CommandConfig {
+    /// Time in seconds after which commands are killed.
+    pub timeout: u64,
+    /// Maximum size of the output in bytes.
+    pub max_output_size: u64,
+    /// Directory the output is cached in.
+    pub cache: String,
+}
+impl Default for CommandConfig {
+    fn default() -> CommandConfig {
+        CommandConfig {
+            timeout: 10,
+            max_output_size: 10_000_000,
+            cache: "cache/commands/".to_string(),
+        }
+    }
+}
+
+/// Sizes of the in-memory caches.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// Maximum total size of cached rendered pages in bytes.
+    pub rendered_size: usize,
+    /// Maximum number of cached tree lookups.
+    pub tree_entries: usize,
+    /// Maximum number of cached modification times of files.
+    pub modified_entries: usize,
+}
+impl Default for CacheConfig {
+    fn default() -> CacheConfig {
+        CacheConfig {
+            rendered_size: 64_000_000,
+            tree_entries: 100_000,
+            modified_entries: 100_000,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum ConfigError {
     Io(std::io::Error),
     Toml(toml::de::Error),
     InvalidSocketAddress,
+    UnknownRenderer(String),
+    InvalidContentType(String),
+    EmptyCommand,
 }
 impl std::error::Error for ConfigError {}
 impl std::fmt::Display for ConfigError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            &ConfigError::Io(ref err) => write!(f, "IO error: {}", err),
-            &ConfigError::Toml(ref err) => write!(f, "TOML error: {}", err),
-            &ConfigError::InvalidSocketAddress => write!(f, "Invalid socket address"),
+        match *self {
+            ConfigError::Io(ref err) => write!(f, "IO error: {}", err),
+            ConfigError::Toml(ref err) => write!(f, "TOML error: {}", err),
+            ConfigError::InvalidSocketAddress => write!(f, "Invalid socket address"),
+            ConfigError::UnknownRenderer(ref renderer) => {
+                write!(f, "Unknown renderer: {}", renderer)
+            }
+            ConfigError::InvalidContentType(ref content_type) => {
+                write!(f, "Invalid content type: {}", content_type)
+            }
+            ConfigError::EmptyCommand => write!(f, "Empty renderer command"),
         }
     }
 }
@@ -0,0 +1,509 @@
+//! Directives in markdown pages.
+//!
+//! Directives have the form `{{name argument}}` and have to stand on a line of their own. They are
+//! expanded in the markdown source right before rendering, so the stored pages never contain the
+//! expanded text. Directives inside fenced code blocks are left alone.
+//!
+//! Supported directives:
+//!
+//! - `{{include path#section}}` includes another page (or only one section of it). Paths are
+//!   relative to the directory of the including page unless they start with `/`. Included pages
+//!   are expanded recursively; cycles and too deeply nested includes are reported in the page.
+//!   Tasks of included pages are shown as symbols instead of checkboxes.
+//! - `{{children}}` lists the files and directories next to the page.
+//! - `{{pages under="dir/" sort="modified" limit="10"}}` lists all files below a directory
+//!   (default: the directory of the page). They are sorted by `name` (default) or by `modified`,
+//!   newest first.
+
+use lazy_static::lazy_static;
+
+use regex::Regex;
+
+use crate::filetype::split_front_matter;
+use crate::git::{GitError, GitItem};
+use crate::markup;
+use crate::sections::sections;
+use crate::tasks;
+use crate::{Config, Filetype, GitRepository, Path, PathStringBuilder};
+
+/// Maximum number of nested includes.
+pub const MAX_INCLUDE_DEPTH: usize = 8;
+
+lazy_static! {
+    static ref DIRECTIVE: Regex =
+        Regex::new(r"^\{\{\s*(?P<name>[a-z]+)(?:\s+(?P<argument>[^}]*?))?\s*\}\}$").unwrap();
+    static ref ARGUMENT: Regex = Regex::new(r#"(?P<key>[a-z]+)="(?P<value>[^"]*)""#).unwrap();
+}
+
+pub struct Directives<'a> {
+    repo: &'a GitRepository,
+    config: &'a Config,
+}
+impl<'a> Directives<'a> {
+    pub fn new(repo: &'a GitRepository, config: &'a Config) -> Directives<'a> {
+        Directives { repo, config }
+    }
+
+    /// Expands all directives in the markdown `source` of the page at `path`.
+    pub fn expand(&self, path: &Path, source: &str) -> Result<String, GitError> {
+        self.expand_nested(path, source, &mut vec![path.clone()])
+    }
+
+    /// Expands the directives of a page. `stack` contains the pages currently being expanded,
+    /// beginning with the requested page.
+    fn expand_nested(
+        &self,
+        path: &Path,
+        source: &str,
+        stack: &mut Vec<Path>,
+    ) -> Result<String, GitError> {
+        let mut expanded = String::with_capacity(source.len());
+        let mut fence = Fence::default();
+        for line in source.lines() {
+            let directive = if fence.update(line) {
+                None
+            } else {
+                DIRECTIVE.captures(line.trim())
+            };
+            match directive {
+                Some(captures) => {
+                    let argument = captures.name("argument").map_or("", |m| m.as_str());
+                    match &captures["name"] {
+                        "include" => expanded.push_str(&self.include(path, argument, stack)?),
+                        "children" => expanded.push_str(&self.children(path)?),
+                        "pages" => expanded.push_str(&self.pages(path, argument)?),
+                        _ => expanded.push_str(line),
+                    }
+                }
+                None => expanded.push_str(line),
+            }
+            expanded.push('\n');
+        }
+        Ok(expanded)
+    }
+
+    fn include(
+        &self,
+        path: &Path,
+        argument: &str,
+        stack: &mut Vec<Path>,
+    ) -> Result<String, GitError> {
+        let (reference, section) = match argument.find('#') {
+            Some(index) => (&argument[..index], Some(&argument[index + 1..])),
+            None => (argument, None),
+        };
+        // Pages always have a parent.
+        let target = match path
+            .parent()
+            .and_then(|parent| parent.resolve(reference.as_bytes()))
+        {
+            Some(target) => target,
+            None => {
+                return Ok(error_note(
+                    "include",
+                    &format!("Invalid include path {}", argument),
+                ))
+            }
+        };
+        if stack.contains(&target) {
+            return Ok(error_note(
+                "include",
+                &format!("Cyclic include of {}", target),
+            ));
+        }
+        if stack.len() > MAX_INCLUDE_DEPTH {
+            return Ok(error_note(
+                "include",
+                &format!("Includes nested deeper than {} levels", MAX_INCLUDE_DEPTH),
+            ));
+        }
+
+        let content = match self.repo.item(target.clone())?.content() {
+            Ok(content) => content,
+            Err(GitError::NotFound) | Err(GitError::IsDir) => {
+                return Ok(error_note("include", &format!("Could not find {}", target)));
+            }
+            Err(err) => return Err(err),
+        };
+        let content = match String::from_utf8(content) {
+            Ok(content) => content,
+            Err(_) => {
+                return Ok(error_note(
+                    "include",
+                    &format!("{} is not a text file", target),
+                ))
+            }
+        };
+
+        match Filetype::for_path(&target, self.config) {
+            Filetype::Markdown => {
+                // The options of the including page are used. Only the tasks of the including
+                // page can be toggled.
+                let content = tasks::freeze(split_front_matter(&content).1);
+                let content = match section {
+                    Some(section) => match extract_section(&content, section) {
+                        Some(content) => content,
+                        None => {
+                            return Ok(error_note(
+                                "include",
+                                &format!("Could not find section {} in {}", section, target),
+                            ));
+                        }
+                    },
+                    None => content,
+                };
+                stack.push(target.clone());
+                let expanded = self.expand_nested(&target, &content, stack);
+                stack.pop();
+                expanded
+            }
+            // Everything else is included verbatim.
+            _ => {
+                let fence = "`".repeat(longest_backtick_run(&content).max(2) + 1);
+                Ok(format!(
+                    "{}{}\n{}\n{}",
+                    fence,
+                    String::from_utf8_lossy(&target.extension().unwrap_or_default()),
+                    content.trim_end_matches('\n'),
+                    fence
+                ))
+            }
+        }
+    }
+
+    fn children(&self, path: &Path) -> Result<String, GitError> {
+        // Pages always have a parent.
+        let directory = self.repo.item(path.parent().unwrap())?;
+        let mut children = directory
+            .list()?
+            .into_iter()
+            .filter(|child| child.path() != path)
+            .map(|child| Ok((child.is_dir()?, child.path().clone())))
+            .collect::<Result<Vec<_>, GitError>>()?;
+        // Directories first, like in the directory listing.
+        children.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+
+        Ok(children
+            .into_iter()
+            .map(|(is_dir, child)| {
+                // Children always have a filename.
+                let mut name = child.filename().unwrap().to_string();
+                if is_dir {
+                    name.push('/');
+                }
+                list_link(&name, child, is_dir)
+            })
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    fn pages(&self, path: &Path, argument: &str) -> Result<String, GitError> {
+        let mut under = ".";
+        let mut sort_by_modified = false;
+        let mut limit = None;
+        for argument in ARGUMENT.captures_iter(argument) {
+            let value = argument.name("value").unwrap().as_str();
+            match &argument["key"] {
+                "under" => under = value,
+                "sort" if value == "name" => sort_by_modified = false,
+                "sort" if value == "modified" => sort_by_modified = true,
+                "limit" => match value.parse::<usize>() {
+                    Ok(value) => limit = Some(value),
+                    Err(_) => return Ok(error_note("pages", &format!("Invalid limit {}", value))),
+                },
+                _ => {
+                    return Ok(error_note(
+                        "pages",
+                        &format!("Invalid argument {}", &argument[0]),
+                    ))
+                }
+            }
+        }
+
+        // Pages always have a parent.
+        let directory = match path.parent().unwrap().resolve(under.as_bytes()) {
+            Some(directory) => self.repo.item(directory)?,
+            None => return Ok(error_note("pages", &format!("Invalid directory {}", under))),
+        };
+        let mut pages = vec![];
+        match collect_files(&directory, &mut pages) {
+            Ok(()) => {}
+            Err(GitError::NotFound) | Err(GitError::IsFile) => {
+                return Ok(error_note(
+                    "pages",
+                    &format!("Could not find directory {}", under),
+                ));
+            }
+            Err(err) => return Err(err),
+        }
+        pages.retain(|page| page.path() != path);
+
+        let mut pages = if sort_by_modified {
+            let mut pages = directory
+                .last_modified_below(&pages)?
+                .into_iter()
+                .zip(pages.iter().map(|page| page.path().clone()))
+                .collect::<Vec<_>>();
+            // Newest first, ties by name.
+            pages.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+            pages.into_iter().map(|(_, page)| page).collect()
+        } else {
+            let mut pages = pages
+                .into_iter()
+                .map(|page| page.path().clone())
+                .collect::<Vec<_>>();
+            pages.sort();
+            pages
+        };
+        if let Some(limit) = limit {
+            pages.truncate(limit);
+        }
+
+        Ok(pages
+            .into_iter()
+            .map(|page| list_link(&page.to_string(), page, false))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+}
+
+/// Adds all files in and below `item` to `files`.
+pub fn collect_files<'repo>(
+    item: &GitItem<'repo>,
+    files: &mut Vec<GitItem<'repo>>,
+) -> Result<(), GitError> {
+    for child in item.list()? {
+        if child.is_dir()? {
+            collect_files(&child, files)?;
+        } else {
+            files.push(child);
+        }
+    }
+    Ok(())
+}
+
+/// Renders a markdown list item linking to `path`.
+fn list_link(text: &str, path: Path, is_dir: bool) -> String {
+    format!(
+        "- [{}](<{}>)",
+        markup::escape(text),
+        PathStringBuilder::new(path)
+            .root(true)
+            .dir(is_dir)
+            .build_percent_encode()
+    )
+}
+
+/// Tracks whether lines are inside a fenced code block.
+#[derive(Default)]
+pub struct Fence {
+    /// Opening fence of the current code block.
+    open: Option<String>,
+}
+impl Fence {
+    /// Updates the state with the next line. Returns whether the line belongs to a code block
+    /// (including the fences).
+    pub fn update(&mut self, line: &str) -> bool {
+        let trimmed = line.trim_start();
+        let marker: String = trimmed
+            .chars()
+            .take_while(|c| *c == '`' || *c == '~')
+            .collect();
+        let is_fence = marker.len() >= 3 && marker.chars().all(|c| marker.starts_with(c));
+        match &self.open {
+            Some(open) => {
+                if is_fence
+                    && marker.starts_with(open.as_str())
+                    && trimmed[marker.len()..].trim().is_empty()
+                {
+                    self.open = None;
+                }
+                true
+            }
+            None if is_fence => {
+                self.open = Some(marker);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Returns the section of a markdown document whose heading has the anchor `anchor`, including
+/// the heading. The section ends at the next heading of the same or a higher level.
+fn extract_section(source: &str, anchor: &str) -> Option<String> {
+    let section = sections(source)
+        .into_iter()
+        .find(|section| slug(&section.title) == anchor)?;
+    let text = &source[section.start..section.end];
+    // The newline of the last line belongs to the following heading.
+    let text = text.strip_suffix('\n').unwrap_or(text);
+    Some(text.strip_suffix('\r').unwrap_or(text).to_string())
+}
+
+/// Returns the anchor of a heading: lowercase, punctuation removed and spaces replaced by `-`.
+pub fn slug(heading: &str) -> String {
+    heading
+        .trim()
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_alphanumeric() || *c == ' ' || *c == '-' || *c == '_')
+        .map(|c| if c == ' ' { '-' } else { c })
+        .collect()
+}
+
+fn longest_backtick_run(text: &str) -> usize {
+    text.split(|c| c != '`').map(str::len).max().unwrap_or(0)
+}
+
+/// Renders an error of a directive as markdown quote so that the page is still shown.
+fn error_note(directive: &str, message: &str) -> String {
+    format!(
+        "> **Error in {} directive:** {}",
+        directive,
+        markup::escape(message)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use crate::directive::{extract_section, slug, Directives};
+    use crate::{Config, GitRepository, Path};
+
+    fn config() -> Config {
+        toml::from_str("bind = \"\"\nindex = \"\"\nmax_upload_size = 0\nrepo = \"\"").unwrap()
+    }
+
+    fn repository(files: &[(&str, &str)]) -> (TempDir, GitRepository) {
+        let tmp = TempDir::new("smeagol").unwrap();
+        let repo = GitRepository::new(tmp.path()).unwrap();
+        for (path, content) in files {
+            repo.item(Path::from(path.to_string()))
+                .unwrap()
+                .edit(content.as_bytes(), "Add file")
+                .unwrap();
+        }
+        (tmp, repo)
+    }
+
+    #[test]
+    fn section() {
+        let source = "# Team\n\n## On-call\n\nAlice\n\n### Backup\n\nBob\n\n## Other\n\nCarol";
+        assert_eq!(
+            extract_section(source, "on-call").unwrap(),
+            "## On-call\n\nAlice\n\n### Backup\n\nBob\n"
+        );
+        assert_eq!(
+            extract_section(source, "backup").unwrap(),
+            "### Backup\n\nBob\n"
+        );
+        assert!(extract_section("```\n# oncall\n```", "oncall").is_none());
+        assert_eq!(slug(" What's up? "), "whats-up");
+    }
+
+    #[test]
+    fn include() {
+        let config = config();
+        let (_tmp, repo) = repository(&[
+            (
+                "shared/contacts.md",
+                "# Contacts\n\n## Oncall\n\nAlice\n\n## Sales\n\nBob",
+            ),
+            ("shared/todo.md", "- [ ] Call Alice\n- [x] Call Bob"),
+            ("docs/page.md", ""),
+        ]);
+        let expanded = Directives::new(&repo, &config)
+            .expand(
+                &Path::from("docs/page.md".to_string()),
+                "Intro\n{{include ../shared/contacts.md#oncall}}\n```\n{{include x.md}}\n```",
+            )
+            .unwrap();
+        assert_eq!(
+            expanded,
+            "Intro\n## Oncall\n\nAlice\n\n```\n{{include x.md}}\n```\n"
+        );
+
+        // Tasks of included pages cannot be toggled.
+        let expanded = Directives::new(&repo, &config)
+            .expand(
+                &Path::from("docs/page.md".to_string()),
+                "{{include /shared/todo.md}}",
+            )
+            .unwrap();
+        assert_eq!(expanded, "- \u{2610} Call Alice\n- \u{2611} Call Bob\n\n");
+    }
+
+    #[test]
+    fn include_errors() {
+        let config = config();
+        let (_tmp, repo) = repository(&[
+            ("a.md", "{{include b.md}}"),
+            ("b.md", "{{include /a.md}}"),
+            ("self.md", "{{include self.md}}"),
+        ]);
+        let directives = Directives::new(&repo, &config);
+        let expanded = directives
+            .expand(&Path::from("a.md".to_string()), "{{include b.md}}")
+            .unwrap();
+        assert_eq!(
+            expanded,
+            "> **Error in include directive:** Cyclic include of a.md\n\n"
+        );
+
+        let expanded = directives
+            .expand(&Path::from("a.md".to_string()), "{{include missing.md}}")
+            .unwrap();
+        assert_eq!(
+            expanded,
+            "> **Error in include directive:** Could not find missing.md\n"
+        );
+
+        let expanded = directives
+            .expand(&Path::from("a.md".to_string()), "{{include ../../x.md}}")
+            .unwrap();
+        assert!(expanded.starts_with("> **Error in include directive:** Invalid include path"));
+    }
+
+    #[test]
+    fn listings() {
+        let config = config();
+        let (_tmp, repo) = repository(&[
+            ("projects/index.md", ""),
+            ("projects/b.md", ""),
+            ("projects/a/x y.md", ""),
+            ("other.md", ""),
+        ]);
+        let directives = Directives::new(&repo, &config);
+        let index = Path::from("projects/index.md".to_string());
+
+        assert_eq!(
+            directives.expand(&index, "{{children}}").unwrap(),
+            "- [a/](</projects/a/>)\n- [b.md](</projects/b.md>)\n"
+        );
+        assert_eq!(
+            directives
+                .expand(
+                    &Path::from("index.md".to_string()),
+                    "{{pages under=\"projects/\"}}"
+                )
+                .unwrap(),
+            "- [projects/a/x y.md](</projects/a/x%20y.md>)\n\
+             - [projects/b.md](</projects/b.md>)\n\
+             - [projects/index.md](</projects/index.md>)\n"
+        );
+        assert_eq!(
+            directives
+                .expand(&index, "{{pages sort=\"modified\" limit=\"1\"}}")
+                .unwrap()
+                .lines()
+                .count(),
+            1
+        );
+        assert!(directives
+            .expand(&index, "{{pages under=\"missing/\"}}")
+            .unwrap()
+            .starts_with("> **Error in pages directive:** Could not find directory"));
+    }
+}
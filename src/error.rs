@@ -1,5 +1,7 @@
 use handlebars::{RenderError, TemplateFileError};
 
+use image::ImageError;
+
 use serde_json::Error as JsonError;
 
 use std::fmt;
@@ -7,26 +9,34 @@ use std::fmt;
 use crate::config::ConfigError;
 use crate::filetype::ParsingError;
 use crate::git::GitError;
+use crate::mediawiki::MediaWikiError;
 
 #[derive(Debug)]
 pub enum SmeagolError {
     Git(GitError),
     Config(ConfigError),
-    TemplateFile(TemplateFileError),
+    /// Boxed because it is much larger than the other errors, which are returned often.
+    TemplateFile(Box<TemplateFileError>),
     TemplateRender(RenderError),
     SerdeJson(JsonError),
     Parsing(ParsingError),
+    Image(ImageError),
+    IO(std::io::Error),
+    MediaWiki(MediaWikiError),
 }
 impl std::error::Error for SmeagolError {}
 impl fmt::Display for SmeagolError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            &SmeagolError::Git(ref err) => write!(f, "Git error: {}", err),
-            &SmeagolError::Config(ref err) => write!(f, "Config error: {}", err),
-            &SmeagolError::TemplateFile(ref err) => write!(f, "Template file error: {}", err),
-            &SmeagolError::TemplateRender(ref err) => write!(f, "Template render error: {}", err),
-            &SmeagolError::SerdeJson(ref err) => write!(f, "Json error: {}", err),
-            &SmeagolError::Parsing(ref err) => write!(f, "Parsing error: {}", err),
+        match *self {
+            SmeagolError::Git(ref err) => write!(f, "Git error: {}", err),
+            SmeagolError::Config(ref err) => write!(f, "Config error: {}", err),
+            SmeagolError::TemplateFile(ref err) => write!(f, "Template file error: {}", err),
+            SmeagolError::TemplateRender(ref err) => write!(f, "Template render error: {}", err),
+            SmeagolError::SerdeJson(ref err) => write!(f, "Json error: {}", err),
+            SmeagolError::Parsing(ref err) => write!(f, "Parsing error: {}", err),
+            SmeagolError::Image(ref err) => write!(f, "Image error: {}", err),
+            SmeagolError::IO(ref err) => write!(f, "IO error: {}", err),
+            SmeagolError::MediaWiki(ref err) => write!(f, "MediaWiki import error: {}", err),
         }
     }
 }
@@ -42,7 +52,7 @@ impl From<ConfigError> for SmeagolError {
 }
 impl From<TemplateFileError> for SmeagolError {
     fn from(err: TemplateFileError) -> Self {
-        SmeagolError::TemplateFile(err)
+        SmeagolError::TemplateFile(Box::new(err))
     }
 }
 impl From<RenderError> for SmeagolError {
@@ -60,7 +70,22 @@ impl From<ParsingError> for SmeagolError {
         SmeagolError::Parsing(err)
     }
 }
+impl From<ImageError> for SmeagolError {
+    fn from(err: ImageError) -> Self {
+        SmeagolError::Image(err)
+    }
+}
 
+impl From<std::io::Error> for SmeagolError {
+    fn from(err: std::io::Error) -> Self {
+        SmeagolError::IO(err)
+    }
+}
+impl From<MediaWikiError> for SmeagolError {
+    fn from(err: MediaWikiError) -> Self {
+        SmeagolError::MediaWiki(err)
+    }
+}
 impl From<SmeagolError> for warp::reject::Rejection {
     fn from(err: SmeagolError) -> warp::reject::Rejection {
         warp::reject::custom(err)
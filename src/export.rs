@@ -0,0 +1,558 @@
+//! Export of the wiki as static HTML files.
+//!
+//! Pages are rendered with the same templates as the server. Afterwards all internal links are
+//! rewritten to relative links to the exported files, so the export can be served from any
+//! directory (or opened from disk).
+//!
+//! Every file of the tree is copied unchanged (for `?raw` links and binary assets). Files that are
+//! shown as a page are additionally rendered to a `.html` file replacing their extension. Links to
+//! pages that cannot be rendered point to their source instead. Links to directories point to
+//! the page of their `index` file like on the server. Each directory gets an `index.html`: its
+//! index page or a listing.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+
+use handlebars::Handlebars;
+
+use lazy_static::lazy_static;
+
+use log::{info, warn};
+
+use regex::{Captures, Regex};
+
+use serde::Serialize;
+
+use crate::directive::Directives;
+use crate::git::GitItem;
+use crate::{Config, Filetype, GitRepository, Path, PathStringBuilder, SmeagolError};
+
+lazy_static! {
+    static ref LINK: Regex =
+        Regex::new(r#"(?P<attribute>\b(?:href|src))="(?P<link>[^"]*)""#).unwrap();
+}
+
+/// Data for `get.html.hbs`.
+#[derive(Serialize)]
+struct TemplateGetData {
+    path: String,
+    parent_list_link: String,
+    content: String,
+    safe: bool,
+    read_only: bool,
+}
+/// Data for `list.html.hbs`.
+#[derive(Serialize)]
+struct TemplateListData {
+    path: String,
+    parent_list_link: Option<String>,
+    children: Vec<TemplateListChildData>,
+    read_only: bool,
+}
+#[derive(Serialize)]
+struct TemplateListChildData {
+    link: String,
+    name: String,
+    thumbnail: Option<String>,
+}
+
+/// A file or directory of the exported tree.
+enum Entry {
+    Directory,
+    /// A file shown as a page. Contains the path of the rendered page.
+    Page(Path),
+    /// A file that is only copied.
+    Asset,
+}
+
+pub struct Exporter<'a> {
+    repo: &'a GitRepository,
+    config: &'a Config,
+    templates: &'a Handlebars,
+    outdir: PathBuf,
+    entries: BTreeMap<Path, Entry>,
+}
+impl<'a> Exporter<'a> {
+    pub fn new<P: Into<PathBuf>>(
+        repo: &'a GitRepository,
+        config: &'a Config,
+        templates: &'a Handlebars,
+        outdir: P,
+    ) -> Exporter<'a> {
+        Exporter {
+            repo,
+            config,
+            templates,
+            outdir: outdir.into(),
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Exports the whole tree and the `static/` directory. Returns the number of rendered pages.
+    pub fn export(mut self) -> Result<usize, SmeagolError> {
+        self.entries.insert(Path::new(), Entry::Directory);
+        self.collect(self.repo.item(Path::new())?)?;
+
+        // Links are rewritten once all pages are rendered, when it is known which pages exist.
+        let mut rendered = HashSet::new();
+        let mut failed = vec![];
+        for (path, entry) in &self.entries {
+            match *entry {
+                Entry::Directory => std::fs::create_dir_all(self.output_path(path))?,
+                Entry::Page(ref page) => {
+                    let content = self.repo.item(path.clone())?.content()?;
+                    std::fs::write(self.output_path(path), &content)?;
+                    // A broken page (e.g. a failing render command) should not abort the
+                    // export. Only its source is exported then.
+                    match self.render_page(path, content) {
+                        Ok(html) => {
+                            std::fs::write(self.output_path(page), html)?;
+                            rendered.insert(page.clone());
+                        }
+                        Err(SmeagolError::Parsing(err)) => {
+                            warn!("Could not render {}: {}", path, err);
+                            failed.push(path.clone());
+                        }
+                        Err(err) => return Err(err),
+                    }
+                }
+                Entry::Asset => {
+                    let content = self.repo.item(path.clone())?.content()?;
+                    std::fs::write(self.output_path(path), &content)?;
+                }
+            }
+        }
+        for path in failed {
+            self.entries.insert(path, Entry::Asset);
+        }
+        for page in &rendered {
+            let html = std::fs::read_to_string(self.output_path(page))?;
+            std::fs::write(self.output_path(page), self.rewrite_links(&html, page))?;
+        }
+
+        // Directories without an `index.html` get a copy of their index page or a listing.
+        let mut pages = rendered.len();
+        for (path, entry) in &self.entries {
+            if let Entry::Directory = *entry {
+                let index = index_page(path);
+                if rendered.contains(&index) || self.entries.contains_key(&index) {
+                    continue;
+                }
+                match self.index_of(path) {
+                    // The index page is in the same directory, so its links stay valid.
+                    Some(page) => std::fs::copy(self.output_path(&page), self.output_path(&index))
+                        .map(|_| ())?,
+                    None => {
+                        let html = self.render_listing(path)?;
+                        std::fs::write(
+                            self.output_path(&index),
+                            self.rewrite_links(&html, &index),
+                        )?;
+                        pages += 1;
+                    }
+                }
+            }
+        }
+
+        copy_dir(&PathBuf::from("static"), &self.outdir.join("static"))?;
+        info!("Exported {} pages to {:?}", pages, self.outdir);
+        Ok(pages)
+    }
+
+    /// Returns the rendered page of the `index` file of a directory, if there is one.
+    fn index_of(&self, directory: &Path) -> Option<Path> {
+        if self.config.index.is_empty() {
+            return None;
+        }
+        let mut index = directory.clone();
+        index.push(self.config.index.to_string());
+        match *self.entries.get(&index)? {
+            Entry::Page(ref page) => Some(page.clone()),
+            _ => None,
+        }
+    }
+
+    /// Collects all entries below `item` and decides which files are shown as pages.
+    fn collect(&mut self, item: GitItem) -> Result<(), SmeagolError> {
+        let mut taken = HashSet::new();
+        for child in item.list()? {
+            let path = child.path().clone();
+            if child.is_dir()? {
+                self.entries.insert(path, Entry::Directory);
+                self.collect(child)?;
+                continue;
+            }
+
+            let content = child.content()?;
+            let filetype = Filetype::detect(&path, &content, self.config);
+            let inline =
+                crate::filetype::filetype_rule(&path, self.config).and_then(|rule| rule.inline);
+            let is_page = inline != Some(false)
+                && (filetype.is_media()
+                    || match filetype {
+                        Filetype::Command(_) => true,
+                        _ => !filetype.is_raw() && std::str::from_utf8(&content).is_ok(),
+                    });
+            if is_page {
+                // Pages replace their extension. Pages whose name would clash keep it.
+                let mut page = page_path(&path, true);
+                if taken.contains(&page) || self.is_file(&page)? {
+                    page = page_path(&path, false);
+                }
+                taken.insert(page.clone());
+                self.entries.insert(path, Entry::Page(page));
+            } else {
+                self.entries.insert(path, Entry::Asset);
+            }
+        }
+        Ok(())
+    }
+
+    fn is_file(&self, path: &Path) -> Result<bool, SmeagolError> {
+        let item = self.repo.item(path.clone())?;
+        Ok(item.exists()? && item.is_file()?)
+    }
+
+    /// Renders a file like `Smeagol::get`.
+    fn render_page(&self, path: &Path, content: Vec<u8>) -> Result<String, SmeagolError> {
+        let filetype = Filetype::detect(path, &content, self.config);
+        let link = PathStringBuilder::new(path.clone())
+            .root(true)
+            .build_percent_encode();
+        let (content, safe) = if filetype.is_media() {
+            (filetype.embed(&format!("{}?raw", link)), true)
+        } else if let Filetype::Command(_) = filetype {
+            let oid = self.repo.item(path.clone())?.oid()?;
+            (filetype.parse_command(oid, &content, self.config)?, true)
+        } else {
+            // Pages are checked to be text in `collect`.
+            let source = String::from_utf8(content).unwrap();
+            let source = match filetype {
+                Filetype::Markdown => {
+                    Directives::new(self.repo, self.config).expand(path, &source)?
+                }
+                _ => source,
+            };
+            (filetype.parse(&source, self.config)?, filetype.is_safe())
+        };
+
+        Ok(self.templates.render(
+            "get.html",
+            &TemplateGetData {
+                path: path.to_string(),
+                // Files always have a parent.
+                parent_list_link: format!(
+                    "{}?list",
+                    PathStringBuilder::new(path.parent().unwrap())
+                        .root(true)
+                        .build_percent_encode()
+                ),
+                content,
+                safe,
+                read_only: true,
+            },
+        )?)
+    }
+
+    /// Renders a directory listing like `Smeagol::list`.
+    fn render_listing(&self, path: &Path) -> Result<String, SmeagolError> {
+        let mut children = self
+            .repo
+            .item(path.clone())?
+            .list()?
+            .into_iter()
+            .map(|child| Ok((child.is_dir()?, child.path().clone())))
+            .collect::<Result<Vec<_>, SmeagolError>>()?;
+        // Directories first.
+        children.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+
+        Ok(self.templates.render(
+            "list.html",
+            &TemplateListData {
+                path: PathStringBuilder::new(path.clone()).dir(true).build_lossy(),
+                parent_list_link: path.parent().map(|parent| {
+                    format!(
+                        "{}?list",
+                        PathStringBuilder::new(parent)
+                            .root(true)
+                            .build_percent_encode()
+                    )
+                }),
+                children: children
+                    .into_iter()
+                    .map(|(is_dir, child)| {
+                        let link = PathStringBuilder::new(child.clone())
+                            .root(true)
+                            .dir(is_dir)
+                            .build_percent_encode();
+                        TemplateListChildData {
+                            thumbnail: if Filetype::for_path(&child, self.config).is_image() {
+                                Some(format!("{}?raw", link))
+                            } else {
+                                None
+                            },
+                            link,
+                            // Children always have a filename.
+                            name: PathStringBuilder::new(child.filename().unwrap())
+                                .dir(is_dir)
+                                .build_lossy(),
+                        }
+                    })
+                    .collect(),
+                read_only: true,
+            },
+        )?)
+    }
+
+    /// Rewrites all internal links of a page at `page` to relative links.
+    fn rewrite_links(&self, html: &str, page: &Path) -> String {
+        LINK.replace_all(html, |captures: &Captures| {
+            let link = captures["link"].replace("&amp;", "&");
+            match self.rewrite_link(&link, page) {
+                Some(link) => format!(
+                    "{}=\"{}\"",
+                    &captures["attribute"],
+                    handlebars::html_escape(&link)
+                ),
+                None => captures[0].to_string(),
+            }
+        })
+        .to_string()
+    }
+
+    /// Maps a link to the exported file it points to. External links and links to missing
+    /// files are not changed (`None`).
+    fn rewrite_link(&self, link: &str, page: &Path) -> Option<String> {
+        let (link, fragment) = match link.find('#') {
+            Some(index) => (&link[..index], &link[index..]),
+            None => (link, ""),
+        };
+        let (link, query) = match link.find('?') {
+            Some(index) => (&link[..index], &link[index + 1..]),
+            None => (link, ""),
+        };
+        let scheme = link
+            .find(':')
+            .is_some_and(|index| !link[..index].contains('/'));
+        if scheme || link.starts_with("//") || (link.is_empty() && query.is_empty()) {
+            return None;
+        }
+
+        // Pages always have a parent.
+        let directory = page.parent().unwrap();
+        let target = if link.is_empty() {
+            // Links consisting of a query refer to the source of the page.
+            self.source_of(page)?
+        } else {
+            let decoded = percent_encoding::percent_decode(link.as_bytes()).collect::<Vec<_>>();
+            directory.resolve(&decoded)?
+        };
+        if link.starts_with("/static/") {
+            return Some(relative_link(&directory, &target) + fragment);
+        }
+
+        let query_tag = |tag: &str| {
+            query
+                .split('&')
+                .any(|part| part.split('=').next() == Some(tag))
+        };
+        let output = match *self.entries.get(&target)? {
+            Entry::Directory => self
+                .index_of(&target)
+                .unwrap_or_else(|| index_page(&target)),
+            // `?list` of a file redirects to the file.
+            Entry::Page(_) if query_tag("raw") || query_tag("thumb") => target,
+            Entry::Page(ref page) => page.clone(),
+            Entry::Asset => target,
+        };
+        Some(relative_link(&directory, &output) + fragment)
+    }
+
+    /// Returns the file a rendered page (or directory index) was created from.
+    fn source_of(&self, page: &Path) -> Option<Path> {
+        self.entries
+            .iter()
+            .find(|(_, entry)| match *entry {
+                Entry::Page(ref rendered) => rendered == page,
+                _ => false,
+            })
+            .map(|(path, _)| path.clone())
+    }
+
+    fn output_path(&self, path: &Path) -> PathBuf {
+        self.outdir.join(std::path::PathBuf::from(path))
+    }
+}
+
+/// Returns the path of the page rendered from a file. The extension is either replaced by or
+/// extended with `.html`.
+fn page_path(path: &Path, replace_extension: bool) -> Path {
+    let mut name = path.bytes().to_vec();
+    if replace_extension {
+        if let Some(extension) = path.extension() {
+            name.truncate(name.len() - extension.len() - 1);
+        }
+    }
+    name.extend_from_slice(b".html");
+    Path::from(name)
+}
+
+/// Returns the path of the index page of a directory.
+fn index_page(directory: &Path) -> Path {
+    let mut index = directory.clone();
+    index.push("index.html".to_string());
+    index
+}
+
+/// Returns a percent encoded link from a page in `directory` to `target`.
+fn relative_link(directory: &Path, target: &Path) -> String {
+    let directory = directory
+        .segments()
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>();
+    let target_segments = target
+        .segments()
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>();
+    let common = directory
+        .iter()
+        .zip(target_segments.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut link = "../".repeat(directory.len() - common);
+    link += &Path::from(target_segments[common..].join(&b'/')).percent_encode();
+    if link.is_empty() {
+        link.push_str("./");
+    }
+    link
+}
+
+fn copy_dir(from: &std::path::Path, to: &std::path::Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &to.join(entry.file_name()))?;
+        } else {
+            std::fs::copy(entry.path(), to.join(entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use handlebars::Handlebars;
+    use tempdir::TempDir;
+
+    use crate::export::{relative_link, Exporter};
+    use crate::{Config, GitRepository, Path};
+
+    #[test]
+    fn relative_links() {
+        let link = |from: &str, to: &str| {
+            relative_link(&Path::from(from.to_string()), &Path::from(to.to_string()))
+        };
+        assert_eq!(link("a/b", "a/b/c.html"), "c.html");
+        assert_eq!(link("a/b", "a/d e.html"), "../d%20e.html");
+        assert_eq!(link("", "a/index.html"), "a/index.html");
+        assert_eq!(link("a", "a"), "./");
+    }
+
+    #[test]
+    fn export() {
+        let tmp = TempDir::new("smeagol").unwrap();
+        let repo = GitRepository::new(tmp.path().join("repo")).unwrap();
+        let files: &[(&str, &[u8])] = &[
+            (
+                "index.md",
+                b"[B](docs/b.md) [Docs](/docs/) [Web](https://example.com)",
+            ),
+            ("docs/b.md", b"[Home](../index.md#top) ![Logo](logo.png)"),
+            ("docs/logo.png", b"\x89PNG\r\n\x1a\n"),
+        ];
+        for (path, content) in files {
+            repo.item(Path::from(path.to_string()))
+                .unwrap()
+                .edit(content, "Add file")
+                .unwrap();
+        }
+        let mut config: Config =
+            toml::from_str("bind = \"\"\nindex = \"index.md\"\nmax_upload_size = 0\nrepo = \"\"")
+                .unwrap();
+        config.repo = tmp.path().join("repo").to_str().unwrap().to_string();
+        let mut templates = Handlebars::new();
+        templates
+            .register_templates_directory(".hbs", "templates/")
+            .unwrap();
+
+        let out = tmp.path().join("out");
+        let pages = Exporter::new(&repo, &config, &templates, &out)
+            .export()
+            .unwrap();
+        // index, docs/b and the listing of docs
+        assert_eq!(pages, 3);
+
+        let index = std::fs::read_to_string(out.join("index.html")).unwrap();
+        assert!(index.contains("href=\"docs/b.html\""));
+        assert!(index.contains("href=\"docs/index.html\""));
+        assert!(index.contains("href=\"https://example.com\""));
+        assert!(index.contains("href=\"static/style.css\""));
+        assert!(!index.contains("?edit"));
+        let page = std::fs::read_to_string(out.join("docs/b.html")).unwrap();
+        assert!(page.contains("href=\"../index.html#top\""));
+        assert!(page.contains("src=\"logo.png\""));
+        assert!(page.contains("href=\"b.md\""));
+        assert!(out.join("docs/index.html").exists());
+        assert!(out.join("docs/logo.png").exists());
+        assert!(out.join("static/style.css").exists());
+    }
+
+    #[test]
+    fn export_index_and_failed_pages() {
+        let tmp = TempDir::new("smeagol").unwrap();
+        let repo = GitRepository::new(tmp.path().join("repo")).unwrap();
+        let files: &[(&str, &[u8])] = &[
+            ("README.md", b"[Guide](guide/) [Broken](broken.fail)"),
+            ("guide/README.md", b"[Back](../)"),
+            ("broken.fail", b"x"),
+        ];
+        for (path, content) in files {
+            repo.item(Path::from(path.to_string()))
+                .unwrap()
+                .edit(content, "Add file")
+                .unwrap();
+        }
+        let mut config: Config = toml::from_str(
+            "bind = \"\"\nindex = \"README.md\"\nmax_upload_size = 0\nrepo = \"\"\n\
+             [filetypes.\".fail\"]\ncommand = [\"/nonexistent/renderer\"]",
+        )
+        .unwrap();
+        config.repo = tmp.path().join("repo").to_str().unwrap().to_string();
+        let mut templates = Handlebars::new();
+        templates
+            .register_templates_directory(".hbs", "templates/")
+            .unwrap();
+
+        let out = tmp.path().join("out");
+        let pages = Exporter::new(&repo, &config, &templates, &out)
+            .export()
+            .unwrap();
+        assert_eq!(pages, 2);
+
+        // Directories link to their index page, which is also their `index.html`.
+        let readme = std::fs::read_to_string(out.join("README.html")).unwrap();
+        assert!(readme.contains("href=\"guide/README.html\""));
+        assert_eq!(
+            std::fs::read_to_string(out.join("index.html")).unwrap(),
+            readme
+        );
+        let guide = std::fs::read_to_string(out.join("guide/index.html")).unwrap();
+        assert!(guide.contains("href=\"../README.html\""));
+
+        // The page that could not be rendered is linked to its source.
+        assert!(readme.contains("href=\"broken.fail\""));
+        assert!(!out.join("broken.html").exists());
+        assert!(out.join("broken.fail").exists());
+    }
+}
@@ -1,70 +1,810 @@
 use std::fmt;
 
+use git2::Oid;
+
+use lazy_static::lazy_static;
+
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::html::{styled_line_to_highlighted_html, IncludeBackground};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+use crate::command::{CommandError, CommandRenderer};
+use crate::config::{FiletypeRule, MarkdownConfig, RawHtml, SanitizeConfig};
 use crate::warp_helper::ContentType;
-use crate::Path;
+use crate::{markup, sections, tasks};
+use crate::{Config, Path};
+
+/// Extensions of files that are shown as highlighted source code.
+///
+/// Extensions without a matching syntax definition are shown with line numbers only.
+const CODE_EXTENSIONS: &[&str] = &[
+    "bash", "c", "cc", "conf", "cpp", "cs", "css", "go", "h", "hpp", "hs", "html", "ini", "java",
+    "js", "lua", "pl", "php", "py", "rb", "rs", "sh", "sql", "ts", "xml",
+];
+
+lazy_static! {
+    // Loading the syntax definitions takes a while. They are therefore only loaded once.
+    static ref SYNTAX_SET: SyntaxSet = SyntaxSet::load_defaults_newlines();
+    static ref THEME: Theme = ThemeSet::load_defaults().themes["InspiredGitHub"].clone();
+}
 
 #[derive(Debug)]
 pub enum Filetype {
     Raw,
     Png,
     Jpg,
+    Gif,
+    WebP,
+    Svg,
+    Pdf,
+    Mp3,
+    Ogg,
+    Mp4,
+    WebM,
     Markdown,
+    AsciiDoc,
+    ReStructuredText,
+    Org,
+    Csv,
+    Tsv,
+    Json,
+    Toml,
+    Yaml,
+    /// Source code. Contains the extension used to select the syntax.
+    Code(String),
+    /// Rendered by an external command. Contains the program and its arguments.
+    Command(Vec<String>),
 }
+// The predicates list their filetypes in a `match` like the other methods.
+#[allow(clippy::match_like_matches_macro)]
 impl Filetype {
     pub fn is_safe(&self) -> bool {
-        match self {
-            &Filetype::Markdown => true,
+        match *self {
+            Filetype::Markdown => true,
+            Filetype::AsciiDoc => true,
+            Filetype::ReStructuredText => true,
+            Filetype::Org => true,
+            Filetype::Csv => true,
+            Filetype::Tsv => true,
+            Filetype::Json => true,
+            Filetype::Toml => true,
+            Filetype::Yaml => true,
+            Filetype::Code(_) => true,
+            Filetype::Command(_) => true,
             _ => false,
         }
     }
 
     pub fn is_raw(&self) -> bool {
-        match self {
-            &Filetype::Png => true,
-            &Filetype::Jpg => true,
+        match *self {
+            Filetype::Png => true,
+            Filetype::Jpg => true,
+            Filetype::Gif => true,
+            Filetype::WebP => true,
+            Filetype::Svg => true,
+            Filetype::Pdf => true,
             _ => false,
         }
     }
     pub fn is_raw_inline(&self) -> bool {
-        match self {
-            &Filetype::Png => true,
-            &Filetype::Jpg => true,
+        match *self {
+            Filetype::Png => true,
+            Filetype::Jpg => true,
+            Filetype::Gif => true,
+            Filetype::WebP => true,
+            Filetype::Svg => true,
+            Filetype::Pdf => true,
             _ => panic!("Attempted to check raw inlining for non-raw filetype"),
         }
     }
 
+    /// Whether the file is an image that can be resized.
+    pub fn is_image(&self) -> bool {
+        match *self {
+            Filetype::Png | Filetype::Jpg | Filetype::Gif | Filetype::WebP => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the file contains structured data, which is validated before it is saved.
+    pub fn is_data(&self) -> bool {
+        match *self {
+            Filetype::Json | Filetype::Toml | Filetype::Yaml => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the file is audio or video. These are embedded within a page using `embed`.
+    pub fn is_media(&self) -> bool {
+        match *self {
+            Filetype::Mp3 | Filetype::Ogg | Filetype::Mp4 | Filetype::WebM => true,
+            _ => false,
+        }
+    }
+
+    /// Returns a native player for media files. `link` points to the raw file.
+    pub fn embed(&self, link: &str) -> String {
+        let link = handlebars::html_escape(link);
+        match *self {
+            Filetype::Mp3 | Filetype::Ogg => format!(
+                "<audio controls preload=\"metadata\" src=\"{}\"></audio>",
+                link
+            ),
+            Filetype::Mp4 | Filetype::WebM => format!(
+                "<video controls preload=\"metadata\" src=\"{}\"></video>",
+                link
+            ),
+            _ => panic!("Attempted to embed non-media filetype"),
+        }
+    }
+
+    /// Returns the `Content-Security-Policy` used when serving the file directly.
+    ///
+    /// SVG files can contain scripts. They are therefore not allowed to load or execute anything.
+    pub fn content_security_policy(&self) -> Option<&'static str> {
+        match *self {
+            Filetype::Svg => Some("default-src 'none'; style-src 'unsafe-inline'; sandbox"),
+            _ => None,
+        }
+    }
+
     pub fn content_type(&self) -> ContentType {
-        match self {
-            &Filetype::Raw => ContentType::Binary,
-            &Filetype::Png => ContentType::Png,
-            &Filetype::Jpg => ContentType::Jpg,
-            &Filetype::Markdown => ContentType::Markdown,
+        match *self {
+            Filetype::Raw => ContentType::Binary,
+            Filetype::Png => ContentType::Png,
+            Filetype::Jpg => ContentType::Jpg,
+            Filetype::Gif => ContentType::Gif,
+            Filetype::WebP => ContentType::WebP,
+            Filetype::Svg => ContentType::Svg,
+            Filetype::Pdf => ContentType::Pdf,
+            Filetype::Mp3 => ContentType::Mp3,
+            Filetype::Ogg => ContentType::Ogg,
+            Filetype::Mp4 => ContentType::Mp4,
+            Filetype::WebM => ContentType::WebM,
+            Filetype::Markdown => ContentType::Markdown,
+            Filetype::AsciiDoc => ContentType::Plain,
+            Filetype::ReStructuredText => ContentType::Plain,
+            Filetype::Org => ContentType::Plain,
+            Filetype::Csv => ContentType::Csv,
+            Filetype::Tsv => ContentType::Tsv,
+            Filetype::Json => ContentType::Json,
+            Filetype::Toml => ContentType::Plain,
+            Filetype::Yaml => ContentType::Plain,
+            Filetype::Code(_) => ContentType::Plain,
+            Filetype::Command(_) => ContentType::Binary,
         }
     }
 
-    pub fn parse(&self, data: &str) -> Result<String, ParsingError> {
-        match self {
-            &Filetype::Markdown => self.parse_markdown(data),
-            &Filetype::Raw => Ok(data.to_string()),
+    pub fn parse(&self, data: &str, config: &Config) -> Result<String, ParsingError> {
+        match *self {
+            Filetype::Markdown => {
+                let (front_matter, data) = split_front_matter(data);
+                let options = match front_matter {
+                    Some(front_matter) => markdown_options(front_matter, &config.markdown),
+                    None => Ok(config.markdown.clone()),
+                };
+                match options {
+                    Ok(options) => Ok(self.parse_markdown(data, &options, config)),
+                    // The page is still readable with the configured options.
+                    Err(err) => Ok(format!(
+                        "<p class=\"parse-error\">Invalid markdown options in the front matter: \
+                         {}</p>\n{}",
+                        handlebars::html_escape(&err.to_string()),
+                        self.parse_markdown(data, &config.markdown, config)
+                    )),
+                }
+            }
+            // Other markup languages are converted to markdown first.
+            Filetype::AsciiDoc => Ok(self.parse_markdown(
+                &markup::asciidoc_to_markdown(data),
+                &config.markdown,
+                config,
+            )),
+            Filetype::ReStructuredText => {
+                Ok(self.parse_markdown(&markup::rst_to_markdown(data), &config.markdown, config))
+            }
+            Filetype::Org => {
+                Ok(self.parse_markdown(&markup::org_to_markdown(data), &config.markdown, config))
+            }
+            Filetype::Csv => self.parse_table(data, b','),
+            Filetype::Tsv => self.parse_table(data, b'\t'),
+            Filetype::Json | Filetype::Toml | Filetype::Yaml => match self.parse_data(data) {
+                Ok(value) => {
+                    let mut html = String::from("<ul class=\"data-tree\">");
+                    data_tree(None, &value, &mut html);
+                    html += "</ul>";
+                    Ok(html)
+                }
+                // Invalid files can be committed outside of the wiki. They are shown as code
+                // below the error, so they can still be read and fixed.
+                Err(err) => {
+                    let extension = match *self {
+                        Filetype::Json => "json",
+                        Filetype::Toml => "toml",
+                        _ => "yaml",
+                    };
+                    let mut html = format!(
+                        "<p class=\"parse-error\">{}</p>\n",
+                        handlebars::html_escape(&err.to_string())
+                    );
+                    html += &self.parse_code(data, extension)?;
+                    Ok(html)
+                }
+            },
+            Filetype::Code(ref extension) => self.parse_code(data, extension),
+            Filetype::Raw => Ok(data.to_string()),
+            Filetype::Command(_) => panic!("Attempted to parse command filetype as text"),
             _ => panic!("Attempted to parse raw filetype"),
         }
     }
 
-    fn parse_markdown(&self, data: &str) -> Result<String, ParsingError> {
-        let mut options = comrak::ComrakOptions::default();
-        options.ext_strikethrough = true;
-        options.ext_table = true;
-        options.ext_tasklist = true;
-        Ok(comrak::markdown_to_html(data, &options))
+    /// Renders a file using its external command. The content does not have to be text.
+    ///
+    /// The output is sanitized like the output of the other markup renderers. `oid` has to be the
+    /// id of the blob containing `content`.
+    pub fn parse_command(
+        &self,
+        oid: Oid,
+        content: &[u8],
+        config: &Config,
+    ) -> Result<String, ParsingError> {
+        match *self {
+            Filetype::Command(ref command) => Ok(sanitize(
+                &CommandRenderer::new(&config.commands).render(oid, command, content)?,
+                &config.sanitize,
+                false,
+            )),
+            _ => panic!("Attempted to run command for non-command filetype"),
+        }
+    }
+
+    /// Returns the filetype of a renderer name used in `[filetypes]`.
+    ///
+    /// `code` selects the syntax using the extension of `path`; `code:<extension>` selects it
+    /// explicitly.
+    pub fn from_name(name: &str, path: &Path) -> Option<Filetype> {
+        match name {
+            "raw" => Some(Filetype::Raw),
+            "png" => Some(Filetype::Png),
+            "jpg" => Some(Filetype::Jpg),
+            "gif" => Some(Filetype::Gif),
+            "webp" => Some(Filetype::WebP),
+            "svg" => Some(Filetype::Svg),
+            "pdf" => Some(Filetype::Pdf),
+            "mp3" => Some(Filetype::Mp3),
+            "ogg" => Some(Filetype::Ogg),
+            "mp4" => Some(Filetype::Mp4),
+            "webm" => Some(Filetype::WebM),
+            "markdown" => Some(Filetype::Markdown),
+            "asciidoc" => Some(Filetype::AsciiDoc),
+            "rst" => Some(Filetype::ReStructuredText),
+            "org" => Some(Filetype::Org),
+            "csv" => Some(Filetype::Csv),
+            "tsv" => Some(Filetype::Tsv),
+            "json" => Some(Filetype::Json),
+            "toml" => Some(Filetype::Toml),
+            "yaml" => Some(Filetype::Yaml),
+            "code" => Some(Filetype::Code(
+                String::from_utf8_lossy(&path.extension().unwrap_or_default()).to_string(),
+            )),
+            name if name.starts_with("code:") => Some(Filetype::Code(name[5..].to_string())),
+            _ => None,
+        }
+    }
+
+    /// Returns the filetype of a path, taking the `[filetypes]` overrides into account.
+    pub fn for_path(path: &Path, config: &Config) -> Filetype {
+        let rule = filetype_rule(path, config);
+        if let Some(command) = rule.and_then(|rule| rule.command.as_ref()) {
+            return Filetype::Command(command.clone());
+        }
+        rule.and_then(|rule| rule.renderer.as_ref())
+            // Renderer names are checked when loading the config.
+            .and_then(|renderer| Filetype::from_name(renderer, path))
+            .unwrap_or_else(|| Filetype::from(path))
+    }
+
+    /// Determines the filetype of a file using its path and its content.
+    ///
+    /// Renderers and commands configured in `[filetypes]` are always used. Otherwise the magic
+    /// bytes of binary formats are used for files without a known extension or with the extension
+    /// of another binary format, so that these are still shown correctly. Files with the extension
+    /// of a text format are never sniffed: their content may start like a binary format. Text
+    /// formats cannot be detected reliably (except SVG).
+    pub fn detect(path: &Path, content: &[u8], config: &Config) -> Filetype {
+        if filetype_rule(path, config)
+            .is_some_and(|rule| rule.renderer.is_some() || rule.command.is_some())
+        {
+            return Filetype::for_path(path, config);
+        }
+        match Filetype::from(path) {
+            Filetype::Raw => Self::sniff(content).unwrap_or_else(|| {
+                if Self::is_svg(content) {
+                    Filetype::Svg
+                } else {
+                    Filetype::Raw
+                }
+            }),
+            filetype @ Filetype::Png
+            | filetype @ Filetype::Jpg
+            | filetype @ Filetype::Gif
+            | filetype @ Filetype::WebP
+            | filetype @ Filetype::Pdf
+            | filetype @ Filetype::Mp3
+            | filetype @ Filetype::Ogg
+            | filetype @ Filetype::Mp4
+            | filetype @ Filetype::WebM => Self::sniff(content).unwrap_or(filetype),
+            filetype => filetype,
+        }
+    }
+
+    /// Detects binary formats using their magic bytes.
+    fn sniff(content: &[u8]) -> Option<Filetype> {
+        // Byte order marks start text, although the one of UTF-16 looks like an MP3 frame.
+        if content.starts_with(b"\xef\xbb\xbf")
+            || content.starts_with(b"\xff\xfe")
+            || content.starts_with(b"\xfe\xff")
+        {
+            None
+        } else if content.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(Filetype::Png)
+        } else if content.starts_with(b"\xff\xd8\xff") {
+            Some(Filetype::Jpg)
+        } else if content.starts_with(b"GIF87a") || content.starts_with(b"GIF89a") {
+            Some(Filetype::Gif)
+        } else if content.starts_with(b"RIFF") && content.get(8..12) == Some(b"WEBP") {
+            Some(Filetype::WebP)
+        } else if content.starts_with(b"%PDF-") {
+            Some(Filetype::Pdf)
+        } else if content.starts_with(b"ID3") || Self::is_mp3_frame(content) {
+            Some(Filetype::Mp3)
+        } else if content.starts_with(b"OggS") {
+            Some(Filetype::Ogg)
+        } else if content.get(4..8) == Some(b"ftyp") {
+            // Other formats use the same container, e.g. HEIC and AVIF images or M4A audio.
+            match content.get(8..12) {
+                Some(b"isom") | Some(b"iso2") | Some(b"iso4") | Some(b"iso5") | Some(b"iso6")
+                | Some(b"mp41") | Some(b"mp42") | Some(b"avc1") | Some(b"M4V ") | Some(b"dash") => {
+                    Some(Filetype::Mp4)
+                }
+                _ => None,
+            }
+        } else if content.starts_with(b"\x1a\x45\xdf\xa3") {
+            Some(Filetype::WebM)
+        } else {
+            None
+        }
+    }
+
+    /// Whether `content` starts with the header of an MPEG audio layer III frame.
+    fn is_mp3_frame(content: &[u8]) -> bool {
+        match content {
+            [0xff, version_layer, rates, ..] => {
+                // Frame sync, a version other than the reserved one and layer III.
+                version_layer & 0xe0 == 0xe0
+                    && version_layer & 0x18 != 0x08
+                    && version_layer & 0x06 == 0x02
+                    // A valid bitrate and sampling rate.
+                    && rates & 0xf0 != 0xf0
+                    && rates & 0x0c != 0x0c
+            }
+            _ => false,
+        }
+    }
+
+    fn is_svg(content: &[u8]) -> bool {
+        // Only the start of the file is checked. It may start with an XML declaration, a doctype
+        // or comments.
+        let start = &content[..content.len().min(1024)];
+        let start = String::from_utf8_lossy(start);
+        let start = start.trim_start();
+        (start.starts_with("<?xml") || start.starts_with("<!") || start.starts_with("<svg"))
+            && start.contains("<svg")
+    }
+
+    /// Checks whether the content of a file can be saved.
+    ///
+    /// Structured data files have to be valid. All other files are always valid.
+    pub fn validate(&self, data: &[u8]) -> Result<(), ParsingError> {
+        match *self {
+            Filetype::Json | Filetype::Toml | Filetype::Yaml => {
+                let data = std::str::from_utf8(data).map_err(|_| ParsingError::InvalidUtf8)?;
+                self.parse_data(data).map(|_| ())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Parses structured data. All formats are converted to JSON values.
+    fn parse_data(&self, data: &str) -> Result<serde_json::Value, ParsingError> {
+        match *self {
+            Filetype::Json => Ok(serde_json::from_str(data)?),
+            Filetype::Toml => Ok(toml_to_json(toml::from_str(data)?)),
+            Filetype::Yaml => Ok(yaml_to_json(serde_yaml::from_str(data)?)),
+            _ => panic!("Attempted to parse unstructured filetype as data"),
+        }
+    }
+
+    /// Renders markdown and sanitizes the result.
+    fn parse_markdown(&self, data: &str, options: &MarkdownConfig, config: &Config) -> String {
+        let comrak_options = comrak::ComrakOptions {
+            unsafe_: config.sanitize.raw_html != RawHtml::Omit,
+            hardbreaks: options.hardbreaks,
+            smart: options.smart,
+            github_pre_lang: options.github_pre_lang,
+            default_info_string: options.default_info_string.clone(),
+            ext_strikethrough: options.strikethrough,
+            ext_tagfilter: options.tagfilter,
+            ext_table: options.table,
+            ext_autolink: options.autolink,
+            ext_tasklist: options.tasklist,
+            ext_superscript: options.superscript,
+            ext_header_ids: options.header_ids.clone(),
+            ext_footnotes: options.footnotes,
+            ext_description_lists: options.description_lists,
+            ..comrak::ComrakOptions::default()
+        };
+        let arena = comrak::Arena::new();
+        let root = comrak::parse_document(&arena, data, &comrak_options);
+        sections::strip_marks(root);
+        tasks::mark_checkboxes(root);
+        let mut html = vec![];
+        // Writing to a vector does not fail and comrak writes valid UTF-8.
+        comrak::format_html(root, &comrak_options, &mut html).unwrap();
+        let html = sanitize(
+            &String::from_utf8(html).unwrap(),
+            &config.sanitize,
+            // Heading anchors and footnotes link to ids.
+            options.header_ids.is_some() || options.footnotes,
+        );
+        tasks::link_checkboxes(&html)
+    }
+
+    /// Renders delimiter separated values as a table.
+    ///
+    /// The first row is used as header if it looks like one (see `is_header`). Otherwise the
+    /// columns are numbered. Sorting and filtering is done in `get.html.hbs`.
+    fn parse_table(&self, data: &str, delimiter: u8) -> Result<String, ParsingError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .delimiter(delimiter)
+            .from_reader(data.as_bytes());
+        let mut rows = reader
+            .records()
+            .map(|record| Ok(record?.iter().map(str::to_string).collect::<Vec<_>>()))
+            .collect::<Result<Vec<_>, ParsingError>>()?;
+
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+        let header = if rows.first().map(|row| is_header(row)).unwrap_or(false) {
+            rows.remove(0)
+        } else {
+            (1..=columns).map(|i| i.to_string()).collect()
+        };
+
+        let mut html = String::from(
+            "<input class=\"table-filter\" type=\"search\" placeholder=\"Filter rows\">\n\
+             <table class=\"data-table\">\n<thead><tr>",
+        );
+        for i in 0..columns {
+            html += &format!(
+                "<th>{}</th>",
+                handlebars::html_escape(header.get(i).map(String::as_str).unwrap_or(""))
+            );
+        }
+        html += "</tr></thead>\n<tbody>\n";
+        for row in &rows {
+            html += "<tr>";
+            for i in 0..columns {
+                html += &format!(
+                    "<td>{}</td>",
+                    handlebars::html_escape(row.get(i).map(String::as_str).unwrap_or(""))
+                );
+            }
+            html += "</tr>\n";
+        }
+        html += "</tbody>\n</table>";
+
+        Ok(html)
+    }
+
+    /// Highlights source code and renders it as a table with one row per line.
+    ///
+    /// Every row has the id `L<number>` so single lines can be linked. Ranges (`#L10-L20`) are
+    /// handled in `get.html.hbs`.
+    fn parse_code(&self, data: &str, extension: &str) -> Result<String, ParsingError> {
+        let syntax = SYNTAX_SET
+            .find_syntax_by_extension(extension)
+            .unwrap_or_else(|| SYNTAX_SET.find_syntax_plain_text());
+        let mut highlighter = HighlightLines::new(syntax, &THEME);
+
+        let mut html = String::from("<table class=\"code\">\n");
+        for (i, line) in LinesWithEndings::from(data).enumerate() {
+            let ranges = highlighter
+                .highlight_line(line, &SYNTAX_SET)?
+                .into_iter()
+                // The line break is implied by the table row.
+                .map(|(style, text)| (style, text.trim_end_matches(&['\r', '\n'][..])))
+                .collect::<Vec<_>>();
+            let number = i + 1;
+            html += &format!(
+                "<tr id=\"L{0}\"><td class=\"line-number\"><a href=\"#L{0}\">{0}</a></td>\
+                 <td class=\"line\">{1}</td></tr>\n",
+                number,
+                styled_line_to_highlighted_html(&ranges[..], IncludeBackground::No)?
+            );
+        }
+        html += "</table>";
+
+        Ok(html)
+    }
+}
+
+/// Renders a value as list item of a collapsible tree. Objects and arrays are shown as `<details>`
+/// containing their children.
+fn data_tree(key: Option<&str>, value: &serde_json::Value, html: &mut String) {
+    use serde_json::Value;
+
+    let key = key
+        .map(|key| {
+            format!(
+                "<span class=\"key\">{}</span>: ",
+                handlebars::html_escape(key)
+            )
+        })
+        .unwrap_or_default();
+    let (children, summary) = match value {
+        Value::Object(map) => (
+            map.iter()
+                .map(|(key, value)| (key.clone(), value))
+                .collect::<Vec<_>>(),
+            format!("{{}} {} entries", map.len()),
+        ),
+        Value::Array(array) => (
+            array
+                .iter()
+                .enumerate()
+                .map(|(i, value)| (i.to_string(), value))
+                .collect(),
+            format!("[] {} items", array.len()),
+        ),
+        Value::String(string) => {
+            *html += &format!(
+                "<li>{}<span class=\"string\">\"{}\"</span></li>",
+                key,
+                handlebars::html_escape(string)
+            );
+            return;
+        }
+        scalar => {
+            *html += &format!("<li>{}<span class=\"scalar\">{}</span></li>", key, scalar);
+            return;
+        }
+    };
+
+    *html += &format!(
+        "<li><details open><summary>{}<span class=\"summary\">{}</span></summary><ul>",
+        key, summary
+    );
+    for (key, value) in children {
+        data_tree(Some(&key), value, html);
+    }
+    *html += "</ul></details></li>";
+}
+
+fn toml_to_json(value: toml::Value) -> serde_json::Value {
+    use serde_json::Value;
+
+    match value {
+        toml::Value::String(string) => Value::String(string),
+        toml::Value::Integer(integer) => Value::from(integer),
+        toml::Value::Float(float) => Value::from(float),
+        toml::Value::Boolean(boolean) => Value::Bool(boolean),
+        toml::Value::Datetime(datetime) => Value::String(datetime.to_string()),
+        toml::Value::Array(array) => Value::Array(array.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(table) => Value::Object(
+            table
+                .into_iter()
+                .map(|(key, value)| (key, toml_to_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+fn yaml_to_json(value: serde_yaml::Value) -> serde_json::Value {
+    use serde_json::Value;
+
+    match value {
+        serde_yaml::Value::Null => Value::Null,
+        serde_yaml::Value::Bool(boolean) => Value::Bool(boolean),
+        serde_yaml::Value::Number(number) => {
+            if let Some(integer) = number.as_i64() {
+                Value::from(integer)
+            } else if let Some(integer) = number.as_u64() {
+                Value::from(integer)
+            } else {
+                // NaN and infinity are converted to null.
+                Value::from(number.as_f64().unwrap_or(f64::NAN))
+            }
+        }
+        serde_yaml::Value::String(string) => Value::String(string),
+        serde_yaml::Value::Sequence(sequence) => {
+            Value::Array(sequence.into_iter().map(yaml_to_json).collect())
+        }
+        serde_yaml::Value::Mapping(mapping) => Value::Object(
+            mapping
+                .into_iter()
+                .map(|(key, value)| {
+                    // JSON only allows string keys.
+                    let key = match yaml_to_json(key) {
+                        Value::String(string) => string,
+                        other => other.to_string(),
+                    };
+                    (key, yaml_to_json(value))
+                })
+                .collect(),
+        ),
+        serde_yaml::Value::Tagged(tagged) => yaml_to_json(tagged.value),
+    }
+}
+
+/// Guesses whether the first row of a table is a header.
+///
+/// This is the case if all of its cells are filled, distinct and not numbers.
+fn is_header(row: &[String]) -> bool {
+    row.iter().enumerate().all(|(i, cell)| {
+        !cell.trim().is_empty() && cell.trim().parse::<f64>().is_err() && !row[..i].contains(cell)
+    })
+}
+
+/// Removes everything not on the allow-list from rendered HTML.
+///
+/// This prevents scripts, event handlers and `javascript:` links in pages. With `RawHtml::Trust`
+/// the HTML is returned unchanged. `allow_ids` keeps the ids of links and list items, which
+/// heading anchors and footnotes rely on.
+fn sanitize(html: &str, config: &SanitizeConfig, allow_ids: bool) -> String {
+    if config.raw_html == RawHtml::Trust {
+        return html.to_string();
+    }
+
+    let mut builder = ammonia::Builder::default();
+    // Task lists are rendered as disabled checkboxes.
+    builder
+        .add_tags(&["input"])
+        .add_tag_attributes("input", &["type", "checked", "disabled"])
+        .add_tags(config.tags.iter().map(String::as_str))
+        .add_generic_attributes(config.attributes.iter().map(String::as_str));
+    if allow_ids {
+        builder
+            .add_tags(&["section"])
+            .add_tag_attributes("a", &["id", "aria-hidden"])
+            .add_tag_attributes("li", &["id"]);
+    }
+    if let Some(ref url_schemes) = config.url_schemes {
+        builder.url_schemes(url_schemes.iter().map(String::as_str).collect());
+    }
+    builder.clean(html).to_string()
+}
+
+/// Splits a markdown document into its YAML front matter and the remaining document.
+///
+/// Front matter is enclosed by `---` lines at the very start of the document. The closing line
+/// can also be `...`. It has to be a YAML mapping (or empty), otherwise the lines are part of the
+/// document: a page can also start with a thematic break.
+pub fn split_front_matter(data: &str) -> (Option<&str>, &str) {
+    let rest = match data
+        .strip_prefix("---\n")
+        .or_else(|| data.strip_prefix("---\r\n"))
+    {
+        Some(rest) => rest,
+        None => return (None, data),
+    };
+    let mut start = 0;
+    for line in rest.split_inclusive('\n') {
+        let trimmed = line.trim_end();
+        if trimmed == "---" || trimmed == "..." {
+            let front_matter = &rest[..start];
+            return match serde_yaml::from_str(front_matter) {
+                Ok(serde_yaml::Value::Mapping(_)) | Ok(serde_yaml::Value::Null) => {
+                    (Some(front_matter), &rest[start + line.len()..])
+                }
+                _ => (None, data),
+            };
+        }
+        start += line.len();
+    }
+    (None, data)
+}
+
+/// Applies the `markdown` key of a page's front matter to the configured options.
+///
+/// The front matter has to be taken from `split_front_matter`. Unknown options and values of the
+/// wrong type are errors.
+fn markdown_options(
+    front_matter: &str,
+    defaults: &MarkdownConfig,
+) -> Result<MarkdownConfig, serde_json::Error> {
+    // The front matter was already parsed when it was split off.
+    let front_matter = yaml_to_json(serde_yaml::from_str(front_matter).unwrap());
+    let overrides = match front_matter.get("markdown") {
+        Some(serde_json::Value::Object(overrides)) => overrides,
+        _ => return Ok(defaults.clone()),
+    };
+    let mut options = serde_json::to_value(defaults)?;
+    // The defaults are always serialized as an object.
+    let object = options.as_object_mut().unwrap();
+    for (key, value) in overrides {
+        object.insert(key.clone(), value.clone());
+    }
+    serde_json::from_value(options)
+}
+
+/// Returns the `[filetypes]` rule matching a path.
+///
+/// Patterns without wildcards take precedence. Otherwise the longest matching pattern is used.
+pub fn filetype_rule<'a>(path: &Path, config: &'a Config) -> Option<&'a FiletypeRule> {
+    let filename = path.filename()?;
+    config
+        .filetypes
+        .iter()
+        .filter(|(pattern, _)| {
+            if pattern.starts_with('.') {
+                path.extension()
+                    .is_some_and(|extension| extension.as_slice() == &pattern.as_bytes()[1..])
+            } else if pattern.contains('/') {
+                glob_match(pattern.trim_start_matches('/').as_bytes(), path.bytes())
+            } else {
+                glob_match(pattern.as_bytes(), filename.bytes())
+            }
+        })
+        .max_by_key(|(pattern, _)| (!pattern.contains(&['*', '?'][..]), pattern.len()))
+        .map(|(_, rule)| rule)
+}
+
+/// Matches a glob pattern. `*` matches anything but `/`, `**` matches anything and `?` matches
+/// a single byte.
+pub fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some(b'*') if pattern.get(1) == Some(&b'*') => {
+            (0..=text.len()).any(|i| glob_match(&pattern[2..], &text[i..]))
+        }
+        Some(b'*') => (0..=text.len())
+            .take_while(|i| *i == 0 || text[i - 1] != b'/')
+            .any(|i| glob_match(&pattern[1..], &text[i..])),
+        Some(b'?') => !text.is_empty() && glob_match(&pattern[1..], &text[1..]),
+        Some(c) => text.first() == Some(c) && glob_match(&pattern[1..], &text[1..]),
     }
 }
+
 impl From<&Path> for Filetype {
     fn from(path: &Path) -> Filetype {
         if let Some(extension) = path.extension() {
             match std::str::from_utf8(&extension) {
                 Ok("md") => Filetype::Markdown,
+                Ok("adoc") | Ok("asciidoc") => Filetype::AsciiDoc,
+                Ok("rst") => Filetype::ReStructuredText,
+                Ok("org") => Filetype::Org,
+                Ok("csv") => Filetype::Csv,
+                Ok("tsv") | Ok("tab") => Filetype::Tsv,
+                Ok("json") => Filetype::Json,
+                Ok("toml") => Filetype::Toml,
+                Ok("yaml") | Ok("yml") => Filetype::Yaml,
                 Ok("png") => Filetype::Png,
                 Ok("jpg") | Ok("jpeg") => Filetype::Jpg,
+                Ok("gif") => Filetype::Gif,
+                Ok("webp") => Filetype::WebP,
+                Ok("svg") => Filetype::Svg,
+                Ok("pdf") => Filetype::Pdf,
+                Ok("mp3") => Filetype::Mp3,
+                Ok("ogg") | Ok("oga") | Ok("opus") => Filetype::Ogg,
+                Ok("mp4") | Ok("m4v") => Filetype::Mp4,
+                Ok("webm") => Filetype::WebM,
+                Ok(extension) if CODE_EXTENSIONS.contains(&extension) => {
+                    Filetype::Code(extension.to_string())
+                }
                 Ok(_) | Err(_) => Filetype::Raw,
             }
         } else {
@@ -74,10 +814,341 @@ impl From<&Path> for Filetype {
 }
 
 #[derive(Debug)]
-pub enum ParsingError {}
+pub enum ParsingError {
+    Highlighting(syntect::Error),
+    Csv(csv::Error),
+    Json(serde_json::Error),
+    Toml(toml::de::Error),
+    Yaml(serde_yaml::Error),
+    Command(CommandError),
+    InvalidUtf8,
+}
+impl ParsingError {
+    /// Returns the line and column (starting at 1) at which the error occurred if it is known.
+    pub fn location(&self) -> Option<(usize, usize)> {
+        match *self {
+            ParsingError::Json(ref err) => Some((err.line(), err.column())),
+            ParsingError::Toml(ref err) => err.line_col().map(|(line, col)| (line + 1, col + 1)),
+            ParsingError::Yaml(ref err) => err
+                .location()
+                .map(|location| (location.line(), location.column())),
+            _ => None,
+        }
+    }
+}
 impl std::error::Error for ParsingError {}
 impl fmt::Display for ParsingError {
-    fn fmt(&self, _f: &mut fmt::Formatter) -> fmt::Result {
-        unreachable!()
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParsingError::Highlighting(ref err) => write!(f, "Highlighting error: {}", err),
+            ParsingError::Csv(ref err) => write!(f, "CSV error: {}", err),
+            ParsingError::Json(ref err) => write!(f, "JSON error: {}", err),
+            ParsingError::Toml(ref err) => write!(f, "TOML error: {}", err),
+            ParsingError::Yaml(ref err) => write!(f, "YAML error: {}", err),
+            ParsingError::Command(ref err) => write!(f, "Command error: {}", err),
+            ParsingError::InvalidUtf8 => write!(f, "Invalid UTF-8"),
+        }
+    }
+}
+impl From<syntect::Error> for ParsingError {
+    fn from(err: syntect::Error) -> Self {
+        ParsingError::Highlighting(err)
+    }
+}
+impl From<csv::Error> for ParsingError {
+    fn from(err: csv::Error) -> Self {
+        ParsingError::Csv(err)
+    }
+}
+impl From<serde_json::Error> for ParsingError {
+    fn from(err: serde_json::Error) -> Self {
+        ParsingError::Json(err)
+    }
+}
+impl From<toml::de::Error> for ParsingError {
+    fn from(err: toml::de::Error) -> Self {
+        ParsingError::Toml(err)
+    }
+}
+impl From<serde_yaml::Error> for ParsingError {
+    fn from(err: serde_yaml::Error) -> Self {
+        ParsingError::Yaml(err)
+    }
+}
+impl From<CommandError> for ParsingError {
+    fn from(err: CommandError) -> Self {
+        ParsingError::Command(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{RawHtml, SanitizeConfig};
+    use crate::filetype::{filetype_rule, sanitize, split_front_matter, ParsingError};
+    use crate::{Config, Filetype, Path};
+
+    fn config() -> Config {
+        toml::from_str("bind = \"\"\nindex = \"\"\nmax_upload_size = 0\nrepo = \"\"").unwrap()
+    }
+
+    #[test]
+    fn code_filetype() {
+        match Filetype::from(&Path::from("src/main.rs".to_string())) {
+            Filetype::Code(ref extension) => assert_eq!(extension, "rs"),
+            _ => panic!(),
+        }
+        match Filetype::from(&Path::from("notes.txt".to_string())) {
+            Filetype::Raw => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn filetype_rules() {
+        let config: Config = toml::from_str(concat!(
+            "bind = \"\"\nindex = \"\"\nmax_upload_size = 0\nrepo = \"\"\n",
+            "[filetypes]\n",
+            "\".markdown\" = { renderer = \"markdown\" }\n",
+            "\"README\" = { renderer = \"markdown\" }\n",
+            "\"*.svg\" = { inline = false }\n",
+            "\"docs/**.txt\" = { renderer = \"code:txt\" }\n",
+            "\"*.txt\" = { renderer = \"markdown\" }\n",
+        ))
+        .unwrap();
+        let filetype = |path: &str| Filetype::for_path(&Path::from(path.to_string()), &config);
+
+        match filetype("notes/a.markdown") {
+            Filetype::Markdown => {}
+            _ => panic!(),
+        }
+        match filetype("README") {
+            Filetype::Markdown => {}
+            _ => panic!(),
+        }
+        match filetype("a/README.txt") {
+            Filetype::Markdown => {}
+            _ => panic!(),
+        }
+        match filetype("docs/a/b.txt") {
+            Filetype::Code(ref extension) => assert_eq!(extension, "txt"),
+            _ => panic!(),
+        }
+        match filetype("logo.svg") {
+            Filetype::Svg => {}
+            _ => panic!(),
+        }
+        let rule = filetype_rule(&Path::from("logo.svg".to_string()), &config).unwrap();
+        assert_eq!(rule.inline, Some(false));
+        assert!(filetype_rule(&Path::from("a/logo.svg/x".to_string()), &config).is_none());
+
+        // Configured renderers take precedence over sniffing.
+        match Filetype::detect(&Path::from("a.txt".to_string()), b"%PDF-1.4", &config) {
+            Filetype::Markdown => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn code_line_anchors() {
+        let html = Filetype::Code("py".to_string())
+            .parse("a = 1\nb = 2\n", &config())
+            .unwrap();
+        assert!(html.contains("id=\"L1\""));
+        assert!(html.contains("id=\"L2\""));
+        assert!(!html.contains("id=\"L3\""));
+    }
+
+    #[test]
+    fn code_is_escaped() {
+        let html = Filetype::Code("sh".to_string())
+            .parse("echo '<script>'", &config())
+            .unwrap();
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("&lt;script&gt;"));
+    }
+
+    #[test]
+    fn table_header_detection() {
+        let html = Filetype::Csv
+            .parse("name,phone\nAlice,123\n\"Bob, Jr.\",<456>\n", &config())
+            .unwrap();
+        assert!(html.contains("<th>name</th><th>phone</th>"));
+        assert!(html.contains("<td>Bob, Jr.</td><td>&lt;456&gt;</td>"));
+
+        let html = Filetype::Tsv.parse("1\t2\n3\t4\n", &config()).unwrap();
+        assert!(html.contains("<th>1</th><th>2</th>"));
+        assert!(html.contains("<td>1</td><td>2</td>"));
+    }
+
+    #[test]
+    fn data_tree() {
+        let html = Filetype::Toml
+            .parse(
+                "[server]\nhost = \"<local>\"\nports = [80, 443]\n",
+                &config(),
+            )
+            .unwrap();
+        assert!(html.contains("<span class=\"key\">server</span>"));
+        assert!(html.contains("<span class=\"string\">\"&lt;local&gt;\"</span>"));
+        assert!(html.contains("<span class=\"scalar\">443</span>"));
+
+        let json = Filetype::Json.parse("{\"a\": [1, 2]}", &config()).unwrap();
+        let yaml = Filetype::Yaml
+            .parse("a:\n  - 1\n  - 2\n", &config())
+            .unwrap();
+        assert_eq!(json, yaml);
+
+        let invalid = Filetype::Json.parse("{\"a\": <b>}", &config()).unwrap();
+        assert!(invalid.starts_with("<p class=\"parse-error\">JSON error: "));
+        assert!(invalid.contains("<table class=\"code\">"));
+        assert!(invalid.contains("&lt;b&gt;"));
+    }
+
+    #[test]
+    fn data_validation() {
+        assert!(Filetype::Json.validate(b"{\"a\": 1}").is_ok());
+        assert!(Filetype::Markdown.validate(b"{").is_ok());
+
+        let err = Filetype::Json.validate(b"{\n  \"a\": }").unwrap_err();
+        assert_eq!(err.location(), Some((2, 8)));
+        let err = Filetype::Toml.validate(b"a = 1\nb = \n").unwrap_err();
+        assert_eq!(err.location().map(|(line, _)| line), Some(2));
+        let err = Filetype::Yaml.validate(b"a: 1\nb: [\n").unwrap_err();
+        assert!(err.location().is_some());
+        match Filetype::Yaml.validate(&[0xff]) {
+            Err(ParsingError::InvalidUtf8) => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn sniffing() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR";
+        let path = Path::from("photo.jpg".to_string());
+        match Filetype::detect(&path, png, &config()) {
+            Filetype::Png => {}
+            _ => panic!(),
+        }
+        let path = Path::from("recording".to_string());
+        match Filetype::detect(&path, b"OggS\0\x02", &config()) {
+            Filetype::Ogg => {}
+            _ => panic!(),
+        }
+        let path = Path::from("drawing".to_string());
+        match Filetype::detect(&path, b"<?xml version=\"1.0\"?>\n<svg></svg>", &config()) {
+            Filetype::Svg => {}
+            _ => panic!(),
+        }
+        let path = Path::from("index.md".to_string());
+        match Filetype::detect(&path, b"# <svg>", &config()) {
+            Filetype::Markdown => {}
+            _ => panic!(),
+        }
+        match Filetype::detect(&path, b"%PDF-1.4 is a version\n", &config()) {
+            Filetype::Markdown => {}
+            _ => panic!(),
+        }
+        let path = Path::from("notes".to_string());
+        match Filetype::detect(&path, b"\xff\xfe#\0 \0", &config()) {
+            Filetype::Raw => {}
+            _ => panic!(),
+        }
+        match Filetype::detect(&path, b"\xff\xfb\x90\x64", &config()) {
+            Filetype::Mp3 => {}
+            _ => panic!(),
+        }
+        match Filetype::detect(&path, b"\0\0\0\x18ftypheic", &config()) {
+            Filetype::Raw => {}
+            _ => panic!(),
+        }
+        match Filetype::detect(&path, b"\0\0\0\x18ftypisom", &config()) {
+            Filetype::Mp4 => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn markdown_is_sanitized() {
+        let html = Filetype::Markdown
+            .parse(
+                "<script>alert(1)</script>\n\n[link](javascript:alert(1)) <kbd>Ctrl</kbd>",
+                &config(),
+            )
+            .unwrap();
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("javascript:"));
+        assert!(html.contains("<kbd>Ctrl</kbd>"));
+    }
+
+    #[test]
+    fn markdown_extensions() {
+        let mut config = config();
+        config.markdown.footnotes = true;
+        config.markdown.autolink = true;
+        config.markdown.header_ids = Some("h-".to_string());
+
+        let html = Filetype::Markdown
+            .parse(
+                "# Title\n\nSee https://example.com[^1]\n\n[^1]: Note",
+                &config,
+            )
+            .unwrap();
+        assert!(html.contains("id=\"h-title\""));
+        assert!(html.contains("<a href=\"https://example.com\""));
+        assert!(html.contains("id=\"fn1\""));
+
+        // Front matter is not shown and can override the options.
+        let html = Filetype::Markdown
+            .parse(
+                "---\ntitle: x\nmarkdown:\n  autolink: false\n---\nhttps://example.com",
+                &config,
+            )
+            .unwrap();
+        assert_eq!(html, "<p>https://example.com</p>\n");
+        let html = Filetype::Markdown
+            .parse("---\nmarkdown:\n  unknown: true\n---\nText", &config)
+            .unwrap();
+        assert!(html.starts_with("<p class=\"parse-error\">Invalid markdown options"));
+        assert!(html.ends_with("<p>Text</p>\n"));
+    }
+
+    #[test]
+    fn front_matter() {
+        assert_eq!(
+            split_front_matter("---\ntitle: x\n...\nText"),
+            (Some("title: x\n"), "Text")
+        );
+        // Thematic breaks and invalid YAML are content.
+        for source in &[
+            "---\nText\n\n---\nMore",
+            "---\ntitle: [\n---\nText",
+            "---\n- a\n---\n",
+        ] {
+            assert_eq!(split_front_matter(source), (None, *source));
+        }
+        let html = Filetype::Markdown
+            .parse("---\nA *b*\n\n---\nC", &config())
+            .unwrap();
+        assert_eq!(html, "<hr>\n<p>A <em>b</em></p>\n<hr>\n<p>C</p>\n");
+    }
+
+    #[test]
+    fn sanitize_allow_list() {
+        let mut config = SanitizeConfig::default();
+        assert_eq!(sanitize("<marquee>a</marquee>", &config, false), "a");
+        config.tags.push("marquee".to_string());
+        assert_eq!(
+            sanitize("<marquee>a</marquee>", &config, false),
+            "<marquee>a</marquee>"
+        );
+        assert_eq!(
+            sanitize("<input type=\"checkbox\" onclick=\"x()\">", &config, false),
+            "<input type=\"checkbox\">"
+        );
+        config.raw_html = RawHtml::Trust;
+        assert_eq!(
+            sanitize("<script>x()</script>", &config, false),
+            "<script>x()</script>"
+        );
     }
 }
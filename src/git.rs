@@ -1,7 +1,11 @@
+use std::fs::{self, File, OpenOptions};
 use std::io;
 use std::io::Write;
-use std::path::Path as StdPath;
+use std::path::{Path as StdPath, PathBuf as StdPathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
+use crate::cache::Caches;
 use crate::Path;
 
 use git2::{
@@ -9,8 +13,14 @@ use git2::{
     TreeBuilder,
 };
 
+/// Number of `BlobStream`s created so far. It makes the names of their files unique.
+static BLOB_STREAMS: AtomicUsize = AtomicUsize::new(0);
+
 pub struct GitRepository {
     repo: Repository,
+    caches: Option<Arc<Caches>>,
+    /// Commit items are read from instead of the head.
+    revision: Option<Oid>,
 }
 impl GitRepository {
     pub fn new<T: AsRef<StdPath>>(dir: T) -> Result<GitRepository, GitError> {
@@ -22,9 +32,47 @@ impl GitRepository {
                     .mkdir(true)
                     .mkpath(false),
             )?,
+            caches: None,
+            revision: None,
         })
     }
 
+    /// Opens the repository like `new` but caches tree lookups.
+    pub fn with_caches<T: AsRef<StdPath>>(
+        dir: T,
+        caches: Arc<Caches>,
+    ) -> Result<GitRepository, GitError> {
+        let mut repo = GitRepository::new(dir)?;
+        repo.caches = Some(caches);
+        Ok(repo)
+    }
+
+    /// Reads items from a revision (e.g. a commit id, branch or tag) instead of the head.
+    ///
+    /// Edits are still applied to the head.
+    pub fn set_revision(&mut self, revision: &str) -> Result<(), GitError> {
+        self.revision = Some(self.repo.revparse_single(revision)?.peel_to_commit()?.id());
+        Ok(())
+    }
+
+    /// Returns the id of the current head commit.
+    pub fn head_id(&self) -> Result<Oid, GitError> {
+        Ok(self.head()?.id())
+    }
+
+    /// Returns the id of the commit items are read from.
+    pub fn commit_id(&self) -> Result<Oid, GitError> {
+        Ok(self.commit()?.id())
+    }
+
+    /// Returns the commit items are read from.
+    fn commit<'repo>(&'repo self) -> Result<Commit<'repo>, GitError> {
+        match self.revision {
+            Some(revision) => Ok(self.repo.find_commit(revision)?),
+            None => self.head(),
+        }
+    }
+
     fn head<'repo>(&'repo self) -> Result<Commit<'repo>, GitError> {
         let head_ref = match self.repo.head() {
             Ok(head_ref) => head_ref,
@@ -54,6 +102,102 @@ impl GitRepository {
         Ok(self.repo.find_commit(head_oid).unwrap())
     }
 
+    /// Writes `content` to a new blob without adding it to any tree.
+    pub fn write_blob(&self, content: &[u8]) -> Result<Oid, GitError> {
+        let mut blob_writer = self.repo.blob_writer(None)?;
+        blob_writer.write_all(content)?;
+        Ok(blob_writer.commit()?)
+    }
+
+    /// Returns the content of a blob, for example an earlier revision of a file.
+    ///
+    /// Returns `GitError::NotFound` if the id is invalid or no blob has this id.
+    pub fn read_blob(&self, id: &str) -> Result<Vec<u8>, GitError> {
+        match Oid::from_str(id).and_then(|oid| self.repo.find_blob(oid)) {
+            Ok(blob) => Ok(blob.content().to_vec()),
+            Err(_) => Err(GitError::NotFound),
+        }
+    }
+
+    /// Adds several blobs (see `write_blob`) in a single commit. Files whose content did not
+    /// change are skipped. Returns the paths of the changed files, or `GitError::NoChange` if none
+    /// changed.
+    pub fn add_files(&self, files: &[(Path, Oid)], message: &str) -> Result<Vec<Path>, GitError> {
+        let signature = Signature::now("smeagol", "smeagol@smeagol")?;
+        self.add_files_by(files, message, &signature)
+    }
+
+    /// Like `add_files` but records `author` (including the time) as the author of the commit.
+    pub fn add_files_by(
+        &self,
+        files: &[(Path, Oid)],
+        message: &str,
+        author: &Signature,
+    ) -> Result<Vec<Path>, GitError> {
+        let head = self.head()?;
+        let head_tree = head.tree()?;
+        let mut tree_builder = self.repo.treebuilder(Some(&head_tree))?;
+
+        let mut changed = vec![];
+        for (path, oid) in files {
+            if path.is_empty() {
+                return Err(GitError::IsDir);
+            }
+            // Subtrees are written to the root tree builder after every file, so later files see
+            // the directories created by earlier ones.
+            match self
+                .item(path.clone())?
+                .add_to_tree(&mut tree_builder, path.clone(), *oid)
+            {
+                Ok(()) => changed.push(path.clone()),
+                Err(GitError::NoChange) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        if changed.is_empty() {
+            return Err(GitError::NoChange);
+        }
+
+        self.commit_tree_by(tree_builder, &head, message, author)?;
+        Ok(changed)
+    }
+
+    /// Writes the tree and commits it on top of `parent`.
+    fn commit_tree(
+        &self,
+        tree_builder: TreeBuilder,
+        parent: &Commit,
+        message: &str,
+    ) -> Result<(), GitError> {
+        let signature = Signature::now("smeagol", "smeagol@smeagol")?;
+        self.commit_tree_by(tree_builder, parent, message, &signature)
+    }
+
+    /// Like `commit_tree` but with a different author. Smeagol is always the committer.
+    fn commit_tree_by(
+        &self,
+        tree_builder: TreeBuilder,
+        parent: &Commit,
+        message: &str,
+        author: &Signature,
+    ) -> Result<(), GitError> {
+        let tree_oid = tree_builder.write()?;
+        let new_tree = self.repo.find_tree(tree_oid)?;
+
+        let signature = Signature::now("smeagol", "smeagol@smeagol")?;
+
+        self.repo.commit(
+            Some("HEAD"),
+            author,
+            &signature,
+            message,
+            &new_tree,
+            &[parent],
+        )?;
+
+        Ok(())
+    }
+
     pub fn item<'repo>(&'repo self, path: Path) -> Result<GitItem<'repo>, GitError> {
         Ok(GitItem {
             repo: self,
@@ -83,12 +227,34 @@ impl<'repo> GitItem<'repo> {
     }
 
     fn object(&self) -> Result<Object<'repo>, GitError> {
-        // object could be cached but I am not sure how cache invalidation could be implemented.
-
         if self.path.is_empty() {
-            return Ok(self.repo.head()?.tree()?.into_object());
+            return Ok(self.repo.commit()?.tree()?.into_object());
         }
 
+        // Lookups are cached by root tree, which changes with every commit.
+        if let Some(ref caches) = self.repo.caches {
+            let root = self.repo.commit()?.tree_id();
+            return match caches.tree_lookup(root, &self.path) {
+                Some(Some(oid)) => Ok(self.repo.repo.find_object(oid, None)?),
+                Some(None) => Err(GitError::NotFound),
+                None => match self.lookup_object() {
+                    Ok(object) => {
+                        caches.insert_tree_lookup(root, self.path.clone(), Some(object.id()));
+                        Ok(object)
+                    }
+                    Err(GitError::NotFound) => {
+                        caches.insert_tree_lookup(root, self.path.clone(), None);
+                        Err(GitError::NotFound)
+                    }
+                    Err(err) => Err(err),
+                },
+            };
+        }
+        self.lookup_object()
+    }
+
+    /// Looks up the object by walking the trees from the root.
+    fn lookup_object(&self) -> Result<Object<'repo>, GitError> {
         let parent_object = self.parent()?.object()?;
         let tree = if let Ok(tree) = parent_object.into_tree() {
             tree
@@ -109,6 +275,161 @@ impl<'repo> GitItem<'repo> {
         }
     }
 
+    /// Returns the id of the object. For files this is the hash of their content.
+    pub fn oid(&self) -> Result<Oid, GitError> {
+        Ok(self.object()?.id())
+    }
+
+    /// Returns the size of a file in bytes without reading its content.
+    pub fn size(&self) -> Result<usize, GitError> {
+        let object = self.object()?;
+        match object.kind() {
+            Some(ObjectType::Blob) => Ok(self.repo.repo.odb()?.read_header(object.id())?.0),
+            _ => Err(GitError::IsDir),
+        }
+    }
+
+    /// Returns the commits that changed the item, newest first. At most `limit` commits are
+    /// returned.
+    ///
+    /// Items that do not exist (anymore) have a history as well, ending with their removal.
+    pub fn history(&self, limit: usize) -> Result<Vec<Revision>, GitError> {
+        let mut revwalk = self.repo.repo.revwalk()?;
+        revwalk.push(self.repo.commit()?.id())?;
+
+        let mut history = vec![];
+        for commit_oid in revwalk {
+            if history.len() >= limit {
+                break;
+            }
+            let commit = self.repo.repo.find_commit(commit_oid?)?;
+            let oid = self.oid_in(&commit)?;
+            let parent_oid = match commit.parents().next() {
+                Some(parent) => self.oid_in(&parent)?,
+                None => None,
+            };
+            if oid != parent_oid {
+                let author = commit.author();
+                history.push(Revision {
+                    commit: commit.id(),
+                    oid,
+                    message: commit.message().unwrap_or("").to_string(),
+                    author: author.name().unwrap_or("").to_string(),
+                    email: author.email().unwrap_or("").to_string(),
+                    time: commit.time().seconds(),
+                });
+            }
+        }
+        Ok(history)
+    }
+
+    /// Returns the time of the last commit that changed the item, in seconds since the epoch.
+    pub fn last_modified(&self) -> Result<i64, GitError> {
+        Ok(self.last_change()?.1)
+    }
+
+    /// Returns the id and time of the last commit that changed the item.
+    ///
+    /// This walks the history back to that commit, so the result is cached by commit and path.
+    pub fn last_change(&self) -> Result<(Oid, i64), GitError> {
+        let head = self.repo.commit()?.id();
+        if let Some(ref caches) = self.repo.caches {
+            if let Some(change) = caches.last_change(head, &self.path) {
+                return Ok(change);
+            }
+        }
+        let oid = self.oid()?;
+        let mut revwalk = self.repo.repo.revwalk()?;
+        revwalk.push(head)?;
+
+        // The commit contains the item, therefore there is at least one commit.
+        let mut last_change = (head, 0);
+        for commit_oid in revwalk {
+            let commit = self.repo.repo.find_commit(commit_oid?)?;
+            if self.oid_in(&commit)? != Some(oid) {
+                break;
+            }
+            last_change = (commit.id(), commit.time().seconds());
+        }
+        if let Some(ref caches) = self.repo.caches {
+            caches.insert_last_change(head, self.path.clone(), last_change);
+        }
+        Ok(last_change)
+    }
+
+    /// Returns `last_modified` of each of `items`, which have to be below this directory.
+    ///
+    /// The history is only walked once for all items. Commits that do not change the directory
+    /// are skipped without looking up the items.
+    pub fn last_modified_below(&self, items: &[GitItem<'repo>]) -> Result<Vec<i64>, GitError> {
+        let head = self.repo.commit()?.id();
+        let mut changes = items
+            .iter()
+            .map(|item| match self.repo.caches {
+                Some(ref caches) => caches.last_change(head, &item.path),
+                None => None,
+            })
+            .collect::<Vec<_>>();
+        let mut pending = (0..items.len())
+            .filter(|&i| changes[i].is_none())
+            .map(|i| Ok((i, items[i].oid()?)))
+            .collect::<Result<Vec<_>, GitError>>()?;
+
+        if !pending.is_empty() {
+            let mut revwalk = self.repo.repo.revwalk()?;
+            revwalk.push(head)?;
+            let mut directory = Some(self.oid()?);
+            let mut last_change = (head, 0);
+            for commit_oid in revwalk {
+                let commit = self.repo.repo.find_commit(commit_oid?)?;
+                let commit_directory = self.oid_in(&commit)?;
+                if commit_directory != directory {
+                    let mut unchanged = vec![];
+                    for (i, oid) in pending {
+                        if items[i].oid_in(&commit)? == Some(oid) {
+                            unchanged.push((i, oid));
+                        } else {
+                            changes[i] = Some(last_change);
+                        }
+                    }
+                    pending = unchanged;
+                    if pending.is_empty() {
+                        break;
+                    }
+                    directory = commit_directory;
+                }
+                last_change = (commit.id(), commit.time().seconds());
+            }
+            // The remaining items were added in the first commit.
+            for (i, _) in pending {
+                changes[i] = Some(last_change);
+            }
+            if let Some(ref caches) = self.repo.caches {
+                for (item, change) in items.iter().zip(&changes) {
+                    // All items have a change now.
+                    caches.insert_last_change(head, item.path.clone(), change.unwrap());
+                }
+            }
+        }
+        Ok(changes
+            .into_iter()
+            .map(|change| change.unwrap().1)
+            .collect())
+    }
+
+    /// Returns the id of the object at the path of this item in another commit.
+    fn oid_in(&self, commit: &Commit) -> Result<Option<Oid>, GitError> {
+        let tree = commit.tree()?;
+        if self.path.is_empty() {
+            return Ok(Some(tree.id()));
+        }
+        match tree.get_path(&StdPathBuf::from(&self.path)) {
+            Ok(entry) => Ok(Some(entry.id())),
+            Err(ref err) if err.code() == ErrorCode::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
     pub fn content(&self) -> Result<Vec<u8>, GitError> {
         if let Ok(blob) = self.object()?.into_blob() {
             Ok(blob.content().to_vec())
@@ -117,7 +438,7 @@ impl<'repo> GitItem<'repo> {
         }
     }
 
-    pub fn list(&self) -> Result<Vec<GitItem>, GitError> {
+    pub fn list(&self) -> Result<Vec<GitItem<'repo>>, GitError> {
         if let Ok(tree) = self.object()?.into_tree() {
             let mut items = vec![];
             for entry in tree.iter() {
@@ -170,31 +491,20 @@ impl<'repo> GitItem<'repo> {
         // objects manually because git2 does not support this. They can be deleted by manually
         // running `git gc` though.
 
-        let mut blob_writer = self.repo.repo.blob_writer(None)?;
-        blob_writer.write(content)?;
-        let blob_oid = blob_writer.commit()?;
+        let blob_oid = self.repo.write_blob(content)?;
+        self.edit_blob(blob_oid, message)
+    }
 
+    /// Like `edit` but with content that has already been written as a blob (see `write_blob`
+    /// and `BlobStream`).
+    pub fn edit_blob(&self, blob_oid: Oid, message: &str) -> Result<(), GitError> {
         let head = self.repo.head()?;
         let head_tree = head.tree()?;
         let mut tree_builder = self.repo.repo.treebuilder(Some(&head_tree))?;
 
         self.add_to_tree(&mut tree_builder, self.path.clone(), blob_oid)?;
 
-        let tree_oid = tree_builder.write()?;
-        let new_tree = self.repo.repo.find_tree(tree_oid)?;
-
-        let signature = Signature::now("smeagol", "smeagol@smeagol")?;
-
-        self.repo.repo.commit(
-            Some("HEAD"),
-            &signature,
-            &signature,
-            message,
-            &new_tree,
-            &[&head],
-        )?;
-
-        Ok(())
+        self.repo.commit_tree(tree_builder, &head, message)
     }
 
     fn add_to_tree(
@@ -269,22 +579,7 @@ impl<'repo> GitItem<'repo> {
             tree_builder
         };
 
-        // TODO deduplicate commit creation with Self::edit
-        let tree_oid = tree_builder.write()?;
-        let new_tree = self.repo.repo.find_tree(tree_oid)?;
-
-        let signature = Signature::now("smeagol", "smeagol@smeagol")?;
-
-        self.repo.repo.commit(
-            Some("HEAD"),
-            &signature,
-            &signature,
-            message,
-            &new_tree,
-            &[&head],
-        )?;
-
-        Ok(())
+        self.repo.commit_tree(tree_builder, &head, message)
     }
 
     fn remove_from_tree(&self, tree: &mut TreeBuilder, mut path: Path) -> Result<bool, GitError> {
@@ -325,6 +620,69 @@ impl<'repo> GitItem<'repo> {
     }
 }
 
+/// A commit that changed an item (see `GitItem::history`).
+#[derive(Debug, Clone)]
+pub struct Revision {
+    pub commit: Oid,
+    /// Id of the item after the commit. `None` if the commit removed it.
+    pub oid: Option<Oid>,
+    pub message: String,
+    pub author: String,
+    pub email: String,
+    /// Time of the commit in seconds since the epoch.
+    pub time: i64,
+}
+
+/// Collects the content of a blob from chunks, so large content never has to be held in memory.
+///
+/// The chunks are written to a temporary file in the repository directory. The blob is only
+/// created by `commit`, so content that is rejected leaves no object behind. The file is removed
+/// when the stream is dropped.
+pub struct BlobStream {
+    path: StdPathBuf,
+    file: File,
+}
+impl BlobStream {
+    /// Creates the temporary file in the repository at `dir`.
+    pub fn new<T: AsRef<StdPath>>(dir: T) -> Result<BlobStream, GitError> {
+        let path = dir.as_ref().join(format!(
+            "upload-{}-{}.tmp",
+            std::process::id(),
+            BLOB_STREAMS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(BlobStream { path, file })
+    }
+
+    /// Appends a chunk to the content.
+    pub fn write(&mut self, chunk: &[u8]) -> Result<(), GitError> {
+        Ok(self.file.write_all(chunk)?)
+    }
+
+    /// Reads the content written so far. This is meant for small content like sections and data
+    /// files that have to be checked.
+    pub fn content(&self) -> Result<Vec<u8>, GitError> {
+        Ok(fs::read(&self.path)?)
+    }
+
+    /// Creates the blob in `repo` and returns its id.
+    pub fn commit(mut self, repo: &GitRepository) -> Result<Oid, GitError> {
+        self.file.flush()?;
+        Ok(repo.repo.blob_path(&self.path)?)
+    }
+}
+impl Drop for BlobStream {
+    fn drop(&mut self) {
+        // The file may not be removable, e.g. if the directory was removed meanwhile. It is only
+        // garbage then.
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
 #[derive(Debug)]
 pub enum GitError {
     Git(git2::Error),
@@ -364,7 +722,11 @@ impl From<io::Error> for GitError {
 
 #[cfg(test)]
 mod tests {
-    use crate::git::GitError;
+    use std::sync::Arc;
+
+    use crate::cache::Caches;
+    use crate::config::CacheConfig;
+    use crate::git::{BlobStream, GitError};
     use crate::{GitRepository, Path};
     use tempdir::TempDir;
 
@@ -380,6 +742,77 @@ mod tests {
         assert!(path.is_dir());
     }
 
+    #[test]
+    fn tree_lookup_cache() {
+        let tmp = TempDir::new("smeagol").unwrap();
+        let caches = Arc::new(Caches::new(&CacheConfig::default()));
+        let repo = GitRepository::with_caches(tmp.path(), caches.clone()).unwrap();
+
+        let path = Path::from("a/b.md".to_string());
+        assert!(!repo.item(path.clone()).unwrap().exists().unwrap());
+        repo.item(path.clone()).unwrap().edit(b"1", "Add").unwrap();
+        assert_eq!(repo.item(path.clone()).unwrap().content().unwrap(), b"1");
+        repo.item(path.clone()).unwrap().edit(b"2", "Edit").unwrap();
+        assert_eq!(repo.item(path.clone()).unwrap().content().unwrap(), b"2");
+        assert_eq!(repo.item(path).unwrap().content().unwrap(), b"2");
+
+        let (_, trees, _) = caches.stats();
+        assert!(trees.hits > 0);
+        assert!(trees.misses > 0);
+    }
+
+    #[test]
+    fn last_modified_cache() {
+        let tmp = TempDir::new("smeagol").unwrap();
+        let caches = Arc::new(Caches::new(&CacheConfig::default()));
+        let repo = GitRepository::with_caches(tmp.path(), caches.clone()).unwrap();
+
+        let path = Path::from("a.md".to_string());
+        repo.item(path.clone()).unwrap().edit(b"1", "Add").unwrap();
+        let time = repo.item(path.clone()).unwrap().last_modified().unwrap();
+        assert_eq!(repo.item(path).unwrap().last_modified().unwrap(), time);
+
+        let (_, _, modified) = caches.stats();
+        assert_eq!((modified.hits, modified.misses), (1, 1));
+    }
+
+    #[test]
+    fn last_modified_below() {
+        let tmp = TempDir::new("smeagol").unwrap();
+        let caches = Arc::new(Caches::new(&CacheConfig::default()));
+        let repo = GitRepository::with_caches(tmp.path(), caches.clone()).unwrap();
+
+        for (path, content) in &[
+            ("dir/a.md", "1"),
+            ("dir/sub/b.md", "1"),
+            ("other.md", "1"),
+            ("dir/a.md", "2"),
+            ("other.md", "2"),
+            ("dir/c.md", "1"),
+        ] {
+            repo.item(Path::from(path.to_string()))
+                .unwrap()
+                .edit(content.as_bytes(), "Edit")
+                .unwrap();
+        }
+        let items = ["dir/a.md", "dir/sub/b.md", "dir/c.md"]
+            .iter()
+            .map(|path| repo.item(Path::from(path.to_string())).unwrap())
+            .collect::<Vec<_>>();
+        let directory = repo.item(Path::from("dir".to_string())).unwrap();
+        assert_eq!(directory.last_modified_below(&items).unwrap().len(), 3);
+
+        // The changes are cached and match the history of every item.
+        for item in &items {
+            assert_eq!(
+                item.last_change().unwrap().0,
+                item.history(1).unwrap()[0].commit
+            );
+        }
+        let (_, _, modified) = caches.stats();
+        assert_eq!((modified.hits, modified.misses), (3, 3));
+    }
+
     #[test]
     fn root_always_exists() {
         let tmp = TempDir::new("smeagol").unwrap();
@@ -421,6 +854,126 @@ mod tests {
         assert_eq!(item.content().unwrap(), file_content);
     }
 
+    #[test]
+    fn add_files_single_commit() {
+        let tmp = TempDir::new("smeagol").unwrap();
+        let repo = GitRepository::new(tmp.path()).unwrap();
+        let head = repo.head_id().unwrap();
+
+        let a = repo.write_blob(b"a").unwrap();
+        let b = repo.write_blob(b"b").unwrap();
+        let files = vec![
+            (Path::from("dir/a.md".to_string()), a),
+            (Path::from("dir/sub/b.md".to_string()), b),
+            (Path::from("c.md".to_string()), a),
+        ];
+        assert_eq!(repo.add_files(&files, "Add files").unwrap().len(), 3);
+        assert_eq!(repo.head().unwrap().parent_id(0).unwrap(), head);
+        for (path, oid) in &files {
+            assert_eq!(repo.item(path.clone()).unwrap().oid().unwrap(), *oid);
+        }
+
+        // Unchanged files are skipped.
+        let changed = repo
+            .add_files(
+                &[
+                    (Path::from("dir/a.md".to_string()), a),
+                    (Path::from("dir/a2.md".to_string()), b),
+                ],
+                "Add a2",
+            )
+            .unwrap();
+        assert_eq!(changed, vec![Path::from("dir/a2.md".to_string())]);
+        match repo.add_files(&files, "No change") {
+            Err(GitError::NoChange) => {}
+            _ => panic!(),
+        }
+
+        // Nothing is committed if a file cannot be added.
+        let head = repo.head_id().unwrap();
+        match repo.add_files(
+            &[
+                (Path::from("d.md".to_string()), a),
+                (Path::from("c.md/e.md".to_string()), a),
+            ],
+            "Conflict",
+        ) {
+            Err(GitError::CannotCreate) => {}
+            _ => panic!(),
+        }
+        assert_eq!(repo.head_id().unwrap(), head);
+    }
+
+    #[test]
+    fn history() {
+        let tmp = TempDir::new("smeagol").unwrap();
+        let repo = GitRepository::new(tmp.path()).unwrap();
+
+        let item = repo.item(Path::from("dir/index.md".to_string())).unwrap();
+        let other = repo.item(Path::from("other.md".to_string())).unwrap();
+        item.edit(b"first", "First").unwrap();
+        other.edit(b"other", "Other").unwrap();
+        item.edit(b"second", "Second").unwrap();
+        assert_eq!(item.size().unwrap(), 6);
+
+        let history = item.history(10).unwrap();
+        let messages = history
+            .iter()
+            .map(|revision| revision.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(messages, vec!["Second", "First"]);
+        assert_eq!(history[0].oid, Some(item.oid().unwrap()));
+        assert_eq!(history[0].author, "smeagol");
+        assert_eq!(item.history(1).unwrap().len(), 1);
+
+        item.remove("Remove").unwrap();
+        let history = item.history(10).unwrap();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].oid, None);
+        assert_eq!(
+            repo.item(Path::new()).unwrap().history(10).unwrap().len(),
+            5
+        );
+    }
+
+    #[test]
+    fn blob_stream() {
+        let tmp = TempDir::new("smeagol").unwrap();
+        let repo = GitRepository::new(tmp.path()).unwrap();
+
+        let content = "chunk ".repeat(100_000).into_bytes();
+        let mut stream = BlobStream::new(tmp.path()).unwrap();
+        for chunk in content.chunks(4096) {
+            stream.write(chunk).unwrap();
+        }
+        assert_eq!(stream.content().unwrap(), content);
+        let blob = stream.commit(&repo).unwrap();
+        assert_eq!(repo.read_blob(&blob.to_string()).unwrap(), content);
+        // The temporary file is removed.
+        assert!(!std::fs::read_dir(tmp.path()).unwrap().any(|entry| entry
+            .unwrap()
+            .path()
+            .extension()
+            == Some("tmp".as_ref())));
+
+        // Dropped streams do not create a blob.
+        let mut dropped = BlobStream::new(tmp.path()).unwrap();
+        dropped.write(b"dropped").unwrap();
+        drop(dropped);
+        assert!(repo
+            .read_blob(
+                &git2::Oid::hash_object(git2::ObjectType::Blob, b"dropped")
+                    .unwrap()
+                    .to_string()
+            )
+            .is_err());
+
+        let item = repo.item(Path::from("large.txt".to_string())).unwrap();
+        item.edit_blob(blob, "Add large file").unwrap();
+        assert_eq!(item.oid().unwrap(), blob);
+        assert_eq!(item.size().unwrap(), content.len());
+    }
+
     #[test]
     fn edit_file_dir() {
         let tmp = TempDir::new("smeagol").unwrap();
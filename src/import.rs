@@ -0,0 +1,286 @@
+//! Import of a directory from the filesystem.
+//!
+//! All files below the directory are added to the repository in a single commit. Ignore patterns
+//! use the same glob syntax as `[filetypes]`: patterns containing `/` match the path relative to
+//! the imported directory, all others the name of a file or directory. Ignored directories are
+//! skipped completely. `.git` directories are always ignored.
+
+use std::path::{Path as StdPath, PathBuf as StdPathBuf};
+
+use git2::{ObjectType, Oid};
+
+use log::warn;
+
+use crate::filetype::glob_match;
+use crate::git::GitError;
+use crate::{GitRepository, Path};
+
+/// Options of `smeagol import`.
+#[derive(Debug, PartialEq)]
+pub struct ImportOptions {
+    pub dir: StdPathBuf,
+    /// Directory in the repository the files are imported to.
+    pub prefix: Path,
+    pub ignore: Vec<String>,
+    pub message: Option<String>,
+    pub dry_run: bool,
+}
+impl ImportOptions {
+    /// Parses the arguments following `import`. Returns `None` if they are invalid.
+    pub fn parse(args: &[&str]) -> Option<ImportOptions> {
+        let mut options = ImportOptions {
+            dir: StdPathBuf::new(),
+            prefix: Path::new(),
+            ignore: vec![],
+            message: None,
+            dry_run: false,
+        };
+        let mut dir = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match *arg {
+                // The prefix must not point outside of the repository.
+                "--prefix" => options.prefix = Path::new().resolve(args.next()?.as_bytes())?,
+                "--ignore" => options.ignore.push(args.next()?.to_string()),
+                "--message" => options.message = Some(args.next()?.to_string()),
+                "--dry-run" => options.dry_run = true,
+                arg if arg.starts_with("--") || dir.is_some() => return None,
+                arg => dir = Some(StdPathBuf::from(arg)),
+            }
+        }
+        options.dir = dir?;
+        Some(options)
+    }
+}
+
+/// A file to import.
+pub struct ImportFile {
+    /// Path in the repository.
+    pub path: Path,
+    pub source: StdPathBuf,
+    pub status: ImportStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportStatus {
+    Added,
+    Modified,
+    Unchanged,
+}
+
+pub struct Importer<'a> {
+    repo: &'a GitRepository,
+    options: &'a ImportOptions,
+}
+impl<'a> Importer<'a> {
+    pub fn new(repo: &'a GitRepository, options: &'a ImportOptions) -> Importer<'a> {
+        Importer { repo, options }
+    }
+
+    /// Lists the files to import and compares them to the repository without writing anything.
+    pub fn scan(&self) -> Result<Vec<ImportFile>, GitError> {
+        let mut files = vec![];
+        self.collect(&self.options.dir, &Path::new(), &mut files)?;
+        files
+            .into_iter()
+            .map(|(relative, source)| {
+                let mut path = self.options.prefix.clone();
+                path.push(relative);
+                let oid = Oid::hash_file(ObjectType::Blob, &source)?;
+                let item = self.repo.item(path.clone())?;
+                let status = if !item.exists()? {
+                    ImportStatus::Added
+                } else if item.is_file()? && item.oid()? == oid {
+                    ImportStatus::Unchanged
+                } else {
+                    ImportStatus::Modified
+                };
+                Ok(ImportFile {
+                    path,
+                    source,
+                    status,
+                })
+            })
+            .collect()
+    }
+
+    /// Imports all changed files in a single commit. Returns the scanned files.
+    pub fn import(&self) -> Result<Vec<ImportFile>, GitError> {
+        let files = self.scan()?;
+        let blobs = files
+            .iter()
+            .filter(|file| file.status != ImportStatus::Unchanged)
+            .map(|file| {
+                let content = std::fs::read(&file.source)?;
+                Ok((file.path.clone(), self.repo.write_blob(&content)?))
+            })
+            .collect::<Result<Vec<_>, GitError>>()?;
+        if blobs.is_empty() {
+            return Err(GitError::NoChange);
+        }
+
+        let message = match self.options.message {
+            Some(ref message) => message.clone(),
+            None => format!("Import {}", self.options.dir.display()),
+        };
+        self.repo.add_files(&blobs, &message)?;
+        Ok(files)
+    }
+
+    /// Collects the files below `dir` (at `relative` in the imported tree) in sorted order.
+    fn collect(
+        &self,
+        dir: &StdPath,
+        relative: &Path,
+        files: &mut Vec<(Path, StdPathBuf)>,
+    ) -> Result<(), GitError> {
+        let mut entries = std::fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(name) => {
+                    warn!("Skipping {:?}: the name is not valid UTF-8", name);
+                    continue;
+                }
+            };
+            let mut path = relative.clone();
+            path.push(name.clone());
+            if name == ".git" || self.is_ignored(&path) {
+                continue;
+            }
+
+            // Symlinks could point outside of the imported directory.
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                self.collect(&entry.path(), &path, files)?;
+            } else if file_type.is_file() {
+                files.push((path, entry.path()));
+            } else {
+                warn!("Skipping {:?}: not a regular file", entry.path());
+            }
+        }
+        Ok(())
+    }
+
+    fn is_ignored(&self, path: &Path) -> bool {
+        self.options.ignore.iter().any(|pattern| {
+            if pattern.contains('/') {
+                glob_match(pattern.trim_start_matches('/').as_bytes(), path.bytes())
+            } else {
+                // Paths are never empty here.
+                glob_match(pattern.as_bytes(), path.filename().unwrap().bytes())
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use tempdir::TempDir;
+
+    use crate::git::GitError;
+    use crate::import::{ImportOptions, ImportStatus, Importer};
+    use crate::{GitRepository, Path};
+
+    #[test]
+    fn parse_options() {
+        let options = ImportOptions::parse(&[
+            "vault",
+            "--prefix",
+            "notes/",
+            "--ignore",
+            "*.tmp",
+            "--dry-run",
+        ])
+        .unwrap();
+        assert_eq!(options.dir, PathBuf::from("vault"));
+        assert_eq!(options.prefix, Path::from("notes".to_string()));
+        assert_eq!(options.ignore, vec!["*.tmp".to_string()]);
+        assert!(options.dry_run);
+
+        assert!(ImportOptions::parse(&[]).is_none());
+        assert!(ImportOptions::parse(&["a", "b"]).is_none());
+        assert!(ImportOptions::parse(&["a", "--prefix"]).is_none());
+        assert!(ImportOptions::parse(&["a", "--prefix", "../x"]).is_none());
+        assert!(ImportOptions::parse(&["a", "--force"]).is_none());
+    }
+
+    #[test]
+    fn import() {
+        let tmp = TempDir::new("smeagol").unwrap();
+        let repo = GitRepository::new(tmp.path().join("repo")).unwrap();
+        repo.item(Path::from("notes/b.md".to_string()))
+            .unwrap()
+            .edit(b"old", "Add b")
+            .unwrap();
+
+        let dir = tmp.path().join("vault");
+        std::fs::create_dir_all(dir.join("sub/.obsidian")).unwrap();
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+        std::fs::write(dir.join("a.md"), "a").unwrap();
+        std::fs::write(dir.join("b.md"), "b").unwrap();
+        std::fs::write(dir.join("a.tmp"), "tmp").unwrap();
+        std::fs::write(dir.join("sub/c.md"), "c").unwrap();
+        std::fs::write(dir.join("sub/.obsidian/config"), "{}").unwrap();
+        std::fs::write(dir.join(".git/HEAD"), "ref").unwrap();
+
+        let options = ImportOptions {
+            dir: dir.clone(),
+            prefix: Path::from("notes".to_string()),
+            ignore: vec!["*.tmp".to_string(), "sub/.obsidian".to_string()],
+            message: None,
+            dry_run: false,
+        };
+        let importer = Importer::new(&repo, &options);
+
+        let scanned = importer
+            .scan()
+            .unwrap()
+            .into_iter()
+            .map(|file| (file.path.to_string(), file.status))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            scanned,
+            vec![
+                ("notes/a.md".to_string(), ImportStatus::Added),
+                ("notes/b.md".to_string(), ImportStatus::Modified),
+                ("notes/sub/c.md".to_string(), ImportStatus::Added),
+            ]
+        );
+        // Scanning does not change the repository.
+        assert!(!repo
+            .item(Path::from("notes/a.md".to_string()))
+            .unwrap()
+            .exists()
+            .unwrap());
+
+        importer.import().unwrap();
+        for (path, content) in &[
+            ("notes/a.md", "a"),
+            ("notes/b.md", "b"),
+            ("notes/sub/c.md", "c"),
+        ] {
+            let item = repo.item(Path::from(path.to_string())).unwrap();
+            assert_eq!(item.content().unwrap(), content.as_bytes());
+        }
+        assert!(!repo
+            .item(Path::from("notes/a.tmp".to_string()))
+            .unwrap()
+            .exists()
+            .unwrap());
+
+        // Importing again changes nothing.
+        assert!(importer
+            .scan()
+            .unwrap()
+            .iter()
+            .all(|file| file.status == ImportStatus::Unchanged));
+        match importer.import() {
+            Err(GitError::NoChange) => {}
+            _ => panic!(),
+        }
+    }
+}
@@ -3,22 +3,86 @@ use log::error;
 mod smeagol;
 use smeagol::Smeagol;
 
+mod api;
+mod cache;
+mod command;
 mod config;
 use config::Config;
+mod directive;
 mod filetype;
 use filetype::Filetype;
 mod git;
 use git::GitRepository;
+mod markup;
+mod mediawiki;
+mod multipart;
 mod path;
 use path::{Path, PathStringBuilder};
 mod error;
 use error::SmeagolError;
+mod export;
+mod import;
+use import::ImportOptions;
+mod sections;
+mod statics;
+mod tasks;
+mod thumbnail;
+mod upload;
 mod warp_helper;
+mod wikitext;
+
+const USAGE: &str = "Usage:
+    smeagol                                         Serve the wiki
+    smeagol export <outdir> [--revision <revision>] Export the wiki as static HTML
+    smeagol import <dir> [options]                  Import a directory in a single commit
+    smeagol import-mediawiki <dump> [--prefix <path>]
+                                                    Import a MediaWiki XML dump with its history
+
+Import options:
+    --prefix <path>     Directory in the wiki to import to
+    --ignore <pattern>  Skip matching files and directories (repeatable)
+    --message <message> Commit message
+    --dry-run           Only list the files that would be imported";
 
 fn main() {
     pretty_env_logger::init_custom_env("SMEAGOL_LOG");
 
-    match Smeagol::new().and_then(|smeagol| smeagol.start()) {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    let result = match &args[..] {
+        [] => Smeagol::new().and_then(|smeagol| smeagol.start()),
+        ["export", outdir] => Smeagol::new().and_then(|smeagol| smeagol.export(outdir, None)),
+        ["export", outdir, "--revision", revision] => {
+            Smeagol::new().and_then(|smeagol| smeagol.export(outdir, Some(revision)))
+        }
+        ["import-mediawiki", dump] => {
+            Smeagol::new().and_then(|smeagol| smeagol.import_mediawiki(dump, Path::new()))
+        }
+        ["import-mediawiki", dump, "--prefix", prefix] => {
+            match Path::new().resolve(prefix.as_bytes()) {
+                Some(prefix) => {
+                    Smeagol::new().and_then(|smeagol| smeagol.import_mediawiki(dump, prefix))
+                }
+                None => {
+                    eprintln!("{}", USAGE);
+                    std::process::exit(2);
+                }
+            }
+        }
+        ["import", options @ ..] => match ImportOptions::parse(options) {
+            Some(options) => Smeagol::new().and_then(|smeagol| smeagol.import(&options)),
+            None => {
+                eprintln!("{}", USAGE);
+                std::process::exit(2);
+            }
+        },
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    match result {
         Ok(_) => (),
         Err(SmeagolError::Config(ref err)) => error!("Could not load config: {}", err),
         Err(ref err) => panic!("{}", err),
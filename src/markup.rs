@@ -0,0 +1,1043 @@
+//! Conversion of other markup languages to markdown.
+//!
+//! AsciiDoc, reStructuredText and Org-mode documents are converted to CommonMark (with the table
+//! extension) and then rendered like any markdown file. This way they share the rendering options
+//! and the sanitization. Only the commonly used subset of each language is supported: headings,
+//! lists, links, tables, code blocks and basic inline formatting. Unknown constructs are shown as
+//! text.
+
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+
+use regex::{Captures, Regex};
+
+lazy_static! {
+    static ref ASCIIDOC_INLINE: Regex = Regex::new(concat!(
+        r"(?P<code>`(?P<code_text>[^`]+)`)",
+        r"|\*\*(?P<strong2>.+?)\*\*|\*(?P<strong>[^*\s](?:[^*]*[^*\s])?)\*",
+        r"|__(?P<em2>.+?)__|_(?P<em>[^_\s](?:[^_]*[^_\s])?)_",
+        r"|<<(?P<xref_id>[^,>]+)(?:,\s*(?P<xref_text>[^>]+))?>>",
+        r"|image:(?P<image_url>[^\s\[:][^\s\[]*)\[(?P<image_alt>[^\]]*)\]",
+        r"|(?:(?:link|xref):(?P<link_target>[^\s\[]+)",
+        r"|(?P<link_url>(?:https?|ftp|mailto):[^\s\[]+))",
+        r"\[(?P<link_text>[^\]]*)\]",
+        r"|(?P<url>https?://[^\s\[<>]+)",
+    ))
+    .unwrap();
+    static ref RST_INLINE: Regex = Regex::new(concat!(
+        r"``(?P<literal>.+?)``",
+        r"|\*\*(?P<strong>.+?)\*\*",
+        r"|\*(?P<em>[^*\s](?:[^*]*[^*\s])?)\*",
+        r"|`(?P<link_text>[^`<]*?)\s*<(?P<link_url>[^>`]+)>`__?",
+        r"|`(?P<ref>[^`]+)`__?",
+        r"|:(?P<role>[\w-]+):`(?P<role_text>[^`]+)`",
+        r"|`(?P<interpreted>[^`]+)`",
+        r"|(?P<url>https?://[^\s<>]+)",
+    ))
+    .unwrap();
+    static ref ORG_INLINE: Regex = Regex::new(concat!(
+        r"\[\[(?P<link_url>[^\]]+)\](?:\[(?P<link_text>[^\]]+)\])?\]",
+        r#"|(?P<pre>^|[\s('"{])(?:"#,
+        r"\*(?P<bold>[^\s*](?:[^*]*[^\s*])?)\*",
+        r"|/(?P<italic>[^\s/](?:[^/]*[^\s/])?)/",
+        r"|_(?P<underline>[^\s_](?:[^_]*[^\s_])?)_",
+        r"|=(?P<verbatim>[^\s=](?:[^=]*[^\s=])?)=",
+        r"|~(?P<code>[^\s~](?:[^~]*[^\s~])?)~",
+        r"|\+(?P<strike>[^\s+](?:[^+]*[^\s+])?)\+",
+        r")",
+        r"|(?P<url>https?://[^\s<>\]]+)",
+    ))
+    .unwrap();
+    static ref ASCIIDOC_ATTRIBUTE: Regex = Regex::new(r"^:!?[\w-]+!?:(\s.*)?$").unwrap();
+    static ref ASCIIDOC_HEADING: Regex = Regex::new(r"^(={1,6})\s+(.+)$").unwrap();
+    static ref ASCIIDOC_LIST: Regex = Regex::new(r"^\s*(\*{1,5}|-|\.{1,5})\s+(.*)$").unwrap();
+    static ref ASCIIDOC_ADMONITION: Regex =
+        Regex::new(r"^(NOTE|TIP|IMPORTANT|WARNING|CAUTION):\s+(.*)$").unwrap();
+    static ref ASCIIDOC_IMAGE: Regex = Regex::new(r"^image::([^\[]+)\[([^\]]*)\]$").unwrap();
+    static ref RST_LIST: Regex =
+        Regex::new(r"^(\s*)(?:([-*+])|(#|\d+|[a-zA-Z])[.)]|\((#|\d+|[a-zA-Z])\))\s+(.*)$").unwrap();
+    static ref RST_TARGET: Regex = Regex::new(r"^\.\.\s+_([^:]+):\s*(\S+)\s*$").unwrap();
+    static ref RST_DIRECTIVE: Regex = Regex::new(r"^\.\.\s+([\w-]+)::\s*(.*)$").unwrap();
+    static ref ORG_HEADING: Regex = Regex::new(r"^(\*+)\s+(.*)$").unwrap();
+    static ref ORG_LIST: Regex = Regex::new(r"^(\s*)(?:([-+])|\s(\*)|(\d+)[.)])\s+(.*)$").unwrap();
+    static ref ORG_KEYWORD: Regex = Regex::new(r"^#\+(\w+):\s*(.*)$").unwrap();
+    static ref ORG_BLOCK_BEGIN: Regex = Regex::new(r"(?i)^#\+begin_(\w+)\s*(\S*)").unwrap();
+}
+
+/// Converts an AsciiDoc document to markdown.
+pub fn asciidoc_to_markdown(data: &str) -> String {
+    let lines = data.lines().collect::<Vec<_>>();
+    let mut markdown = String::new();
+
+    let mut source_language = String::new();
+    let mut table_header = false;
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        let trimmed = line.trim_end();
+
+        if trimmed.starts_with("////") {
+            // Comment block
+            i = skip_until(&lines, i + 1, |line| line.trim_end() == trimmed);
+        } else if trimmed.starts_with("//") {
+            // Line comment
+        } else if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            // Block attributes apply to the next block.
+            let attributes = &trimmed[1..trimmed.len() - 1];
+            let mut attributes = attributes.split(',').map(str::trim);
+            if attributes.next() == Some("source") {
+                source_language = attributes.next().unwrap_or("").to_string();
+            }
+            table_header = trimmed.contains("header");
+        } else if trimmed == "----" || trimmed == "...." {
+            let end = skip_until(&lines, i + 1, |line| line.trim_end() == trimmed);
+            markdown += &code_block(&source_language, &lines[i + 1..end.min(lines.len())]);
+            source_language.clear();
+            i = end;
+        } else if trimmed == "++++" {
+            // Passthrough blocks contain raw HTML.
+            let end = skip_until(&lines, i + 1, |line| line.trim_end() == trimmed);
+            markdown.push('\n');
+            for line in &lines[i + 1..end.min(lines.len())] {
+                markdown += line;
+                markdown.push('\n');
+            }
+            markdown.push('\n');
+            i = end;
+        } else if trimmed == "|===" {
+            let end = skip_until(&lines, i + 1, |line| line.trim_end() == "|===");
+            let table_lines = &lines[i + 1..end.min(lines.len())];
+            markdown += &asciidoc_table(table_lines, table_header);
+            table_header = false;
+            i = end;
+        } else if trimmed == "'''" {
+            markdown += "\n***\n\n";
+        } else if ASCIIDOC_ATTRIBUTE.is_match(trimmed) {
+            // Document attributes (`:toc:`, `:author: Name`) are not shown.
+        } else if let Some(captures) = ASCIIDOC_HEADING.captures(trimmed) {
+            markdown += &heading(captures[1].len(), &asciidoc_inline(&captures[2]));
+        } else if let Some(captures) = ASCIIDOC_IMAGE.captures(trimmed) {
+            markdown += &format!("![{}]({})\n", escape(&captures[2]), url(&captures[1]));
+        } else if let Some(captures) = ASCIIDOC_LIST.captures(line) {
+            let marker = &captures[1];
+            let depth = if marker == "-" { 0 } else { marker.len() - 1 };
+            markdown += &list_item(
+                depth,
+                marker.starts_with('.'),
+                &asciidoc_inline(&captures[2]),
+            );
+        } else if let Some(captures) = ASCIIDOC_ADMONITION.captures(trimmed) {
+            markdown += &format!(
+                "**{}:** {}\n",
+                title_case(&captures[1]),
+                asciidoc_inline(&captures[2])
+            );
+        } else if trimmed.starts_with('.') && trimmed.len() > 1 && !trimmed[1..].starts_with(' ') {
+            // Block title
+            markdown += &format!("*{}*\n\n", asciidoc_inline(&trimmed[1..]));
+        } else if let Some(text) = trimmed.strip_suffix(" +") {
+            // Hard line break
+            markdown += &asciidoc_inline(text);
+            markdown += "\\\n";
+        } else {
+            markdown += &asciidoc_inline(trimmed.trim_start());
+            markdown.push('\n');
+        }
+
+        i += 1;
+    }
+
+    markdown
+}
+
+fn asciidoc_inline(text: &str) -> String {
+    convert_inline(text, &ASCIIDOC_INLINE, |captures| {
+        if let Some(code) = captures.name("code_text") {
+            code_span(code.as_str())
+        } else if let Some(strong) = captures.name("strong").or(captures.name("strong2")) {
+            format!("**{}**", asciidoc_inline(strong.as_str()))
+        } else if let Some(em) = captures.name("em").or(captures.name("em2")) {
+            format!("*{}*", asciidoc_inline(em.as_str()))
+        } else if let Some(id) = captures.name("xref_id") {
+            let text = captures.name("xref_text").unwrap_or(id).as_str();
+            link(&asciidoc_inline(text), &format!("#{}", id.as_str()))
+        } else if let Some(image) = captures.name("image_url") {
+            format!(
+                "![{}]({})",
+                escape(&captures["image_alt"]),
+                url(image.as_str())
+            )
+        } else if let Some(target) = captures.name("link_target").or(captures.name("link_url")) {
+            let text = &captures["link_text"];
+            let text = if text.is_empty() {
+                escape(target.as_str())
+            } else {
+                asciidoc_inline(text)
+            };
+            link(&text, target.as_str())
+        } else {
+            format!("<{}>", &captures["url"])
+        }
+    })
+}
+
+/// Converts the lines between the `|===` delimiters.
+///
+/// The number of columns is taken from the first line. Cells may either be written on a single
+/// line or on separate lines. If the first row is followed by an empty line (or the block has the
+/// `header` option) it is used as header.
+fn asciidoc_table(lines: &[&str], mut has_header: bool) -> String {
+    let mut columns = 0;
+    let mut cells = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            if i == 1 {
+                has_header = true;
+            }
+            continue;
+        }
+        let line_cells = line
+            .split('|')
+            .skip(1)
+            .map(|cell| asciidoc_inline(cell.trim()))
+            .collect::<Vec<_>>();
+        if columns == 0 {
+            columns = line_cells.len();
+        }
+        if line.starts_with('|') {
+            cells.extend(line_cells);
+        } else if let Some(last) = cells.last_mut() {
+            // Continuation of the previous cell
+            *last += " ";
+            *last += &asciidoc_inline(line);
+        }
+    }
+    if columns == 0 {
+        return String::new();
+    }
+
+    let mut rows = cells
+        .chunks(columns)
+        .map(|row| row.to_vec())
+        .collect::<Vec<_>>();
+    let header = if has_header && !rows.is_empty() {
+        Some(rows.remove(0))
+    } else {
+        None
+    };
+    table(header, rows)
+}
+
+/// Converts a reStructuredText document to markdown.
+pub fn rst_to_markdown(data: &str) -> String {
+    let lines = data.lines().collect::<Vec<_>>();
+
+    // Hyperlink targets can be defined after they are used.
+    let targets = lines
+        .iter()
+        .filter_map(|line| RST_TARGET.captures(line))
+        .map(|captures| (captures[1].to_lowercase(), captures[2].to_string()))
+        .collect::<HashMap<_, _>>();
+
+    let mut markdown = String::new();
+    // Heading levels are determined by the order in which the underline styles are encountered.
+    let mut heading_styles: Vec<(char, bool)> = vec![];
+    let mut list_indentation = ListIndentation::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i].trim_end();
+        let next = lines.get(i + 1).map(|line| line.trim_end());
+        let previous_blank = i == 0 || lines[i - 1].trim().is_empty();
+
+        if let Some(style) = rst_underline(line).filter(|_| {
+            // Overlined title
+            next.map(|next| !next.trim().is_empty()).unwrap_or(false)
+                && lines.get(i + 2).map(|line| line.trim_end()) == Some(line)
+        }) {
+            markdown += &heading(
+                heading_level(&mut heading_styles, (style, true)),
+                &rst_inline(next.unwrap().trim(), &targets),
+            );
+            i += 3;
+            continue;
+        } else if let Some(style) = next.and_then(rst_underline).filter(|_| {
+            !line.trim().is_empty() && !line.starts_with(' ') && next.unwrap().len() >= line.len()
+        }) {
+            markdown += &heading(
+                heading_level(&mut heading_styles, (style, false)),
+                &rst_inline(line, &targets),
+            );
+            i += 2;
+            continue;
+        } else if previous_blank && rst_underline(line).is_some() && line.len() >= 4 {
+            markdown += "\n***\n\n";
+        } else if line.starts_with('+') && line.ends_with('+') && line.contains("-+") {
+            let end = skip_until(&lines, i, |line| {
+                let line = line.trim();
+                !line.starts_with('+') && !line.starts_with('|')
+            });
+            markdown += &rst_grid_table(&lines[i..end], &targets);
+            i = end;
+            continue;
+        } else if is_rst_simple_table_border(line) {
+            // The table ends with the last border line before an empty line.
+            let mut end = i + 1;
+            let mut last_border = i;
+            while end < lines.len() && !lines[end].trim().is_empty() {
+                if is_rst_simple_table_border(lines[end].trim_end()) {
+                    last_border = end;
+                }
+                end += 1;
+            }
+            markdown += &rst_simple_table(&lines[i..=last_border], &targets);
+            i = last_border + 1;
+            continue;
+        } else if let Some(captures) = RST_DIRECTIVE.captures(line) {
+            let directive = captures[1].to_lowercase();
+            let argument = captures[2].trim();
+            let (content, end) = indented_block(&lines, i + 1);
+            // Directive options (`:linenos:`) are part of the indented block.
+            let content = content
+                .into_iter()
+                .skip_while(|line| line.trim_start().starts_with(':'))
+                .collect::<Vec<_>>();
+            match &directive[..] {
+                "code" | "code-block" | "sourcecode" => {
+                    markdown += &code_block(argument, &trim_blank_lines(&content));
+                }
+                "image" | "figure" => {
+                    markdown += &format!("![]({})\n\n", url(argument));
+                }
+                "note" | "tip" | "important" | "warning" | "caution" | "danger" | "attention"
+                | "hint" | "error" | "admonition" => {
+                    markdown += &format!("\n**{}:** ", title_case(&directive));
+                    if !argument.is_empty() {
+                        markdown += &rst_inline(argument, &targets);
+                        markdown.push('\n');
+                    }
+                    for line in content {
+                        markdown += &rst_inline(line.trim(), &targets);
+                        markdown.push('\n');
+                    }
+                    markdown.push('\n');
+                }
+                // Unsupported directives are not shown.
+                _ => {}
+            }
+            i = end;
+            continue;
+        } else if line.starts_with("..") {
+            // Comments and hyperlink targets
+            let (_, end) = indented_block(&lines, i + 1);
+            i = end;
+            continue;
+        } else if let Some(captures) = RST_LIST.captures(line) {
+            let depth = list_indentation.depth(captures[1].len());
+            let text = rst_inline(&captures[5], &targets);
+            if line.ends_with("::") {
+                markdown += &list_item(depth, captures.get(2).is_none(), &text[..text.len() - 1]);
+                let (content, end) = indented_block(&lines, i + 1);
+                markdown += &code_block("", &trim_blank_lines(&content));
+                i = end;
+                continue;
+            }
+            markdown += &list_item(depth, captures.get(2).is_none(), &text);
+        } else if line.ends_with("::") {
+            // Literal block
+            let text = line.trim()[..line.trim().len() - 2].trim_end();
+            if !text.is_empty() {
+                markdown += &rst_inline(text, &targets);
+                // `Paragraph::` is shown as `Paragraph:`.
+                if !line.ends_with(" ::") {
+                    markdown.push(':');
+                }
+                markdown.push('\n');
+            }
+            let (content, end) = indented_block(&lines, i + 1);
+            markdown += &code_block("", &trim_blank_lines(&content));
+            i = end;
+            continue;
+        } else if line.trim().is_empty() {
+            markdown.push('\n');
+        } else if line.starts_with(' ') && list_indentation.is_empty() {
+            // Block quote
+            markdown += "> ";
+            markdown += &rst_inline(line.trim(), &targets);
+            markdown.push('\n');
+        } else {
+            if !line.starts_with(' ') {
+                list_indentation.clear();
+            }
+            markdown += &rst_inline(line.trim(), &targets);
+            markdown.push('\n');
+        }
+
+        i += 1;
+    }
+
+    markdown
+}
+
+fn rst_inline(text: &str, targets: &HashMap<String, String>) -> String {
+    convert_inline(text, &RST_INLINE, |captures| {
+        if let Some(literal) = captures.name("literal") {
+            code_span(literal.as_str())
+        } else if let Some(strong) = captures.name("strong") {
+            format!("**{}**", escape(strong.as_str()))
+        } else if let Some(em) = captures.name("em") {
+            format!("*{}*", escape(em.as_str()))
+        } else if let Some(target) = captures.name("link_url") {
+            let text = captures["link_text"].trim();
+            let text = if text.is_empty() {
+                target.as_str()
+            } else {
+                text
+            };
+            link(&escape(text), target.as_str())
+        } else if let Some(reference) = captures.name("ref") {
+            match targets.get(&reference.as_str().to_lowercase()) {
+                Some(target) => link(&escape(reference.as_str()), target),
+                None => escape(reference.as_str()),
+            }
+        } else if let Some(role) = captures.name("role") {
+            match role.as_str() {
+                "code" | "literal" | "file" | "command" | "kbd" => {
+                    code_span(&captures["role_text"])
+                }
+                _ => escape(&captures["role_text"]),
+            }
+        } else if let Some(interpreted) = captures.name("interpreted") {
+            format!("*{}*", escape(interpreted.as_str()))
+        } else {
+            format!("<{}>", &captures["url"])
+        }
+    })
+}
+
+/// Returns the character used if the line is a section underline or a transition.
+fn rst_underline(line: &str) -> Option<char> {
+    let first = line.chars().next()?;
+    if line.len() >= 3
+        && first.is_ascii_punctuation()
+        && !"+|".contains(first)
+        && line.chars().all(|c| c == first)
+    {
+        Some(first)
+    } else {
+        None
+    }
+}
+
+fn is_rst_simple_table_border(line: &str) -> bool {
+    line.starts_with('=') && line.contains(' ') && line.chars().all(|c| c == '=' || c == ' ')
+}
+
+/// Converts a simple table. The column boundaries are defined by the border lines.
+fn rst_simple_table(lines: &[&str], targets: &HashMap<String, String>) -> String {
+    let border = lines[0];
+    let mut columns = vec![];
+    let mut previous = ' ';
+    for (i, c) in border.char_indices() {
+        if c == '=' && previous == ' ' {
+            columns.push(i);
+        }
+        previous = c;
+    }
+
+    let borders = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| is_rst_simple_table_border(line.trim_end()))
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    let has_header = borders.len() > 2;
+
+    let mut header = None;
+    let mut rows: Vec<Vec<String>> = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        if borders.contains(&i) {
+            if has_header && i == borders[1] {
+                header = rows.pop();
+            }
+            continue;
+        }
+        let row = split_columns(line, &columns)
+            .iter()
+            .map(|cell| rst_inline(cell.trim(), targets))
+            .collect::<Vec<_>>();
+        // Rows with an empty first column continue the previous row.
+        match rows.last_mut() {
+            Some(previous) if row[0].is_empty() => {
+                join_cells(previous, row);
+            }
+            _ => rows.push(row),
+        }
+    }
+
+    table(header, rows)
+}
+
+/// Converts a grid table. The column boundaries are taken from the `+` in the first line. Cells
+/// spanning multiple columns are not supported.
+fn rst_grid_table(lines: &[&str], targets: &HashMap<String, String>) -> String {
+    let border = lines[0].trim();
+    let columns = border
+        .char_indices()
+        .filter(|&(_, c)| c == '+')
+        .map(|(i, _)| i + 1)
+        .collect::<Vec<_>>();
+    let columns = &columns[..columns.len() - 1];
+
+    let mut header = None;
+    let mut rows: Vec<Vec<String>> = vec![];
+    let mut current: Option<Vec<String>> = None;
+    for line in &lines[1..] {
+        let line = line.trim();
+        if line.starts_with('+') {
+            if let Some(row) = current.take() {
+                rows.push(row);
+            }
+            if line.contains('=') && header.is_none() {
+                header = rows.pop();
+            }
+        } else {
+            let row = split_columns(line, columns)
+                .iter()
+                .map(|cell| rst_inline(cell.trim_end_matches('|').trim(), targets))
+                .collect::<Vec<_>>();
+            match current {
+                Some(ref mut current) => join_cells(current, row),
+                None => current = Some(row),
+            }
+        }
+    }
+
+    table(header, rows)
+}
+
+/// Converts an Org-mode document to markdown.
+pub fn org_to_markdown(data: &str) -> String {
+    let lines = data.lines().collect::<Vec<_>>();
+    let mut markdown = String::new();
+
+    let mut list_indentation = ListIndentation::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i].trim_end();
+
+        if let Some(captures) = ORG_BLOCK_BEGIN.captures(line) {
+            let kind = captures[1].to_lowercase();
+            let end_line = format!("#+end_{}", kind);
+            let end = skip_until(&lines, i + 1, |line| {
+                line.trim().to_lowercase().starts_with(&end_line)
+            });
+            let content = &lines[i + 1..end.min(lines.len())];
+            match &kind[..] {
+                "src" => markdown += &code_block(&captures[2], content),
+                "example" => markdown += &code_block("", content),
+                "quote" | "verse" => {
+                    markdown.push('\n');
+                    for line in content {
+                        markdown += "> ";
+                        markdown += &org_inline(line.trim());
+                        markdown.push('\n');
+                    }
+                    markdown.push('\n');
+                }
+                _ => {
+                    for line in content {
+                        markdown += &org_inline(line.trim());
+                        markdown.push('\n');
+                    }
+                }
+            }
+            i = end;
+        } else if let Some(captures) = ORG_KEYWORD.captures(line) {
+            if captures[1].eq_ignore_ascii_case("title") {
+                markdown += &heading(1, &org_inline(&captures[2]));
+            }
+        } else if line.starts_with("# ") || line == "#" {
+            // Comment
+        } else if let Some(captures) = ORG_HEADING.captures(line) {
+            list_indentation.clear();
+            markdown += &heading(captures[1].len().min(6), &org_inline(&captures[2]));
+        } else if line.trim_start().starts_with('|') {
+            let end = skip_until(&lines, i, |line| !line.trim_start().starts_with('|'));
+            markdown += &org_table(&lines[i..end]);
+            i = end;
+            continue;
+        } else if line.trim().len() >= 5 && line.trim().chars().all(|c| c == '-') {
+            markdown += "\n***\n\n";
+        } else if let Some(captures) = ORG_LIST.captures(line) {
+            let depth = list_indentation.depth(captures[1].len());
+            let text = captures[5].replacen("[X]", "[x]", 1);
+            markdown += &list_item(depth, captures.get(4).is_some(), &org_inline(&text));
+        } else if line.trim().is_empty() {
+            markdown.push('\n');
+        } else {
+            if !line.starts_with(' ') {
+                list_indentation.clear();
+            }
+            markdown += &org_inline(line.trim());
+            markdown.push('\n');
+        }
+
+        i += 1;
+    }
+
+    markdown
+}
+
+fn org_inline(text: &str) -> String {
+    convert_inline(text, &ORG_INLINE, |captures| {
+        let whole = captures.get(0).unwrap();
+        if let Some(target) = captures.name("link_url") {
+            let target = target.as_str();
+            let target = target.strip_prefix("file:").unwrap_or(target);
+            let text = captures
+                .name("link_text")
+                .map(|text| org_inline(text.as_str()))
+                .unwrap_or_else(|| escape(target));
+            return link(&text, target);
+        } else if let Some(url) = captures.name("url") {
+            return format!("<{}>", url.as_str());
+        }
+
+        // Emphasis markers have to be followed by whitespace or punctuation.
+        let following = text[whole.end()..].chars().next();
+        if following.map(char::is_alphanumeric).unwrap_or(false) {
+            return escape(whole.as_str());
+        }
+
+        let pre = escape(&captures["pre"]);
+        if let Some(bold) = captures.name("bold") {
+            format!("{}**{}**", pre, org_inline(bold.as_str()))
+        } else if let Some(italic) = captures.name("italic").or(captures.name("underline")) {
+            format!("{}*{}*", pre, org_inline(italic.as_str()))
+        } else if let Some(code) = captures.name("verbatim").or(captures.name("code")) {
+            format!("{}{}", pre, code_span(code.as_str()))
+        } else {
+            format!("{}~~{}~~", pre, org_inline(&captures["strike"]))
+        }
+    })
+}
+
+/// Converts a table. Separator lines directly after the first row mark it as header.
+fn org_table(lines: &[&str]) -> String {
+    let is_separator = |line: &str| line.trim().starts_with("|-");
+
+    let mut rows = lines
+        .iter()
+        .filter(|line| !is_separator(line))
+        .map(|line| {
+            let line = line.trim();
+            let line = line.strip_prefix('|').unwrap_or(line);
+            let line = line.strip_suffix('|').unwrap_or(line);
+            line.split('|')
+                .map(|cell| org_inline(cell.trim()))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let header = if lines.len() > 1 && is_separator(lines[1]) && !rows.is_empty() {
+        Some(rows.remove(0))
+    } else {
+        None
+    };
+
+    table(header, rows)
+}
+
+/// Tracks the indentation of nested list items to compute their depth.
+struct ListIndentation {
+    stack: Vec<usize>,
+}
+impl ListIndentation {
+    fn new() -> ListIndentation {
+        ListIndentation { stack: vec![] }
+    }
+
+    fn depth(&mut self, indentation: usize) -> usize {
+        while let Some(&last) = self.stack.last() {
+            if last == indentation {
+                return self.stack.len() - 1;
+            } else if last > indentation {
+                self.stack.pop();
+            } else {
+                break;
+            }
+        }
+        self.stack.push(indentation);
+        self.stack.len() - 1
+    }
+
+    fn clear(&mut self) {
+        self.stack.clear();
+    }
+
+    fn is_empty(&self) -> bool {
+        self.stack.is_empty()
+    }
+}
+
+/// Converts inline markup. Text outside of the matches is escaped.
+fn convert_inline<F>(text: &str, regex: &Regex, convert: F) -> String
+where
+    F: Fn(&Captures) -> String,
+{
+    let mut markdown = String::new();
+    let mut last = 0;
+    for captures in regex.captures_iter(text) {
+        let whole = captures.get(0).unwrap();
+        markdown += &escape(&text[last..whole.start()]);
+        markdown += &convert(&captures);
+        last = whole.end();
+    }
+    markdown += &escape(&text[last..]);
+    markdown
+}
+
+/// Escapes all characters that have a meaning in markdown.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\`*_[]<>#|!&~^".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Returns the index after the first line from `start` matching `predicate` (or the number of
+/// lines).
+fn skip_until<F>(lines: &[&str], start: usize, predicate: F) -> usize
+where
+    F: Fn(&str) -> bool,
+{
+    lines[start.min(lines.len())..]
+        .iter()
+        .position(|line| predicate(line))
+        .map(|position| start + position)
+        .unwrap_or(lines.len())
+}
+
+/// Returns the block of lines indented relative to the line before `start` with the common
+/// indentation removed, and the index of the first line after the block.
+fn indented_block<'a>(lines: &[&'a str], start: usize) -> (Vec<&'a str>, usize) {
+    let mut end = start;
+    while end < lines.len() && (lines[end].trim().is_empty() || lines[end].starts_with(' ')) {
+        end += 1;
+    }
+    let block = &lines[start.min(end)..end];
+    // Only ASCII whitespace counts as indentation, so removing it keeps lines at char boundaries.
+    let leading = |line: &str| line.len() - line.trim_start_matches([' ', '\t']).len();
+    let indentation = block
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| leading(line))
+        .min()
+        .unwrap_or(0);
+    let block = block
+        .iter()
+        .map(|line| &line[leading(line).min(indentation)..])
+        .collect();
+
+    (block, end)
+}
+
+fn trim_blank_lines<'a>(lines: &[&'a str]) -> Vec<&'a str> {
+    let start = lines.iter().position(|line| !line.trim().is_empty());
+    let end = lines.iter().rposition(|line| !line.trim().is_empty());
+    match (start, end) {
+        (Some(start), Some(end)) => lines[start..=end].to_vec(),
+        _ => vec![],
+    }
+}
+
+fn split_columns(line: &str, columns: &[usize]) -> Vec<String> {
+    columns
+        .iter()
+        .enumerate()
+        .map(|(i, &start)| {
+            let end = columns.get(i + 1).cloned().unwrap_or(line.len());
+            line.get(start.min(line.len())..end.min(line.len()))
+                .unwrap_or("")
+                .to_string()
+        })
+        .collect()
+}
+
+fn join_cells(row: &mut [String], continuation: Vec<String>) {
+    for (cell, text) in row.iter_mut().zip(continuation) {
+        if !text.is_empty() {
+            if !cell.is_empty() {
+                cell.push(' ');
+            }
+            *cell += &text;
+        }
+    }
+}
+
+fn heading_level(styles: &mut Vec<(char, bool)>, style: (char, bool)) -> usize {
+    if let Some(position) = styles.iter().position(|&s| s == style) {
+        (position + 1).min(6)
+    } else {
+        styles.push(style);
+        styles.len().min(6)
+    }
+}
+
+fn heading(level: usize, text: &str) -> String {
+    format!("\n{} {}\n\n", "#".repeat(level), text)
+}
+
+fn list_item(depth: usize, ordered: bool, text: &str) -> String {
+    format!(
+        "{}{} {}\n",
+        "    ".repeat(depth),
+        if ordered { "1." } else { "-" },
+        text
+    )
+}
+
+fn code_block(language: &str, lines: &[&str]) -> String {
+    // The fence has to be longer than any backtick sequence in the code.
+    let longest = lines
+        .iter()
+        .map(|line| line.trim_start().chars().take_while(|&c| c == '`').count())
+        .max()
+        .unwrap_or(0);
+    let fence = "`".repeat(longest.max(2) + 1);
+
+    let mut block = format!("\n{}{}\n", fence, language);
+    for line in lines {
+        block += line;
+        block.push('\n');
+    }
+    block += &fence;
+    block += "\n\n";
+    block
+}
+
+fn code_span(code: &str) -> String {
+    let mut longest = 0;
+    let mut current = 0;
+    for c in code.chars() {
+        current = if c == '`' { current + 1 } else { 0 };
+        longest = longest.max(current);
+    }
+    let fence = "`".repeat(longest + 1);
+    if code.starts_with('`') || code.ends_with('`') {
+        format!("{} {} {}", fence, code, fence)
+    } else {
+        format!("{}{}{}", fence, code, fence)
+    }
+}
+
+fn link(text: &str, target: &str) -> String {
+    format!("[{}]({})", text, url(target))
+}
+
+fn url(target: &str) -> String {
+    target
+        .trim()
+        .replace(' ', "%20")
+        .replace('(', "%28")
+        .replace(')', "%29")
+}
+
+fn table(header: Option<Vec<String>>, rows: Vec<Vec<String>>) -> String {
+    let columns = header
+        .iter()
+        .chain(rows.iter())
+        .map(Vec::len)
+        .max()
+        .unwrap_or(0);
+    if columns == 0 {
+        return String::new();
+    }
+
+    let row = |cells: &[String]| {
+        let mut line = String::from("|");
+        for i in 0..columns {
+            line += " ";
+            line += cells.get(i).map(String::as_str).unwrap_or("");
+            line += " |";
+        }
+        line.push('\n');
+        line
+    };
+
+    let mut markdown = String::from("\n");
+    markdown += &row(&header.unwrap_or_default());
+    markdown += &row(&vec!["---".to_string(); columns]);
+    for cells in &rows {
+        markdown += &row(cells);
+    }
+    markdown.push('\n');
+    markdown
+}
+
+fn title_case(word: &str) -> String {
+    let lowercase = word.to_lowercase();
+    let mut chars = lowercase.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::markup::{asciidoc_to_markdown, org_to_markdown, rst_to_markdown};
+
+    fn render(markdown: &str) -> String {
+        let options = comrak::ComrakOptions {
+            ext_table: true,
+            ..Default::default()
+        };
+        comrak::markdown_to_html(markdown, &options)
+    }
+
+    #[test]
+    fn asciidoc() {
+        let html = render(&asciidoc_to_markdown(
+            "= Title\n\
+             :toc:\n\
+             \n\
+             == Section\n\
+             \n\
+             Some *bold* and _italic_ text with https://example.com[a link].\n\
+             \n\
+             * One\n\
+             ** Nested\n\
+             . First\n\
+             \n\
+             [source,rust]\n\
+             ----\n\
+             fn main() {}\n\
+             ----\n\
+             \n\
+             |===\n\
+             |A |B\n\
+             \n\
+             |1 |2\n\
+             |===\n",
+        ));
+        assert!(html.contains("<h1>Title</h1>"));
+        assert!(html.contains("<h2>Section</h2>"));
+        assert!(!html.contains("toc"));
+        assert!(html.contains("<strong>bold</strong>"));
+        assert!(html.contains("<em>italic</em>"));
+        assert!(html.contains("<a href=\"https://example.com\">a link</a>"));
+        assert!(html.contains("<li>Nested</li>"));
+        assert!(html.contains("<ol>"));
+        assert!(html.contains("<code class=\"language-rust\">fn main() {}"));
+        assert!(html.contains("<th>A</th>"));
+        assert!(html.contains("<td>2</td>"));
+    }
+
+    #[test]
+    fn rst() {
+        let html = render(&rst_to_markdown(concat!(
+            "=====\n",
+            "Title\n",
+            "=====\n",
+            "\n",
+            "Section\n",
+            "-------\n",
+            "\n",
+            "Some **bold** and ``code`` with `a link <https://example.com>`_ and `ref`_.\n",
+            "\n",
+            "- One\n",
+            "\n",
+            "  - Nested\n",
+            "\n",
+            "#. First\n",
+            "\n",
+            ".. code-block:: python\n",
+            "\n",
+            "   print(1)\n",
+            "\n",
+            "=====  =====\n",
+            "A      B\n",
+            "=====  =====\n",
+            "1      2\n",
+            "=====  =====\n",
+            "\n",
+            ".. _ref: https://example.org\n",
+        )));
+        assert!(html.contains("<h1>Title</h1>"));
+        assert!(html.contains("<h2>Section</h2>"));
+        assert!(html.contains("<strong>bold</strong>"));
+        assert!(html.contains("<code>code</code>"));
+        assert!(html.contains("<a href=\"https://example.com\">a link</a>"));
+        assert!(html.contains("<a href=\"https://example.org\">ref</a>"));
+        assert!(html.contains("Nested"));
+        assert!(html.contains("<ol>"));
+        assert!(html.contains("<code class=\"language-python\">print(1)"));
+        assert!(html.contains("<th>A</th>"));
+        assert!(html.contains("<td>2</td>"));
+    }
+
+    #[test]
+    fn rst_unicode_indentation() {
+        // Lines of other whitespace in an indented block do not count as indentation.
+        let markdown = rst_to_markdown(".. note::\n\n  first\n\u{3000}\n  second\n");
+        assert!(markdown.contains("first"));
+        assert!(markdown.contains("second"));
+    }
+
+    #[test]
+    fn rst_grid_table() {
+        let html = render(&rst_to_markdown(
+            "+-----+-----+\n\
+             | A   | B   |\n\
+             +=====+=====+\n\
+             | 1   | 2   |\n\
+             | one | two |\n\
+             +-----+-----+\n",
+        ));
+        assert!(html.contains("<th>A</th>"));
+        assert!(html.contains("<td>1 one</td>"));
+    }
+
+    #[test]
+    fn org() {
+        let html = render(&org_to_markdown(
+            "#+TITLE: Title\n\
+             * Section\n\
+             Some *bold*, /italic/ and =code= with [[https://example.com][a link]].\n\
+             A path like /usr/bin/ is not italic.\n\
+             - One\n  \
+               - Nested\n\
+             1. First\n\
+             #+BEGIN_SRC sh\n\
+             echo 1\n\
+             #+END_SRC\n\
+             | A | B |\n\
+             |---+---|\n\
+             | 1 | 2 |\n",
+        ));
+        assert!(html.contains("<h1>Title</h1>"));
+        assert!(html.contains("<h1>Section</h1>"));
+        assert!(html.contains("<strong>bold</strong>"));
+        assert!(html.contains("<em>italic</em>"));
+        assert!(html.contains("<code>code</code>"));
+        assert!(html.contains("<a href=\"https://example.com\">a link</a>"));
+        assert!(html.contains("/usr/bin/"));
+        assert!(html.contains("<li>Nested</li>"));
+        assert!(html.contains("<ol>"));
+        assert!(html.contains("<code class=\"language-sh\">echo 1"));
+        assert!(html.contains("<th>A</th>"));
+        assert!(html.contains("<td>2</td>"));
+    }
+
+    #[test]
+    fn special_characters_are_escaped() {
+        let markdown = org_to_markdown("<script> # not a heading");
+        assert!(render(&markdown).contains("&lt;script&gt; # not a heading"));
+    }
+}
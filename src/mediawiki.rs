@@ -0,0 +1,448 @@
+//! Import of MediaWiki XML dumps (`Special:Export` or `dumpBackup.php --full`).
+//!
+//! Every revision is replayed as a commit with the original author, timestamp and edit summary.
+//! The commits of all pages are ordered by their timestamp. Wikitext is converted to markdown (see
+//! `wikitext`). Page titles are mapped to paths: namespaces become directories, subpages
+//! subdirectories and spaces underscores, e.g. `Help talk:Foo bar/Baz` is imported to
+//! `Help_talk/Foo_bar/Baz.md`. Uploaded files are not part of dumps; images are linked to
+//! `File/<name>`.
+
+use std::fmt;
+use std::io::BufRead;
+
+use git2::{Oid, Signature, Time};
+
+use lazy_static::lazy_static;
+
+use log::info;
+
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+
+use regex::Regex;
+
+use crate::git::GitError;
+use crate::wikitext::{to_markdown, Link};
+use crate::{GitRepository, Path, PathStringBuilder};
+
+lazy_static! {
+    static ref TIMESTAMP: Regex =
+        Regex::new(r"^(\d{4})-(\d{2})-(\d{2})T(\d{2}):(\d{2}):(\d{2})Z$").unwrap();
+}
+
+/// Key of the `File` namespace, which is also called `Image`.
+const FILE_NAMESPACE: i64 = 6;
+
+/// A revision of a page as read from the dump.
+#[derive(Default)]
+struct Revision {
+    id: u64,
+    timestamp: String,
+    contributor: Option<String>,
+    comment: Option<String>,
+    text: String,
+}
+
+/// A converted revision waiting to be committed.
+struct Edit {
+    id: u64,
+    time: i64,
+    title: String,
+    contributor: String,
+    comment: Option<String>,
+    path: Path,
+    blob: Oid,
+}
+
+pub struct MediaWikiImporter<'a> {
+    repo: &'a GitRepository,
+    prefix: Path,
+    /// Namespaces by key. The main namespace has no name.
+    namespaces: Vec<(i64, String)>,
+    edits: Vec<Edit>,
+}
+impl<'a> MediaWikiImporter<'a> {
+    pub fn new(repo: &'a GitRepository, prefix: Path) -> MediaWikiImporter<'a> {
+        MediaWikiImporter {
+            repo,
+            prefix,
+            namespaces: vec![],
+            edits: vec![],
+        }
+    }
+
+    /// Imports a dump. Returns the number of commits.
+    ///
+    /// The converted revisions are written to the repository while reading, so only their metadata
+    /// is kept in memory until they are committed.
+    pub fn import<R: BufRead>(mut self, dump: R) -> Result<usize, MediaWikiError> {
+        self.read(dump)?;
+
+        // Dumps are ordered by page. The sort is stable and revision ids increase, so edits with
+        // the same timestamp keep their order.
+        self.edits.sort_by_key(|edit| (edit.time, edit.id));
+        let mut commits = 0;
+        for edit in &self.edits {
+            // Dumps do not contain email addresses, but git requires one.
+            let author = Signature::new(
+                &edit.contributor,
+                "mediawiki@smeagol",
+                &Time::new(edit.time, 0),
+            )?;
+            let mut message = edit
+                .comment
+                .clone()
+                .filter(|comment| !comment.trim().is_empty())
+                .unwrap_or_else(|| format!("Edit {}", edit.title));
+            message += &format!(
+                "\n\nMediaWiki-Page: {}\nMediaWiki-Revision: {}",
+                edit.title, edit.id
+            );
+            match self
+                .repo
+                .add_files_by(&[(edit.path.clone(), edit.blob)], &message, &author)
+            {
+                Ok(_) => commits += 1,
+                // Null edits and edits only changing unconverted markup.
+                Err(GitError::NoChange) => info!("Skipping unchanged revision {}", edit.id),
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(commits)
+    }
+
+    fn read<R: BufRead>(&mut self, dump: R) -> Result<(), MediaWikiError> {
+        let mut reader = Reader::from_reader(dump);
+        let mut buffer = vec![];
+        // Names of the open elements.
+        let mut elements: Vec<String> = vec![];
+        let mut text = String::new();
+        let mut namespace_key = 0;
+        let mut title = String::new();
+        let mut revision = Revision::default();
+
+        loop {
+            match reader.read_event_into(&mut buffer)? {
+                Event::Start(start) => {
+                    let name = start.local_name().as_ref().to_string();
+                    if name == "namespace" {
+                        namespace_key = match start.try_get_attribute("key")? {
+                            Some(key) => key.value.parse().unwrap_or(0),
+                            None => 0,
+                        };
+                    } else if name == "revision" {
+                        revision = Revision::default();
+                    }
+                    elements.push(name);
+                    text.clear();
+                }
+                // Elements without content, e.g. deleted comments or the namespace without name.
+                Event::Empty(_) => {}
+                Event::Text(content) => text += &content.xml10_content(),
+                Event::CData(content) => text += &content.xml10_content(),
+                Event::GeneralRef(reference) => match reference.resolve_char_ref()? {
+                    Some(c) => text.push(c),
+                    None => match resolve_predefined_entity(&reference) {
+                        Some(resolved) => text += resolved,
+                        None => return Err(MediaWikiError::UnknownEntity(reference.to_string())),
+                    },
+                },
+                Event::End(_) => {
+                    let element = elements.pop();
+                    let parent = elements.last().map(String::as_str);
+                    match (parent, element.as_deref()) {
+                        (Some("namespaces"), Some("namespace")) => {
+                            self.namespaces.push((namespace_key, text.clone()))
+                        }
+                        (Some("page"), Some("title")) => title = text.clone(),
+                        (Some("revision"), Some("id")) => {
+                            revision.id = text.trim().parse().unwrap_or(0)
+                        }
+                        (Some("revision"), Some("timestamp")) => revision.timestamp = text.clone(),
+                        (Some("contributor"), Some("username"))
+                        | (Some("contributor"), Some("ip")) => {
+                            revision.contributor = Some(text.clone())
+                        }
+                        (Some("revision"), Some("comment")) => {
+                            revision.comment = Some(text.clone())
+                        }
+                        (Some("revision"), Some("text")) => revision.text = text.clone(),
+                        (Some("page"), Some("revision")) => {
+                            let revision = std::mem::take(&mut revision);
+                            self.add_revision(&title, revision)?;
+                        }
+                        _ => {}
+                    }
+                    text.clear();
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+            buffer.clear();
+        }
+        Ok(())
+    }
+
+    /// Converts a revision and writes it to a blob.
+    fn add_revision(&mut self, title: &str, revision: Revision) -> Result<(), MediaWikiError> {
+        let time = parse_timestamp(&revision.timestamp)
+            .ok_or_else(|| MediaWikiError::InvalidTimestamp(revision.timestamp.clone()))?;
+        let path = self.page_path(title);
+        let markdown = to_markdown(&revision.text, &|target| self.link(target));
+        let blob = self.repo.write_blob(markdown.as_bytes())?;
+        self.edits.push(Edit {
+            id: revision.id,
+            time,
+            title: title.to_string(),
+            // The contributor of old revisions can be hidden.
+            contributor: revision
+                .contributor
+                .filter(|contributor| !contributor.trim().is_empty())
+                .unwrap_or_else(|| "Unknown".to_string()),
+            comment: revision.comment,
+            path,
+            blob,
+        });
+        Ok(())
+    }
+
+    /// Splits a title into its namespace key and the title within the namespace.
+    fn split_namespace<'t>(&self, title: &'t str) -> (i64, Option<&str>, &'t str) {
+        if let Some(index) = title.find(':') {
+            let prefix = normalize_title(&title[..index]).to_lowercase();
+            let namespace = self
+                .namespaces
+                .iter()
+                .find(|(_, name)| !name.is_empty() && name.to_lowercase() == prefix);
+            if let Some(&(key, ref name)) = namespace {
+                return (key, Some(name), &title[index + 1..]);
+            }
+            if prefix == "image" {
+                return (FILE_NAMESPACE, Some("File"), &title[index + 1..]);
+            }
+        }
+        (0, None, title)
+    }
+
+    /// Returns the directory of a title and the segments of the title within the namespace.
+    fn title_segments(&self, title: &str) -> (i64, Path) {
+        let (key, namespace, title) = self.split_namespace(title);
+        let mut path = self.prefix.clone();
+        if let Some(namespace) = namespace {
+            path.push(normalize_title(namespace).replace(' ', "_"));
+        }
+        // MediaWiki forbids `.` and `..` segments in titles; they are dropped just in case.
+        let title = capitalize(&normalize_title(title));
+        for segment in title.split('/') {
+            if segment != "." && segment != ".." {
+                path.push(segment.replace(' ', "_"));
+            }
+        }
+        (key, path)
+    }
+
+    fn page_path(&self, title: &str) -> Path {
+        let (_, path) = self.title_segments(title);
+        Path::from(format!("{}.md", path))
+    }
+
+    fn link(&self, title: &str) -> Link {
+        let (key, path) = self.title_segments(title);
+        if key == FILE_NAMESPACE {
+            Link::File(
+                PathStringBuilder::new(path)
+                    .root(true)
+                    .build_percent_encode(),
+            )
+        } else {
+            let path = Path::from(format!("{}.md", path));
+            Link::Page(
+                PathStringBuilder::new(path)
+                    .root(true)
+                    .build_percent_encode(),
+            )
+        }
+    }
+}
+
+/// Collapses underscores and whitespace into single spaces like MediaWiki does.
+fn normalize_title(title: &str) -> String {
+    title
+        .replace('_', " ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// MediaWiki capitalizes the first letter of titles.
+fn capitalize(title: &str) -> String {
+    let mut chars = title.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Parses a MediaWiki timestamp (`2001-01-15T13:15:00Z`) into seconds since the epoch.
+fn parse_timestamp(timestamp: &str) -> Option<i64> {
+    let captures = TIMESTAMP.captures(timestamp.trim())?;
+    let number = |index: usize| captures[index].parse::<i64>().unwrap();
+    let (year, month, day) = (number(1), number(2), number(3));
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Days since the epoch of the proleptic Gregorian calendar, see
+    // http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    Some(days * 86400 + number(4) * 3600 + number(5) * 60 + number(6))
+}
+
+#[derive(Debug)]
+pub enum MediaWikiError {
+    Xml(quick_xml::Error),
+    Git(GitError),
+    InvalidTimestamp(String),
+    UnknownEntity(String),
+}
+impl std::error::Error for MediaWikiError {}
+impl fmt::Display for MediaWikiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MediaWikiError::Xml(ref err) => write!(f, "XML error: {}", err),
+            MediaWikiError::Git(ref err) => write!(f, "Git error: {}", err),
+            MediaWikiError::InvalidTimestamp(ref timestamp) => {
+                write!(f, "Invalid timestamp: {}", timestamp)
+            }
+            MediaWikiError::UnknownEntity(ref entity) => write!(f, "Unknown entity: &{};", entity),
+        }
+    }
+}
+impl From<quick_xml::Error> for MediaWikiError {
+    fn from(err: quick_xml::Error) -> Self {
+        MediaWikiError::Xml(err)
+    }
+}
+impl From<quick_xml::events::attributes::AttrError> for MediaWikiError {
+    fn from(err: quick_xml::events::attributes::AttrError) -> Self {
+        MediaWikiError::Xml(err.into())
+    }
+}
+impl From<quick_xml::escape::EscapeError> for MediaWikiError {
+    fn from(err: quick_xml::escape::EscapeError) -> Self {
+        MediaWikiError::Xml(err.into())
+    }
+}
+impl From<GitError> for MediaWikiError {
+    fn from(err: GitError) -> Self {
+        MediaWikiError::Git(err)
+    }
+}
+impl From<git2::Error> for MediaWikiError {
+    fn from(err: git2::Error) -> Self {
+        MediaWikiError::Git(err.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use git2::Repository;
+    use tempdir::TempDir;
+
+    use crate::mediawiki::{parse_timestamp, MediaWikiImporter};
+    use crate::{GitRepository, Path};
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_timestamp("2001-01-15T13:15:00Z"), Some(979_564_500));
+        assert_eq!(parse_timestamp("2024-02-29T23:59:59Z"), Some(1_709_251_199));
+        assert_eq!(parse_timestamp("2024-13-01T00:00:00Z"), None);
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+
+    #[test]
+    fn import_dump() {
+        let tmp = TempDir::new("smeagol").unwrap();
+        let repo = GitRepository::new(tmp.path()).unwrap();
+        let dump = include_bytes!("../fixtures/mediawiki.xml");
+        let commits = MediaWikiImporter::new(&repo, Path::from("wiki".to_string()))
+            .import(&dump[..])
+            .unwrap();
+        // The null edit of the main page is skipped.
+        assert_eq!(commits, 4);
+
+        let content = |path: &str| {
+            String::from_utf8(
+                repo.item(Path::from(path.to_string()))
+                    .unwrap()
+                    .content()
+                    .unwrap(),
+            )
+            .unwrap()
+        };
+        assert_eq!(
+            content("wiki/Main_Page.md"),
+            "# Welcome\n\nSee [the guide](</wiki/Help/Getting_started.md#first-steps>) & \
+             [Sandbox](</wiki/Sandbox.md>).\n\n- one\n- two\n"
+        );
+        assert_eq!(
+            content("wiki/Help/Getting_started.md"),
+            "## First steps\n\n![Logo](</wiki/File/Logo.png>)\n"
+        );
+        assert_eq!(content("wiki/Talk/Main_Page.md"), "> I like it.\n");
+
+        // Commits are ordered by time across pages and keep author, time and summary.
+        let git = Repository::open_bare(tmp.path()).unwrap();
+        let mut revwalk = git.revwalk().unwrap();
+        revwalk.push_head().unwrap();
+        let commits = revwalk
+            .map(|oid| git.find_commit(oid.unwrap()).unwrap())
+            .map(|commit| {
+                (
+                    commit.author().name().unwrap().to_string(),
+                    commit.author().when().seconds(),
+                    commit.summary().unwrap().to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            commits,
+            vec![
+                (
+                    "Alice".to_string(),
+                    979_650_000,
+                    "Link the guide".to_string()
+                ),
+                ("192.0.2.1".to_string(), 979_646_400, "Comment".to_string()),
+                (
+                    "Bob".to_string(),
+                    979_570_800,
+                    "Edit Help:Getting started".to_string()
+                ),
+                (
+                    "Alice".to_string(),
+                    979_564_500,
+                    "Create the main page".to_string()
+                ),
+                (
+                    "smeagol".to_string(),
+                    commits[4].1,
+                    "Root commit".to_string()
+                ),
+            ]
+        );
+        let head = git.head().unwrap().peel_to_commit().unwrap();
+        assert!(head
+            .message()
+            .unwrap()
+            .ends_with("MediaWiki-Page: Main Page\nMediaWiki-Revision: 4"));
+    }
+}
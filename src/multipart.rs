@@ -0,0 +1,332 @@
+//! Incremental parsing of `multipart/form-data` bodies.
+//!
+//! `warp::multipart` reads the whole body into memory. Uploads can be large, so the body is
+//! parsed chunk by chunk instead: the content of file parts is written into `BlobStream`s as it
+//! arrives, other fields are kept in memory.
+
+use std::path::PathBuf;
+
+use lazy_static::lazy_static;
+
+use regex::Regex;
+
+use crate::git::{BlobStream, GitError};
+
+/// Maximum size of the headers of a part.
+const MAX_HEADERS_SIZE: usize = 8 * 1024;
+
+lazy_static! {
+    static ref BOUNDARY: Regex =
+        Regex::new(r#"(?i)^multipart/form-data\s*;.*\bboundary=(?:"([^"]+)"|([^;\s]+))"#).unwrap();
+    static ref NAME: Regex = Regex::new(r#"(?i)\bname=(?:"([^"]*)"|([^;\s]*))"#).unwrap();
+    static ref FILENAME: Regex = Regex::new(r#"(?i)\bfilename=(?:"([^"]*)"|([^;\s]*))"#).unwrap();
+}
+
+/// Returns the boundary of a `multipart/form-data` content type.
+pub fn boundary(content_type: &str) -> Option<String> {
+    let captures = BOUNDARY.captures(content_type)?;
+    Some(
+        captures
+            .get(1)
+            .or_else(|| captures.get(2))?
+            .as_str()
+            .to_string(),
+    )
+}
+
+/// What the parser found in a chunk of the body.
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    /// The start of a part. Its content follows as `Data`.
+    Part {
+        name: String,
+        filename: Option<String>,
+    },
+    Data(Vec<u8>),
+}
+
+enum State {
+    /// Before the first boundary.
+    Preamble,
+    Headers,
+    Body,
+    /// After the last boundary.
+    Epilogue,
+}
+
+/// Splits a body into parts without holding more than a chunk of it at once.
+pub struct Parser {
+    /// `\r\n--` and the boundary.
+    delimiter: Vec<u8>,
+    /// Received data that was not handled yet because it might be the start of a delimiter.
+    buffer: Vec<u8>,
+    state: State,
+}
+impl Parser {
+    pub fn new(boundary: &str) -> Parser {
+        Parser {
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            // The first delimiter may start the body, without a line break before it.
+            buffer: b"\r\n".to_vec(),
+            state: State::Preamble,
+        }
+    }
+
+    /// Parses the next chunk of the body. Returns `None` if the body is invalid.
+    pub fn feed(&mut self, chunk: &[u8]) -> Option<Vec<Event>> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = vec![];
+        loop {
+            match self.state {
+                State::Preamble | State::Body => {
+                    let body = matches!(self.state, State::Body);
+                    match find(&self.buffer, &self.delimiter) {
+                        // The line break after the delimiter (or `--` after the last one) has to
+                        // be received as well.
+                        Some(start) if self.buffer.len() >= start + self.delimiter.len() + 2 => {
+                            let end = start + self.delimiter.len();
+                            if body && start > 0 {
+                                events.push(Event::Data(self.buffer[..start].to_vec()));
+                            }
+                            self.state = match &self.buffer[end..end + 2] {
+                                b"\r\n" => State::Headers,
+                                b"--" => State::Epilogue,
+                                _ => return None,
+                            };
+                            self.buffer.drain(..end + 2);
+                        }
+                        Some(_) => return Some(events),
+                        None => {
+                            // The end of the buffer may be the start of a delimiter.
+                            let keep = self.delimiter.len() - 1;
+                            if self.buffer.len() > keep {
+                                let end = self.buffer.len() - keep;
+                                if body {
+                                    events.push(Event::Data(self.buffer[..end].to_vec()));
+                                }
+                                self.buffer.drain(..end);
+                            }
+                            return Some(events);
+                        }
+                    }
+                }
+                State::Headers => match find(&self.buffer, b"\r\n\r\n") {
+                    Some(end) => {
+                        let headers = String::from_utf8_lossy(&self.buffer[..end]).into_owned();
+                        events.push(part(&headers)?);
+                        self.buffer.drain(..end + 4);
+                        self.state = State::Body;
+                    }
+                    None if self.buffer.len() > MAX_HEADERS_SIZE => return None,
+                    None => return Some(events),
+                },
+                State::Epilogue => {
+                    self.buffer.clear();
+                    return Some(events);
+                }
+            }
+        }
+    }
+
+    /// Whether the last boundary was found.
+    pub fn is_finished(&self) -> bool {
+        matches!(self.state, State::Epilogue)
+    }
+}
+
+/// Returns the start of a part from its headers.
+fn part(headers: &str) -> Option<Event> {
+    let disposition = headers.split("\r\n").find(|line| {
+        line.to_ascii_lowercase()
+            .starts_with("content-disposition:")
+    })?;
+    let value = |regex: &Regex| {
+        regex.captures(disposition).map(|captures| {
+            captures
+                .get(1)
+                .or_else(|| captures.get(2))
+                .map_or("", |value| value.as_str())
+                .to_string()
+        })
+    };
+    Some(Event::Part {
+        name: value(&NAME)?,
+        filename: value(&FILENAME),
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// The content of a form field.
+pub enum Value {
+    Text(Vec<u8>),
+    File(BlobStream),
+}
+
+/// A field of a form.
+pub struct Field {
+    pub name: String,
+    /// Name of the file sent in this field.
+    pub filename: Option<String>,
+    pub value: Value,
+}
+
+/// Reads a form from the chunks of its body. File parts are written to `BlobStream`s in the
+/// repository at `dir`.
+pub struct FormReader {
+    parser: Parser,
+    dir: PathBuf,
+    fields: Vec<Field>,
+    invalid: bool,
+}
+impl FormReader {
+    pub fn new(boundary: &str, dir: PathBuf) -> FormReader {
+        FormReader {
+            parser: Parser::new(boundary),
+            dir,
+            fields: vec![],
+            invalid: false,
+        }
+    }
+
+    /// Handles the next chunk of the body. Writing file parts blocks.
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<(), GitError> {
+        if self.invalid {
+            return Ok(());
+        }
+        let events = match self.parser.feed(chunk) {
+            Some(events) => events,
+            None => {
+                self.invalid = true;
+                return Ok(());
+            }
+        };
+        for event in events {
+            match event {
+                Event::Part { name, filename } => {
+                    let value = if filename.is_some() {
+                        Value::File(BlobStream::new(&self.dir)?)
+                    } else {
+                        Value::Text(vec![])
+                    };
+                    self.fields.push(Field {
+                        name,
+                        filename,
+                        value,
+                    });
+                }
+                // Data always follows the start of a part.
+                Event::Data(data) => match self.fields.last_mut().unwrap().value {
+                    Value::Text(ref mut text) => text.extend_from_slice(&data),
+                    Value::File(ref mut blob) => blob.write(&data)?,
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the fields of the form, or `None` if the body is not a valid form.
+    pub fn finish(self) -> Option<Vec<Field>> {
+        if self.invalid || !self.parser.is_finished() {
+            None
+        } else {
+            Some(self.fields)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use crate::multipart::{boundary, Event, FormReader, Parser, Value};
+
+    const BODY: &str = "preamble\r\n--XyZ\r\n\
+                        Content-Disposition: form-data; name=\"message\"\r\n\r\n\
+                        Hello\r\n--XyZ\r\n\
+                        Content-Disposition: form-data; name=\"files\"; filename=\"a.txt\"\r\n\
+                        Content-Type: text/plain\r\n\r\n\
+                        line\r\n--XyQ not a delimiter\r\n\
+                        --XyZ--\r\nepilogue";
+
+    #[test]
+    fn parse_in_chunks() {
+        assert_eq!(
+            boundary("multipart/form-data; boundary=XyZ"),
+            Some("XyZ".to_string())
+        );
+        assert_eq!(
+            boundary("Multipart/Form-Data; charset=utf-8; boundary=\"a b\""),
+            Some("a b".to_string())
+        );
+        assert_eq!(boundary("text/plain; boundary=XyZ"), None);
+
+        // The result does not depend on how the body is split.
+        for size in 1..BODY.len() {
+            let mut parser = Parser::new("XyZ");
+            let mut events = vec![];
+            for chunk in BODY.as_bytes().chunks(size) {
+                events.extend(parser.feed(chunk).unwrap());
+            }
+            assert!(parser.is_finished());
+
+            let mut merged: Vec<Event> = vec![];
+            for event in events {
+                match (merged.last_mut(), event) {
+                    (Some(Event::Data(data)), Event::Data(more)) => data.extend(more),
+                    (_, event) => merged.push(event),
+                }
+            }
+            assert_eq!(
+                merged,
+                vec![
+                    Event::Part {
+                        name: "message".to_string(),
+                        filename: None
+                    },
+                    Event::Data(b"Hello".to_vec()),
+                    Event::Part {
+                        name: "files".to_string(),
+                        filename: Some("a.txt".to_string())
+                    },
+                    Event::Data(b"line\r\n--XyQ not a delimiter".to_vec()),
+                ]
+            );
+        }
+
+        let mut parser = Parser::new("XyZ");
+        assert!(parser
+            .feed(b"--XyZ\r\nContent-Type: text/plain\r\n\r\n")
+            .is_none());
+    }
+
+    #[test]
+    fn read_form() {
+        let tmp = TempDir::new("smeagol").unwrap();
+        let mut reader = FormReader::new("XyZ", tmp.path().to_path_buf());
+        for chunk in BODY.as_bytes().chunks(7) {
+            reader.feed(chunk).unwrap();
+        }
+        let fields = reader.finish().unwrap();
+        assert_eq!(fields.len(), 2);
+        match fields[0].value {
+            Value::Text(ref text) => assert_eq!(text, b"Hello"),
+            Value::File(_) => panic!(),
+        }
+        match fields[1].value {
+            Value::File(ref blob) => {
+                assert_eq!(blob.content().unwrap(), b"line\r\n--XyQ not a delimiter")
+            }
+            Value::Text(_) => panic!(),
+        }
+
+        // Truncated bodies are invalid.
+        let mut reader = FormReader::new("XyZ", tmp.path().to_path_buf());
+        reader.feed(&BODY.as_bytes()[..40]).unwrap();
+        assert!(reader.finish().is_none());
+    }
+}
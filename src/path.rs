@@ -17,7 +17,7 @@ const EXTENSION_SEPARATOR: u8 = '.' as u8;
 // 3. Some conversions using From/Into are lossy
 //
 // I'd like to redesign it but I just don't think it's worth it at this point.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Path {
     content: Vec<u8>,
 }
@@ -124,6 +124,30 @@ impl Path {
         }
     }
 
+    /// Resolves a reference to another path relative to this path (treated as a directory).
+    ///
+    /// References starting with `/` are resolved from the root. `.` and `..` segments are
+    /// supported. Returns `None` if the reference points outside of the root.
+    pub fn resolve(&self, reference: &[u8]) -> Option<Path> {
+        let mut segments = if reference.first() == Some(&PATH_SEPARATOR) {
+            vec![]
+        } else {
+            self.segments()
+                .filter(|segment| !segment.is_empty())
+                .collect::<Vec<_>>()
+        };
+        for segment in reference.split(|b| *b == PATH_SEPARATOR) {
+            match segment {
+                b"" | b"." => {}
+                b".." => {
+                    segments.pop()?;
+                }
+                segment => segments.push(segment),
+            }
+        }
+        Some(Path::from(segments.join(&PATH_SEPARATOR)))
+    }
+
     pub fn segments<'a>(&'a self) -> impl Iterator<Item = &[u8]> + 'a {
         self.content.split(|b| *b == PATH_SEPARATOR)
     }
@@ -310,6 +334,25 @@ mod tests {
         assert_eq!(path.filename(), Some(Path::from("def".to_string())));
     }
 
+    #[test]
+    fn resolve() {
+        let path = Path::from("a/b".to_string());
+        assert_eq!(
+            path.resolve(b"c.md"),
+            Some(Path::from("a/b/c.md".to_string()))
+        );
+        assert_eq!(
+            path.resolve(b"../c/./d.md"),
+            Some(Path::from("a/c/d.md".to_string()))
+        );
+        assert_eq!(path.resolve(b"/c.md"), Some(Path::from("c.md".to_string())));
+        assert_eq!(path.resolve(b"../../.."), None);
+        assert_eq!(
+            Path::new().resolve(b"c.md"),
+            Some(Path::from("c.md".to_string()))
+        );
+    }
+
     #[test]
     fn extension() {
         let path = Path::from("".to_string());
@@ -0,0 +1,272 @@
+//! Sections of markdown pages.
+//!
+//! A section starts at an ATX heading (`## Title`) and ends at the next heading of the same or a
+//! higher level, so it contains its subsections. Sections are numbered from 1 in document order.
+//! Headings in fenced code blocks and in the front matter are skipped.
+//!
+//! To link the headings of a rendered page to their sections, the headings of the page source are
+//! marked before rendering (`mark_headings`) and the marks are replaced by links afterwards
+//! (`link_headings`). Headings of included pages are therefore never linked. The marks consist of
+//! private use characters, which are dropped from heading anchors. Lines that look like headings
+//! but are not rendered as headings (in HTML blocks, for example) are marked as well, so those
+//! marks are removed from the parsed page (`strip_marks`).
+
+use comrak::nodes::{AstNode, NodeValue};
+use lazy_static::lazy_static;
+
+use regex::{bytes, Captures, Regex};
+
+use crate::directive::Fence;
+use crate::filetype::split_front_matter;
+
+lazy_static! {
+    static ref HEADING: Regex =
+        Regex::new(r"^(?P<level>#{1,6})(?:\s+(?P<text>.*?))?\s*#*\s*$").unwrap();
+    static ref MARK: Regex = Regex::new(concat!(
+        "\u{F0000}(?P<index>[\u{F0010}-\u{F0019}]+)\u{F0001}",
+        "(?P<text>[^\u{F0000}]*?)</h(?P<level>[1-6])>"
+    ))
+    .unwrap();
+    static ref MARK_BYTES: bytes::Regex =
+        bytes::Regex::new("\u{F0000}[\u{F0010}-\u{F0019}]+\u{F0001}").unwrap();
+}
+
+const MARK_START: char = '\u{F0000}';
+const MARK_END: char = '\u{F0001}';
+/// Digits of the section index are encoded starting at this character.
+const MARK_DIGITS: u32 = 0xF0010;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    /// Number of the section, starting at 1.
+    pub index: usize,
+    pub level: usize,
+    pub title: String,
+    /// Byte offset of the heading in the page.
+    pub start: usize,
+    /// Byte offset after the last line of the section.
+    pub end: usize,
+}
+
+/// Returns all sections of a markdown page in document order.
+pub fn sections(source: &str) -> Vec<Section> {
+    let body = split_front_matter(source).1;
+    let mut offset = source.len() - body.len();
+
+    let mut fence = Fence::default();
+    let mut sections: Vec<Section> = vec![];
+    for line in body.split_inclusive('\n') {
+        let heading = if fence.update(line) {
+            None
+        } else {
+            HEADING.captures(line.trim_end())
+        };
+        if let Some(heading) = heading {
+            let level = heading["level"].len();
+            // The new heading ends all open sections of the same or a lower level.
+            for section in sections.iter_mut() {
+                if section.end == source.len() && section.level >= level {
+                    section.end = offset;
+                }
+            }
+            sections.push(Section {
+                index: sections.len() + 1,
+                level,
+                title: heading
+                    .name("text")
+                    .map_or("", |text| text.as_str())
+                    .to_string(),
+                start: offset,
+                end: source.len(),
+            });
+        }
+        offset += line.len();
+    }
+    sections
+}
+
+/// Returns the section with the given number.
+pub fn section(source: &str, index: usize) -> Option<Section> {
+    sections(source)
+        .into_iter()
+        .find(|section| section.index == index)
+}
+
+/// Replaces `section` of `source` with `replacement`. The section has to be taken from
+/// `sections(source)`.
+pub fn splice(source: &str, section: &Section, replacement: &str) -> String {
+    let mut spliced = String::with_capacity(source.len() + replacement.len());
+    spliced.push_str(&source[..section.start]);
+    spliced.push_str(replacement);
+    // The following heading has to start on a new line.
+    if section.end < source.len() && !replacement.is_empty() && !replacement.ends_with('\n') {
+        spliced.push('\n');
+    }
+    spliced.push_str(&source[section.end..]);
+    spliced
+}
+
+/// Marks the headings of a page with their section number (see `link_headings`).
+pub fn mark_headings(source: &str) -> String {
+    let mut marked = source.to_string();
+    for section in sections(source).iter().rev() {
+        // The text of the heading starts after the `#`s and the following whitespace.
+        let line = source[section.start..].lines().next().unwrap_or("");
+        let text = line[section.level..].len() - line[section.level..].trim_start().len();
+        let mut mark = MARK_START.to_string();
+        for digit in section.index.to_string().chars() {
+            // Digits are always valid and the offset stays within the private use area.
+            let digit = std::char::from_u32(MARK_DIGITS + digit.to_digit(10).unwrap()).unwrap();
+            mark.push(digit);
+        }
+        mark.push(MARK_END);
+        let position = section.start + section.level + text;
+        // Headings without text would not be rendered as headings with a mark.
+        if position < section.start + line.trim_end().len() {
+            marked.insert_str(position, &mark);
+        }
+    }
+    marked
+}
+
+/// Removes the marks of `mark_headings` from a parsed page, except those in headings.
+pub fn strip_marks<'a>(root: &'a AstNode<'a>) {
+    for node in root.descendants() {
+        let in_heading = node
+            .ancestors()
+            .any(|ancestor| matches!(ancestor.data.borrow().value, NodeValue::Heading(_)));
+        if in_heading {
+            continue;
+        }
+        let mut data = node.data.borrow_mut();
+        let literal = match data.value {
+            NodeValue::Text(ref mut literal)
+            | NodeValue::Code(ref mut literal)
+            | NodeValue::HtmlInline(ref mut literal) => literal,
+            NodeValue::HtmlBlock(ref mut block) => &mut block.literal,
+            NodeValue::CodeBlock(ref mut block) => &mut block.literal,
+            _ => continue,
+        };
+        if MARK_BYTES.is_match(literal) {
+            *literal = MARK_BYTES.replace_all(literal, &b""[..]).into_owned();
+        }
+    }
+}
+
+/// Replaces the marks in rendered HTML with links to edit the sections.
+pub fn link_headings(html: &str) -> String {
+    MARK.replace_all(html, |captures: &Captures| {
+        let index = captures["index"]
+            .chars()
+            .map(|digit| std::char::from_digit(digit as u32 - MARK_DIGITS, 10).unwrap())
+            .collect::<String>();
+        format!(
+            "{} <a class=\"edit-section\" href=\"?edit&amp;section={}\">edit</a></h{}>",
+            &captures["text"], index, &captures["level"]
+        )
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sections::{link_headings, mark_headings, section, sections, splice, strip_marks};
+
+    const PAGE: &str = "---\n# not a heading\n---\nIntro\n# One\n\nText\n## Two ##\n\
+                        ```\n# Code\n```\n### Three\n# Four\nEnd";
+
+    #[test]
+    fn find_sections() {
+        let found = sections(PAGE)
+            .into_iter()
+            .map(|section| {
+                (
+                    section.index,
+                    section.level,
+                    section.title.clone(),
+                    &PAGE[section.start..section.end],
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    1,
+                    1,
+                    "One".to_string(),
+                    "# One\n\nText\n## Two ##\n```\n# Code\n```\n### Three\n"
+                ),
+                (
+                    2,
+                    2,
+                    "Two".to_string(),
+                    "## Two ##\n```\n# Code\n```\n### Three\n"
+                ),
+                (3, 3, "Three".to_string(), "### Three\n"),
+                (4, 1, "Four".to_string(), "# Four\nEnd"),
+            ]
+        );
+    }
+
+    #[test]
+    fn splice_sections() {
+        let two = section(PAGE, 2).unwrap();
+        assert_eq!(
+            splice(PAGE, &two, "## Changed"),
+            "---\n# not a heading\n---\nIntro\n# One\n\nText\n## Changed\n# Four\nEnd"
+        );
+        let four = section(PAGE, 4).unwrap();
+        assert!(splice(PAGE, &four, "").ends_with("### Three\n"));
+        assert!(section(PAGE, 5).is_none());
+    }
+
+    #[test]
+    fn mark_and_link_headings() {
+        let marked = mark_headings("# One\n#\n## Two\n");
+        // Marks do not change the structure of the page.
+        let levels = |source: &str| {
+            sections(source)
+                .iter()
+                .map(|section| section.level)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(levels(&marked), vec![1, 1, 2]);
+        // Rendering keeps the marks in the heading text.
+        let html = marked
+            .lines()
+            .filter(|line| line.len() > 1)
+            .map(|line| {
+                let level = line.find(' ').unwrap();
+                format!("<h{0}>{1}</h{0}>", level, &line[level + 1..])
+            })
+            .collect::<String>();
+        assert_eq!(
+            link_headings(&html),
+            "<h1>One <a class=\"edit-section\" href=\"?edit&amp;section=1\">edit</a></h1>\
+             <h2>Two <a class=\"edit-section\" href=\"?edit&amp;section=3\">edit</a></h2>"
+        );
+    }
+
+    #[test]
+    fn strip_marks_outside_headings() {
+        let source = "<div>\n# In HTML\n</div>\n\n# One\n\nText\n\n## Two\n";
+        let marked = mark_headings(source);
+        let arena = comrak::Arena::new();
+        let options = comrak::ComrakOptions {
+            unsafe_: true,
+            ..comrak::ComrakOptions::default()
+        };
+        let root = comrak::parse_document(&arena, &marked, &options);
+        strip_marks(root);
+        let mut html = vec![];
+        comrak::format_html(root, &options, &mut html).unwrap();
+        assert_eq!(
+            link_headings(&String::from_utf8(html).unwrap()),
+            "<div>\n# In HTML\n</div>\n\
+             <h1>One <a class=\"edit-section\" href=\"?edit&amp;section=2\">edit</a></h1>\n\
+             <p>Text</p>\n\
+             <h2>Two <a class=\"edit-section\" href=\"?edit&amp;section=3\">edit</a></h2>\n"
+        );
+    }
+}
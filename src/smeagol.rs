@@ -1,37 +1,79 @@
 use std::cmp::Ordering;
+use std::fs::File;
+use std::io::BufReader;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures::future::{self, Either};
+use futures::Async;
 
 use handlebars::Handlebars;
 
 use itertools::Itertools;
 
+use lazy_static::lazy_static;
+
 use log::{debug, error};
 
 use serde::{Deserialize, Serialize};
 
-use warp::http::Response;
-use warp::{Buf, Filter, Rejection, Reply};
+use warp::body::BodyStream;
+use warp::http::{header, HeaderMap, HeaderValue, Response};
+use warp::{Buf, Filter, Future, Rejection, Reply, Stream};
 
-use crate::git::GitError;
-use crate::warp_helper::ResponseBuilder;
+use crate::api::{self, ChangeData, ErrorData, ItemData, RevisionData};
+use crate::cache::{CacheStats, Caches, RenderKey};
+use crate::config::FiletypeRule;
+use crate::directive::{collect_files, Directives};
+use crate::export::Exporter;
+use crate::filetype::filetype_rule;
+use crate::git::{BlobStream, GitError, GitItem};
+use crate::import::{ImportOptions, ImportStatus, Importer};
+use crate::mediawiki::MediaWikiImporter;
+use crate::multipart::{self, Field, FormReader, Value};
+use crate::sections;
+use crate::statics::StaticFiles;
+use crate::tasks;
+use crate::thumbnail::{ImageSize, Thumbnailer};
+use crate::upload::{OverwritePolicy, UploadStatus, Uploader};
+use crate::warp_helper::{compress, negotiate, ContentType, ResponseBuilder, Validators};
 use crate::{Config, Filetype, GitRepository, Path, PathStringBuilder, SmeagolError};
 
+/// `Cache-Control` of responses that never change.
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+lazy_static! {
+    /// Identifies the running server. Rendered pages depend on the templates and the config,
+    /// which are only loaded at startup.
+    static ref INSTANCE: String = format!(
+        "{:x}",
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs())
+    );
+}
+
 pub struct Smeagol {
     handlebars: Arc<Handlebars>,
     config: Arc<Config>,
+    caches: Arc<Caches>,
+    statics: Arc<StaticFiles>,
 }
 impl Smeagol {
     /// Initializes the Smeagol instance.
     ///
-    /// This reads the config file and initializes the template engine.
+    /// This reads the config file, initializes the template engine and loads the static assets.
     pub fn new() -> Result<Smeagol, SmeagolError> {
         debug!("Initializing");
 
         let config_file = std::env::var("SMEAGOL_CONF").unwrap_or("Smeagol.toml".to_string());
 
+        let config = Config::load(&config_file)?;
         Ok(Smeagol {
             handlebars: Arc::new(Self::initialize_handlebars()?),
-            config: Arc::new(Config::load(&config_file)?),
+            caches: Arc::new(Caches::new(&config.cache)),
+            statics: Arc::new(StaticFiles::load("static/")?),
+            config: Arc::new(config),
         })
     }
     fn initialize_handlebars() -> Result<Handlebars, SmeagolError> {
@@ -49,13 +91,97 @@ impl Smeagol {
         Ok(())
     }
 
+    /// Exports all pages of the head (or the given revision) as static HTML files to `outdir`.
+    pub fn export(self, outdir: &str, revision: Option<&str>) -> Result<(), SmeagolError> {
+        let mut repo = GitRepository::new(&self.config.repo)?;
+        if let Some(revision) = revision {
+            repo.set_revision(revision)?;
+        }
+        Exporter::new(&repo, &self.config, &self.handlebars, outdir).export()?;
+
+        Ok(())
+    }
+
+    /// Imports a directory in a single commit and prints the added and modified files. Nothing is
+    /// written if `options.dry_run` is set.
+    pub fn import(self, options: &ImportOptions) -> Result<(), SmeagolError> {
+        let repo = GitRepository::new(&self.config.repo)?;
+        let importer = Importer::new(&repo, options);
+        let files = if options.dry_run {
+            importer.scan()?
+        } else {
+            match importer.import() {
+                Err(GitError::NoChange) => {
+                    println!("Nothing to import");
+                    return Ok(());
+                }
+                result => result?,
+            }
+        };
+
+        for file in &files {
+            match file.status {
+                ImportStatus::Added => println!("A {}", file.path),
+                ImportStatus::Modified => println!("M {}", file.path),
+                ImportStatus::Unchanged => {}
+            }
+        }
+        let changed = files
+            .iter()
+            .filter(|file| file.status != ImportStatus::Unchanged)
+            .count();
+        if options.dry_run {
+            println!("Would import {} of {} files", changed, files.len());
+        } else {
+            println!("Imported {} of {} files", changed, files.len());
+        }
+
+        Ok(())
+    }
+
+    /// Imports a MediaWiki XML dump with its whole history into `prefix`.
+    pub fn import_mediawiki(self, dump: &str, prefix: Path) -> Result<(), SmeagolError> {
+        let repo = GitRepository::new(&self.config.repo)?;
+        let dump = BufReader::new(File::open(dump)?);
+        let commits = MediaWikiImporter::new(&repo, prefix).import(dump)?;
+        println!("Imported {} revisions", commits);
+
+        Ok(())
+    }
+
     /// Collects the different routes and returns a single Filter.
     fn routes(&self) -> impl Filter<Extract = impl Reply, Error = warp::Rejection> + Clone {
+        let dynamic = self
+            .api()
+            .recover(self.handle_500_json())
+            .unify()
+            .or(self.edit().recover(self.handle_500_html()).unify())
+            .unify()
+            .or(self.toggle_task().recover(self.handle_500_json()).unify())
+            .unify()
+            .or(self.upload().recover(self.handle_500_html()).unify())
+            .unify()
+            .or(self.post().recover(self.handle_500_json()).unify())
+            .unify()
+            .or(self.list().recover(self.handle_500_html()).unify())
+            .unify()
+            .or(self.raw().recover(self.handle_500_html()).unify())
+            .unify()
+            .or(self.resize().recover(self.handle_500_html()).unify())
+            .unify()
+            .or(self.stats().recover(self.handle_500_json()).unify())
+            .unify()
+            .or(self.tasks().recover(self.handle_500_html()).unify())
+            .unify()
+            .or(self.get().recover(self.handle_500_html()).unify())
+            .unify();
+
         self.statics()
-            .or(self.edit().recover(self.handle_500_html()))
-            .or(self.post().recover(self.handle_500_json()))
-            .or(self.list().recover(self.handle_500_html()))
-            .or(self.get().recover(self.handle_500_html()))
+            .or(warp::header::optional::<String>("accept-encoding")
+                .and(dynamic)
+                .map(|accept_encoding: Option<String>, response| {
+                    compress(accept_encoding.as_deref(), response)
+                }))
             .with(warp::log::log("smeagol"))
     }
 
@@ -118,10 +244,217 @@ impl Smeagol {
     }
 
     /// Serves all files in `./static` under `/static`. Matches only existing files.
+    ///
+    /// Browsers may reuse them for an hour without asking again.
     fn statics(&self) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        let statics = self.statics.clone();
         warp::get2()
             .and(warp::filters::path::path("static"))
-            .and(warp::fs::dir("static/"))
+            .and(warp::path::tail())
+            .and(warp::header::headers_cloned())
+            .and_then(
+                move |tail: warp::filters::path::Tail,
+                      request: HeaderMap|
+                      -> Result<Response<Vec<u8>>, Rejection> {
+                    statics
+                        .response(tail.as_str(), &request)
+                        .ok_or_else(warp::reject::not_found)
+                },
+            )
+    }
+
+    /// Serves the JSON API (see `api`). Matches URLs under `/api/v1/`.
+    fn api(&self) -> impl Filter<Extract = (Response<Vec<u8>>,), Error = warp::Rejection> + Clone {
+        warp::path("api").and(warp::path("v1")).and(
+            self.api_get()
+                .or(self.api_put())
+                .unify()
+                .or(self.api_delete())
+                .unify()
+                .or(self.api_history())
+                .unify(),
+        )
+    }
+
+    /// Returns a file with its content or a directory with its entries.
+    fn api_get(
+        &self,
+    ) -> impl Filter<Extract = (Response<Vec<u8>>,), Error = warp::Rejection> + Clone {
+        warp::get2()
+            .and(api_path("files"))
+            .and(self.config())
+            .and_then(
+                |path: Path, config: Arc<Config>| -> Result<Response<Vec<u8>>, Rejection> {
+                    let repo = GitRepository::new(&config.repo)?;
+                    let item = repo.item(path)?;
+                    match ItemData::new(&item) {
+                        Ok(data) => Ok(ResponseBuilder::new().status(200).body_json(&data)?),
+                        Err(err) => api_error(err),
+                    }
+                },
+            )
+    }
+
+    /// Writes a file. Structured data files are validated like in `post`.
+    fn api_put(
+        &self,
+    ) -> impl Filter<Extract = (Response<Vec<u8>>,), Error = warp::Rejection> + Clone {
+        /// Query parameter matcher.
+        #[derive(Deserialize)]
+        struct QueryParameters {
+            message: Option<String>,
+            /// Blob id the change is based on, empty for new files.
+            revision: Option<String>,
+        }
+
+        /// Commits the streamed body after checking it. Blocks.
+        fn write(
+            path: Path,
+            query: QueryParameters,
+            config: &Config,
+            blob: BlobStream,
+        ) -> Result<Response<Vec<u8>>, Rejection> {
+            let repo = GitRepository::new(&config.repo)?;
+            let item = repo.item(path.clone())?;
+            let exists = item.exists()?;
+            // The file may have changed while the body was streamed.
+            if !is_at_revision(&item, query.revision.as_deref())? {
+                return api_conflict();
+            }
+
+            let filetype = Filetype::for_path(&path, config);
+            if filetype.is_data() {
+                if let Err(err) = filetype.validate(&blob.content()?) {
+                    let error = match err.location() {
+                        Some((line, column)) => {
+                            format!("Invalid file at line {}, column {}: {}", line, column, err)
+                        }
+                        None => format!("Invalid file: {}", err),
+                    };
+                    return Ok(ResponseBuilder::new()
+                        .status(400)
+                        .body_json(&ErrorData { error })?);
+                }
+            }
+
+            let message = query.message.unwrap_or_else(|| format!("Edited {}", path));
+            // The blob is only created once the content was accepted.
+            let changed = match item.edit_blob(blob.commit(&repo)?, &message) {
+                Ok(()) => true,
+                Err(GitError::NoChange) => false,
+                Err(err) => return api_error(err),
+            };
+            Ok(ResponseBuilder::new()
+                .status(if exists { 200 } else { 201 })
+                .body_json(&ChangeData::new(&item, changed)?)?)
+        }
+
+        warp::put2()
+            .or(warp::post2())
+            .unify()
+            .and(api_path("files"))
+            .and(warp::query::<QueryParameters>())
+            .and(self.config())
+            .and(warp::body::content_length_limit(
+                self.config.max_upload_size,
+            ))
+            .and(warp::body::stream())
+            .and_then(
+                |path: Path, query: QueryParameters, config: Arc<Config>, body: BodyStream| {
+                    // Conflicting changes are rejected before the body is read.
+                    let at_revision = GitRepository::new(&config.repo).and_then(|repo| {
+                        is_at_revision(&repo.item(path.clone())?, query.revision.as_deref())
+                    });
+                    match at_revision {
+                        Ok(true) => {}
+                        Ok(false) => return Either::A(future::result(api_conflict())),
+                        Err(err) => return Either::A(future::err(err.into())),
+                    }
+                    let dir = config.repo.clone();
+                    Either::B(
+                        stream_body(body, dir).and_then(move |blob| {
+                            blocking(move || write(path, query, &config, blob))
+                        }),
+                    )
+                },
+            )
+    }
+
+    /// Removes a file.
+    fn api_delete(
+        &self,
+    ) -> impl Filter<Extract = (Response<Vec<u8>>,), Error = warp::Rejection> + Clone {
+        /// Query parameter matcher.
+        #[derive(Deserialize)]
+        struct QueryParameters {
+            message: Option<String>,
+            /// Blob id the removal is based on.
+            revision: Option<String>,
+        }
+
+        warp::delete2()
+            .and(api_path("files"))
+            .and(warp::query::<QueryParameters>())
+            .and(self.config())
+            .and_then(
+                |path: Path,
+                 query: QueryParameters,
+                 config: Arc<Config>|
+                 -> Result<Response<Vec<u8>>, Rejection> {
+                    let repo = GitRepository::new(&config.repo)?;
+                    let item = repo.item(path.clone())?;
+                    // Directories are not removed to prevent accidentally removing many files.
+                    if item.exists()? && item.is_dir()? {
+                        return api_error(GitError::IsDir);
+                    }
+                    if !is_at_revision(&item, query.revision.as_deref())? {
+                        return api_conflict();
+                    }
+
+                    let message = query.message.unwrap_or_else(|| format!("Removed {}", path));
+                    if let Err(err) = item.remove(&message) {
+                        return api_error(err);
+                    }
+                    Ok(ResponseBuilder::new()
+                        .status(200)
+                        .body_json(&ChangeData::new(&item, true)?)?)
+                },
+            )
+    }
+
+    /// Returns the commits that changed a file or directory.
+    fn api_history(
+        &self,
+    ) -> impl Filter<Extract = (Response<Vec<u8>>,), Error = warp::Rejection> + Clone {
+        /// Query parameter matcher.
+        #[derive(Deserialize)]
+        struct QueryParameters {
+            limit: Option<usize>,
+        }
+
+        warp::get2()
+            .and(api_path("history"))
+            .and(warp::query::<QueryParameters>())
+            .and(self.config())
+            .and_then(
+                |path: Path,
+                 query: QueryParameters,
+                 config: Arc<Config>|
+                 -> Result<Response<Vec<u8>>, Rejection> {
+                    let repo = GitRepository::new(&config.repo)?;
+                    let item = repo.item(path)?;
+                    let history = item.history(query.limit.unwrap_or(api::HISTORY_LIMIT))?;
+                    if history.is_empty() {
+                        return api_error(GitError::NotFound);
+                    }
+                    Ok(ResponseBuilder::new().status(200).body_json(
+                        &history
+                            .into_iter()
+                            .map(RevisionData::from)
+                            .collect::<Vec<_>>(),
+                    )?)
+                },
+            )
     }
 
     /// Serves an file in the repository. Matches any URL.
@@ -132,11 +465,16 @@ impl Smeagol {
     /// It used the following steps to determine how the data is presented:
     ///
     /// 1. If a directory is selected a redirect to `config.index` is returned.
+    /// 1. If the file is audio or video it is embedded within a page using a native player.
     /// 1. If the file only contains valid UTF-8 and the filetype is not raw it is embedded within
     ///    a page for display. `Filetype.parse` is used.
     /// 1. If the filetype is raw and raw inline the file is shown as its own page.
     /// 1. The file is offered for download.
-    fn get(&self) -> impl Filter<Extract = impl Reply, Error = warp::Rejection> + Clone {
+    ///
+    /// The `Accept` header can request other representations: `text/markdown` or `text/plain`
+    /// return the source of text files (other files are served like `raw`) and
+    /// `application/json` returns the content with metadata like the API.
+    fn get(&self) -> impl Filter<Extract = (Response<Vec<u8>>,), Error = warp::Rejection> + Clone {
         /// Data for `get.html.hbs`.
         #[derive(Serialize)]
         struct TemplateGetData {
@@ -148,6 +486,8 @@ impl Smeagol {
             content: String,
             /// Whether the file content needs to be escaped.
             safe: bool,
+            /// Blob id of the page if its tasks can be checked on the page.
+            tasks_revision: Option<String>,
         }
         /// Data for `get_not_found.hbs`.
         #[derive(Serialize)]
@@ -165,63 +505,260 @@ impl Smeagol {
                     Path::from_percent_encoded(fullpath.as_str().to_string().as_bytes())
                 }),
             )
+            .and(warp::header::optional::<String>("accept"))
+            .and(warp::header::headers_cloned())
             .and(self.templates())
             .and(self.config())
+            .and(self.caches())
             .and_then(
                 |path: Path,
+                 accept: Option<String>,
+                 request: HeaderMap,
                  templates: Arc<Handlebars>,
-                 config: Arc<Config>|
+                 config: Arc<Config>,
+                 caches: Arc<Caches>|
                  -> Result<Response<Vec<u8>>, Rejection> {
-                    let repo = GitRepository::new(&config.repo)?;
+                    let repo = GitRepository::with_caches(&config.repo, caches.clone())?;
                     let item = repo.item(path.clone())?;
 
+                    let representation = negotiate(
+                        accept.as_deref(),
+                        &[
+                            "text/html",
+                            "text/markdown",
+                            "text/plain",
+                            "application/json",
+                        ],
+                    );
+                    if representation == "application/json" {
+                        return match ItemData::new(&item) {
+                            Ok(data) => Ok(ResponseBuilder::new().status(200).body_json(&data)?),
+                            Err(err) => api_error(err),
+                        };
+                    }
+
                     match item.content() {
                         Ok(content) => {
-                            let filetype = Filetype::from(&path);
+                            let filetype = Filetype::detect(&path, &content, &config);
+                            let rule = filetype_rule(&path, &config);
+                            let inline = rule.and_then(|rule| rule.inline);
+
+                            let last_change = item.last_change()?;
+                            let last_modified = Some(last_change.1);
+                            // Served files are identified by their blob id. Rendered pages also
+                            // depend on the templates and the config.
+                            let file_validators = Validators::strong(item.oid()?, last_modified);
+                            let page_validators = Validators::weak(
+                                format!("{}-{}", item.oid()?, *INSTANCE),
+                                last_modified,
+                            );
+
+                            if representation != "text/html" {
+                                if file_validators.is_fresh(&request) {
+                                    return Ok(
+                                        ResponseBuilder::new().not_modified(&file_validators)
+                                    );
+                                }
+                                let content_type = match filetype {
+                                    _ if std::str::from_utf8(&content).is_err() => None,
+                                    Filetype::Markdown if representation == "text/markdown" => {
+                                        Some(ContentType::Markdown)
+                                    }
+                                    _ => Some(ContentType::Plain),
+                                };
+                                return Ok(match content_type {
+                                    Some(content_type) => ResponseBuilder::new()
+                                        .header(header::CONTENT_TYPE, content_type)
+                                        .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+                                        .validators(&file_validators)
+                                        .body_range(&request, &file_validators, content),
+                                    None => raw_response(
+                                        &mut ResponseBuilder::new(),
+                                        &request,
+                                        &filetype,
+                                        rule,
+                                        &file_validators,
+                                        content,
+                                    ),
+                                });
+                            }
+                            if inline == Some(false) {
+                                if file_validators.is_fresh(&request) {
+                                    return Ok(
+                                        ResponseBuilder::new().not_modified(&file_validators)
+                                    );
+                                }
+                                return Ok(ResponseBuilder::new()
+                                    .filetype(&filetype, rule)
+                                    .validators(&file_validators)
+                                    .download()
+                                    .body_range(&request, &file_validators, content));
+                            }
+
+                            // File path has to have parent
+                            let parent_list_link = format!(
+                                "{}?list",
+                                PathStringBuilder::new(path.parent().unwrap())
+                                    .root(true)
+                                    .build_percent_encode()
+                            );
+
+                            if filetype.is_media() {
+                                if page_validators.is_fresh(&request) {
+                                    return Ok(
+                                        ResponseBuilder::new().not_modified(&page_validators)
+                                    );
+                                }
+                                // Media is embedded from its revision URL, which can be cached
+                                // forever.
+                                let raw_link = format!(
+                                    "{}?raw&revision={}",
+                                    PathStringBuilder::new(path.clone())
+                                        .root(true)
+                                        .build_percent_encode(),
+                                    last_change.0
+                                );
+                                return Ok(ResponseBuilder::new()
+                                    .validators(&page_validators)
+                                    .status(200)
+                                    .body_template(
+                                        &templates,
+                                        "get.html",
+                                        &TemplateGetData {
+                                            path: path.to_string(),
+                                            parent_list_link,
+                                            content: filetype.embed(&raw_link),
+                                            safe: true,
+                                            tasks_revision: None,
+                                        },
+                                    )?);
+                            }
+
+                            // External commands also render binary files.
+                            if let Filetype::Command(_) = filetype {
+                                if page_validators.is_fresh(&request) {
+                                    return Ok(
+                                        ResponseBuilder::new().not_modified(&page_validators)
+                                    );
+                                }
+                                return Ok(ResponseBuilder::new()
+                                    .validators(&page_validators)
+                                    .status(200)
+                                    .body_template(
+                                        &templates,
+                                        "get.html",
+                                        &TemplateGetData {
+                                            path: path.to_string(),
+                                            parent_list_link,
+                                            content: caches
+                                                .rendered(
+                                                    RenderKey::new(
+                                                        item.oid()?,
+                                                        None,
+                                                        &filetype,
+                                                        &config,
+                                                    ),
+                                                    || {
+                                                        filetype
+                                                            .parse_command(
+                                                                item.oid()?,
+                                                                &content,
+                                                                &config,
+                                                            )
+                                                            .map_err(SmeagolError::from)
+                                                    },
+                                                )?
+                                                .to_string(),
+                                            safe: true,
+                                            tasks_revision: None,
+                                        },
+                                    )?);
+                            }
+
                             // Possible: Get rid of clone?
                             let parsed_utf8 = String::from_utf8(content.clone());
 
                             // let binding not used because of additional checks
                             if !filetype.is_raw() && parsed_utf8.is_ok() {
-                                Ok(ResponseBuilder::new().status(200).body_template(
-                                    &templates,
-                                    "get.html",
-                                    &TemplateGetData {
-                                        path: path.to_string(),
-                                        // File path has to have parent
-                                        parent_list_link: format!(
-                                            "{}?list",
-                                            PathStringBuilder::new(path.parent().unwrap(),)
-                                                .root(true)
-                                                .build_percent_encode()
-                                        ),
-                                        content: filetype
-                                            .parse(
-                                                // parsing result checked above
-                                                &parsed_utf8.unwrap(),
-                                            )
-                                            .map_err(|err| SmeagolError::from(err))?,
-                                        safe: filetype.is_safe(),
-                                    },
-                                )?)
-                            } else {
-                                if filetype.is_raw() && filetype.is_raw_inline() {
-                                    Ok(ResponseBuilder::new()
-                                        .header(
-                                            warp::http::header::CONTENT_TYPE,
-                                            filetype.content_type(),
-                                        )
-                                        .status(200)
-                                        .body(content))
+                                // parsing result checked above
+                                let source = parsed_utf8.unwrap();
+                                // Directives are only supported in markdown pages. Their output
+                                // depends on other files and therefore on the head commit.
+                                let (has_directives, tasks_revision) = match filetype {
+                                    Filetype::Markdown => {
+                                        (source.contains("{{"), Some(item.oid()?.to_string()))
+                                    }
+                                    _ => (false, None),
+                                };
+                                let head = if has_directives {
+                                    Some(repo.head_id()?)
                                 } else {
-                                    Ok(ResponseBuilder::new()
-                                        .header(
-                                            warp::http::header::CONTENT_TYPE,
-                                            filetype.content_type(),
-                                        )
-                                        .status(200)
-                                        .body_download(content))
+                                    None
+                                };
+                                // Pages with directives change with every commit.
+                                let page_validators = match head {
+                                    Some(head) => Validators::weak(
+                                        format!("{}-{}-{}", item.oid()?, head, *INSTANCE),
+                                        None,
+                                    ),
+                                    None => page_validators,
+                                };
+                                if page_validators.is_fresh(&request) {
+                                    return Ok(
+                                        ResponseBuilder::new().not_modified(&page_validators)
+                                    );
+                                }
+                                let key = RenderKey::new(
+                                    item.oid()?,
+                                    head.map(|head| (head, path.clone())),
+                                    &filetype,
+                                    &config,
+                                );
+                                let html = caches.rendered(key, || {
+                                    // Headings are marked before includes are expanded so that
+                                    // only the sections of the page itself can be edited.
+                                    let source = match filetype {
+                                        Filetype::Markdown => sections::mark_headings(&source),
+                                        _ => source,
+                                    };
+                                    let source = if has_directives {
+                                        Directives::new(&repo, &config).expand(&path, &source)?
+                                    } else {
+                                        source
+                                    };
+                                    filetype.parse(&source, &config).map_err(SmeagolError::from)
+                                })?;
+
+                                Ok(ResponseBuilder::new()
+                                    .validators(&page_validators)
+                                    .status(200)
+                                    .body_template(
+                                        &templates,
+                                        "get.html",
+                                        &TemplateGetData {
+                                            path: path.to_string(),
+                                            parent_list_link,
+                                            content: sections::link_headings(&html),
+                                            safe: filetype.is_safe(),
+                                            tasks_revision,
+                                        },
+                                    )?)
+                            } else {
+                                if file_validators.is_fresh(&request) {
+                                    return Ok(
+                                        ResponseBuilder::new().not_modified(&file_validators)
+                                    );
                                 }
+                                let mut response = ResponseBuilder::new();
+                                response
+                                    .filetype(&filetype, rule)
+                                    .validators(&file_validators);
+                                if !(filetype.is_raw() && filetype.is_raw_inline())
+                                    && inline != Some(true)
+                                {
+                                    response.download();
+                                }
+                                Ok(response.body_range(&request, &file_validators, content))
                             }
                         }
                         Err(GitError::IsDir) => {
@@ -243,6 +780,379 @@ impl Smeagol {
                     }
                 },
             )
+            .map(|mut response: Response<Vec<u8>>| {
+                response
+                    .headers_mut()
+                    .append(header::VARY, HeaderValue::from_static("Accept"));
+                response
+            })
+    }
+
+    /// Serves the unmodified content of a file in the repository. Matches any URL with a `raw`
+    /// query parameter.
+    ///
+    /// This is the escape hatch for rendered filetypes. If the file does not exist a 404 page is
+    /// served; directories are listed instead.
+    ///
+    /// An optional `revision` query parameter serves the file as of an older commit. If it is a
+    /// full commit id the response never changes and may be cached forever.
+    fn raw(&self) -> impl Filter<Extract = (Response<Vec<u8>>,), Error = warp::Rejection> + Clone {
+        /// Query parameter matcher.
+        ///
+        /// The actual value of the query parameter does not matter and is never accessed. It only
+        /// matters whether it is given (`?raw`, `?raw=`, `?raw=abc`).
+        #[derive(Deserialize)]
+        struct QueryParameters {
+            // This field is never accessed but is required for the tag
+            #[allow(dead_code)]
+            raw: String,
+            revision: Option<String>,
+        }
+        /// Data for `get_not_found.hbs`.
+        #[derive(Serialize)]
+        struct TemplateGetNotFoundData {
+            /// Path of the served file.
+            path: String,
+            /// Whether the file can be created.
+            can_exist: bool,
+        }
+
+        warp::get2()
+            .and(
+                warp::path::full().map(|fullpath: warp::filters::path::FullPath| {
+                    Path::from_percent_encoded(fullpath.as_str().to_string().as_bytes())
+                }),
+            )
+            .and(warp::query::<QueryParameters>())
+            .and(warp::header::headers_cloned())
+            .and(self.templates())
+            .and(self.config())
+            .and(self.caches())
+            .and_then(
+                |path: Path,
+                 query: QueryParameters,
+                 request: HeaderMap,
+                 templates: Arc<Handlebars>,
+                 config: Arc<Config>,
+                 caches: Arc<Caches>|
+                 -> Result<Response<Vec<u8>>, Rejection> {
+                    let mut repo = GitRepository::with_caches(&config.repo, caches.clone())?;
+                    let mut immutable = false;
+                    if let Some(ref revision) = query.revision {
+                        match repo.set_revision(revision) {
+                            // Branch names and abbreviated ids can point to other commits later.
+                            Ok(()) => immutable = repo.commit_id()?.to_string() == *revision,
+                            Err(_) => return Ok(ResponseBuilder::new().status(404).body(vec![])),
+                        }
+                    }
+                    let item = repo.item(path.clone())?;
+
+                    match item.content() {
+                        Ok(content) => {
+                            let last_modified = Some(item.last_modified()?);
+                            let validators = Validators::strong(item.oid()?, last_modified);
+                            let mut response = ResponseBuilder::new();
+                            if immutable {
+                                response.header(header::CACHE_CONTROL, IMMUTABLE);
+                            }
+                            if validators.is_fresh(&request) {
+                                return Ok(response.not_modified(&validators));
+                            }
+                            let filetype = Filetype::detect(&path, &content, &config);
+                            let rule = filetype_rule(&path, &config);
+                            Ok(raw_response(
+                                &mut response,
+                                &request,
+                                &filetype,
+                                rule,
+                                &validators,
+                                content,
+                            ))
+                        }
+                        Err(GitError::IsDir) => {
+                            Ok(ResponseBuilder::new().redirect_query(path, "list"))
+                        }
+                        Err(GitError::NotFound) => {
+                            Ok(ResponseBuilder::new().status(404).body_template(
+                                &templates,
+                                "get_not_found.html",
+                                &TemplateGetNotFoundData {
+                                    path: path.to_string(),
+                                    can_exist: item.can_exist()?,
+                                },
+                            )?)
+                        }
+                        Err(err) => Err(err.into()),
+                    }
+                },
+            )
+    }
+
+    /// Serves a resized image. Matches any URL with a `w`, `h` or `thumb` query parameter that
+    /// points to an image.
+    ///
+    /// `w` and `h` limit the width and the height; the aspect ratio is kept. `thumb` fits the
+    /// image in a square of `config.images.thumbnail_size`. Images are never enlarged. Other
+    /// files are passed on to the next route.
+    fn resize(
+        &self,
+    ) -> impl Filter<Extract = (Response<Vec<u8>>,), Error = warp::Rejection> + Clone {
+        /// Query parameter matcher.
+        ///
+        /// The value of `thumb` does not matter and is never accessed.
+        #[derive(Deserialize)]
+        struct QueryParameters {
+            w: Option<u32>,
+            h: Option<u32>,
+            thumb: Option<String>,
+        }
+
+        warp::get2()
+            .and(
+                warp::path::full().map(|fullpath: warp::filters::path::FullPath| {
+                    Path::from_percent_encoded(fullpath.as_str().to_string().as_bytes())
+                }),
+            )
+            .and(warp::query::<QueryParameters>())
+            .and(warp::header::headers_cloned())
+            .and(self.config())
+            .and(self.caches())
+            .and_then(
+                |path: Path,
+                 query: QueryParameters,
+                 request: HeaderMap,
+                 config: Arc<Config>,
+                 caches: Arc<Caches>|
+                 -> Result<Response<Vec<u8>>, Rejection> {
+                    let size = if query.thumb.is_some() {
+                        ImageSize {
+                            width: Some(config.images.thumbnail_size),
+                            height: Some(config.images.thumbnail_size),
+                        }
+                    } else {
+                        ImageSize {
+                            width: query.w,
+                            height: query.h,
+                        }
+                    };
+                    if size.width.is_none() && size.height.is_none() {
+                        return Err(warp::reject::not_found());
+                    }
+
+                    let repo = GitRepository::with_caches(&config.repo, caches.clone())?;
+                    let item = repo.item(path.clone())?;
+
+                    let content = match item.content() {
+                        Ok(content) => content,
+                        // Directories and missing files are handled by `get`.
+                        Err(GitError::IsDir) | Err(GitError::NotFound) => {
+                            return Err(warp::reject::not_found())
+                        }
+                        Err(err) => return Err(err.into()),
+                    };
+                    let filetype = Filetype::detect(&path, &content, &config);
+                    if !filetype.is_image() {
+                        return Err(warp::reject::not_found());
+                    }
+
+                    // Resized images are identified by the blob id and the size.
+                    let clamped = size.clamp(config.images.max_size);
+                    let dimension = |dimension: Option<u32>| {
+                        dimension.map_or(String::new(), |dimension| dimension.to_string())
+                    };
+                    let validators = Validators::strong(
+                        format!(
+                            "{}-{}x{}",
+                            item.oid()?,
+                            dimension(clamped.width),
+                            dimension(clamped.height)
+                        ),
+                        Some(item.last_modified()?),
+                    );
+                    let mut response = ResponseBuilder::new();
+                    // The image at a path can change, so clients have to revalidate it.
+                    response.header(header::CACHE_CONTROL, "no-cache");
+                    if validators.is_fresh(&request) {
+                        return Ok(response.not_modified(&validators));
+                    }
+
+                    match Thumbnailer::new(&config.images)
+                        .resize(item.oid()?, &content, &filetype, size)
+                        .map_err(SmeagolError::from)?
+                    {
+                        Some(resized) => Ok(response
+                            .filetype(&Thumbnailer::output_filetype(&filetype), None)
+                            .validators(&validators)
+                            .status(200)
+                            .body(resized)),
+                        None => Ok(response
+                            .filetype(&filetype, filetype_rule(&path, &config))
+                            .validators(&validators)
+                            .status(200)
+                            .body(content)),
+                    }
+                },
+            )
+    }
+
+    /// Serves the hit and miss counts of the caches as JSON. Matches `/?stats`.
+    fn stats(
+        &self,
+    ) -> impl Filter<Extract = (Response<Vec<u8>>,), Error = warp::Rejection> + Clone {
+        /// Query parameter matcher.
+        #[derive(Deserialize)]
+        struct QueryParameters {
+            // This field is never accessed but is required for the tag
+            #[allow(dead_code)]
+            stats: String,
+        }
+        /// Data returned by the route.
+        #[derive(Serialize)]
+        struct StatsData {
+            rendered: CacheStats,
+            trees: CacheStats,
+            modified: CacheStats,
+        }
+
+        warp::get2()
+            .and(warp::path::end())
+            .and(warp::query::<QueryParameters>())
+            .and(self.caches())
+            .and_then(
+                |_: QueryParameters, caches: Arc<Caches>| -> Result<Response<Vec<u8>>, Rejection> {
+                    let (rendered, trees, modified) = caches.stats();
+                    Ok(ResponseBuilder::new().status(200).body_json(&StatsData {
+                        rendered,
+                        trees,
+                        modified,
+                    })?)
+                },
+            )
+    }
+
+    /// Serves a page listing the open tasks of all markdown pages. Matches the root URL with a
+    /// `tasks` query parameter.
+    ///
+    /// The optional `path` parameter limits the tasks to a directory or page, the optional
+    /// `assignee` parameter to tasks mentioning `@assignee`.
+    fn tasks(
+        &self,
+    ) -> impl Filter<Extract = (Response<Vec<u8>>,), Error = warp::Rejection> + Clone {
+        /// Query parameter matcher.
+        #[derive(Deserialize)]
+        struct QueryParameters {
+            // This field is never accessed but is required for the tag
+            #[allow(dead_code)]
+            tasks: String,
+            #[serde(default)]
+            path: String,
+            #[serde(default)]
+            assignee: String,
+        }
+        /// Data for `tasks.html.hbs`.
+        #[derive(Serialize)]
+        struct TemplateTasksData {
+            /// The path filter.
+            path: String,
+            /// The assignee filter.
+            assignee: String,
+            /// Pages with matching tasks, sorted by path.
+            pages: Vec<TemplateTasksPageData>,
+            /// Number of matching tasks.
+            count: usize,
+        }
+        #[derive(Serialize)]
+        struct TemplateTasksPageData {
+            path: String,
+            link: String,
+            /// Blob id of the page, required to check tasks.
+            revision: String,
+            tasks: Vec<TemplateTaskData>,
+        }
+        #[derive(Serialize)]
+        struct TemplateTaskData {
+            /// Index of the task on its page.
+            index: usize,
+            line: usize,
+            text: String,
+        }
+
+        warp::get2()
+            .and(warp::path::end())
+            .and(warp::query::<QueryParameters>())
+            .and(self.templates())
+            .and(self.config())
+            .and(self.caches())
+            .and_then(
+                |query: QueryParameters,
+                 templates: Arc<Handlebars>,
+                 config: Arc<Config>,
+                 caches: Arc<Caches>|
+                 -> Result<Response<Vec<u8>>, Rejection> {
+                    let repo = GitRepository::with_caches(&config.repo, caches)?;
+
+                    // An invalid path filter matches nothing.
+                    let mut files = vec![];
+                    if let Some(filter) = Path::new().resolve(query.path.as_bytes()) {
+                        let item = repo.item(filter)?;
+                        if item.exists()? {
+                            if item.is_dir()? {
+                                collect_files(&item, &mut files)?;
+                            } else {
+                                files.push(item);
+                            }
+                        }
+                    }
+
+                    let mut pages = vec![];
+                    for file in files {
+                        match Filetype::for_path(file.path(), &config) {
+                            Filetype::Markdown => {}
+                            _ => continue,
+                        }
+                        let source = match String::from_utf8(file.content()?) {
+                            Ok(source) => source,
+                            Err(_) => continue,
+                        };
+                        let tasks = tasks::tasks(&source)
+                            .into_iter()
+                            .enumerate()
+                            .filter(|(_, task)| !task.checked)
+                            .filter(|(_, task)| {
+                                query.assignee.trim().is_empty()
+                                    || task.is_assigned_to(query.assignee.trim())
+                            })
+                            .map(|(index, task)| TemplateTaskData {
+                                index,
+                                line: task.line,
+                                text: task.text,
+                            })
+                            .collect::<Vec<_>>();
+                        if !tasks.is_empty() {
+                            pages.push(TemplateTasksPageData {
+                                path: file.path().to_string(),
+                                link: PathStringBuilder::new(file.path().clone())
+                                    .root(true)
+                                    .build_percent_encode(),
+                                revision: file.oid()?.to_string(),
+                                tasks,
+                            });
+                        }
+                    }
+                    pages.sort_by(|a, b| a.path.cmp(&b.path));
+
+                    Ok(ResponseBuilder::new().status(200).body_template(
+                        &templates,
+                        "tasks.html",
+                        &TemplateTasksData {
+                            count: pages.iter().map(|page| page.tasks.len()).sum(),
+                            path: query.path,
+                            assignee: query.assignee,
+                            pages,
+                        },
+                    )?)
+                },
+            )
     }
 
     /// Serves an edit page for a file in the repository. Matches any URL with `edit` query
@@ -252,7 +1162,10 @@ impl Smeagol {
     /// shown. If the file cannot exist a special page is served.
     ///
     /// If the file is invalid UTF-8 the textarea is empty and a warning is shown.
-    fn edit(&self) -> impl Filter<Extract = impl Reply, Error = warp::Rejection> + Clone {
+    ///
+    /// With a `section` query parameter only that section of a markdown page is edited (see
+    /// `sections`). If the section does not exist the whole page is edited instead.
+    fn edit(&self) -> impl Filter<Extract = (Response<Vec<u8>>,), Error = warp::Rejection> + Clone {
         /// Query parameter matcher.
         ///
         /// The actual value of the query parameter does not matter and is never accessed. It only
@@ -262,6 +1175,8 @@ impl Smeagol {
             // This field is never accessed but is required for the tag
             #[allow(dead_code)]
             edit: String,
+            /// Number of the edited section, starting at 1.
+            section: Option<usize>,
         }
 
         /// Data for `edit.html.hbs`.
@@ -275,6 +1190,12 @@ impl Smeagol {
             ///
             /// If not, a warning is shown above the text area.
             is_valid: bool,
+            /// Number of the edited section if only a section is edited.
+            section: Option<usize>,
+            /// Heading of the edited section.
+            section_title: Option<String>,
+            /// Blob id of the edited file. It is empty if the file does not exist yet.
+            revision: String,
             // TODO replace config with used parameters
             config: &'a Config,
         }
@@ -295,7 +1216,7 @@ impl Smeagol {
             .and(self.config())
             .and_then(
                 |path: Path,
-                 _: QueryParameters,
+                 query: QueryParameters,
                  templates: Arc<Handlebars>,
                  config: Arc<Config>|
                  -> Result<Response<Vec<u8>>, Rejection> {
@@ -315,13 +1236,38 @@ impl Smeagol {
                     match item.content() {
                         Ok(content) => {
                             let parsed_content = String::from_utf8(content);
+                            let is_valid = parsed_content.is_ok();
+                            let is_markdown =
+                                matches!(Filetype::for_path(&path, &config), Filetype::Markdown);
+                            let section = match (query.section, &parsed_content) {
+                                (Some(index), Ok(source)) if is_markdown => {
+                                    match sections::section(source, index) {
+                                        Some(section) => Some(section),
+                                        None => {
+                                            return Ok(
+                                                ResponseBuilder::new().redirect_query(path, "edit")
+                                            );
+                                        }
+                                    }
+                                }
+                                _ => None,
+                            };
+                            let content = match (&section, parsed_content) {
+                                (Some(section), Ok(source)) => {
+                                    source[section.start..section.end].to_string()
+                                }
+                                (_, parsed_content) => parsed_content.unwrap_or_default(),
+                            };
                             Ok(ResponseBuilder::new().status(200).body_template(
                                 &templates,
                                 "edit.html",
                                 &TemplateEditData {
                                     path: path.to_string(),
-                                    is_valid: parsed_content.is_ok(),
-                                    content: parsed_content.unwrap_or("".to_string()),
+                                    is_valid,
+                                    content,
+                                    section: section.as_ref().map(|section| section.index),
+                                    section_title: section.map(|section| section.title),
+                                    revision: item.oid()?.to_string(),
                                     config: &config,
                                 },
                             )?)
@@ -334,6 +1280,9 @@ impl Smeagol {
                                     path: path.to_string(),
                                     is_valid: true,
                                     content: "".to_string(),
+                                    section: None,
+                                    section_title: None,
+                                    revision: "".to_string(),
                                     config: &config,
                                 },
                             )?)
@@ -344,14 +1293,238 @@ impl Smeagol {
             )
     }
 
+    /// Uploads several files in a single commit (see `upload`). Matches any URL with an `upload`
+    /// query parameter.
+    ///
+    /// The request body is `multipart/form-data` with these fields:
+    ///
+    /// - `files`: the uploaded files (any number).
+    /// - `directory`: the directory the files are uploaded to. Defaults to the URL path.
+    /// - `message`: the commit message.
+    /// - `overwrite`: `skip` (default), `replace` or `abort`.
+    ///
+    /// Returns the result of every file as an HTML page or, if requested by the `Accept` header,
+    /// as JSON.
+    fn upload(
+        &self,
+    ) -> impl Filter<Extract = (Response<Vec<u8>>,), Error = warp::Rejection> + Clone {
+        /// Query parameter matcher.
+        #[derive(Deserialize)]
+        struct QueryParameters {
+            // This field is never accessed but is required for the tag
+            #[allow(dead_code)]
+            upload: String,
+        }
+        /// Data for `upload.html.hbs` and the JSON response.
+        #[derive(Serialize)]
+        struct UploadData {
+            /// Path of the directory the files were uploaded to.
+            directory: String,
+            /// Link to the directory listing.
+            list_link: String,
+            /// Whether a commit was created.
+            committed: bool,
+            files: Vec<UploadFileData>,
+        }
+        #[derive(Serialize)]
+        struct UploadFileData {
+            path: String,
+            /// Link to the file if it exists.
+            link: Option<String>,
+            status: UploadStatus,
+            error: Option<String>,
+        }
+        warp::post2()
+            .and(
+                warp::path::full().map(|fullpath: warp::filters::path::FullPath| {
+                    Path::from_percent_encoded(fullpath.as_str().to_string().as_bytes())
+                }),
+            )
+            .and(warp::query::<QueryParameters>())
+            .and(warp::header::optional::<String>("accept"))
+            .and(self.templates())
+            .and(self.config())
+            .and(
+                warp::header::optional::<String>("content-type")
+                    .and(warp::body::content_length_limit(
+                        self.config.max_upload_size,
+                    ))
+                    .and(warp::body::stream())
+                    .and(self.config())
+                    .and_then(
+                        |content_type: Option<String>, body: BodyStream, config: Arc<Config>| {
+                            // Invalid forms are reported by the handler.
+                            let boundary =
+                                match content_type.as_deref().and_then(multipart::boundary) {
+                                    Some(boundary) => boundary,
+                                    None => return Either::A(future::ok(None)),
+                                };
+                            let reader = FormReader::new(&boundary, config.repo.clone().into());
+                            Either::B(
+                                body.map_err(warp::reject::custom)
+                                    .fold(reader, |mut reader, chunk| {
+                                        blocking(move || {
+                                            reader.feed(chunk.bytes())?;
+                                            Ok(reader)
+                                        })
+                                    })
+                                    .map(FormReader::finish),
+                            )
+                        },
+                    ),
+            )
+            .and_then(
+                |path: Path,
+                 _: QueryParameters,
+                 accept: Option<String>,
+                 templates: Arc<Handlebars>,
+                 config: Arc<Config>,
+                 fields: Option<Vec<Field>>|
+                 -> Result<Response<Vec<u8>>, Rejection> {
+                    let json = negotiate(accept.as_deref(), &["text/html", "application/json"])
+                        == "application/json";
+                    let error = |status: u16, error: &str| {
+                        if json {
+                            ResponseBuilder::new().status(status).body_json(&ErrorData {
+                                error: error.to_string(),
+                            })
+                        } else {
+                            Ok(ResponseBuilder::new()
+                                .status(status)
+                                .header(header::CONTENT_TYPE, ContentType::Plain)
+                                .body(error.as_bytes().to_vec()))
+                        }
+                    };
+
+                    let fields = match fields {
+                        Some(fields) => fields,
+                        None => return Ok(error(400, "Invalid form data.")?),
+                    };
+                    let mut files = vec![];
+                    let mut directory = path;
+                    let mut message = None;
+                    let mut policy = OverwritePolicy::Skip;
+                    for field in fields {
+                        let text = match field.value {
+                            // Browsers send an empty file if none was selected.
+                            Value::File(content) => {
+                                match field.filename {
+                                    Some(filename)
+                                        if field.name == "files" && !filename.is_empty() =>
+                                    {
+                                        files.push((filename, content))
+                                    }
+                                    _ => {}
+                                }
+                                continue;
+                            }
+                            Value::Text(text) => String::from_utf8_lossy(&text).trim().to_string(),
+                        };
+                        match &field.name[..] {
+                            "directory" if !text.is_empty() => {
+                                directory = match Path::new().resolve(text.as_bytes()) {
+                                    Some(directory) => directory,
+                                    None => return Ok(error(400, "Invalid directory.")?),
+                                }
+                            }
+                            "message" if !text.is_empty() => message = Some(text),
+                            "overwrite" => match OverwritePolicy::parse(&text) {
+                                Some(parsed) => policy = parsed,
+                                None => return Ok(error(400, "Invalid overwrite policy.")?),
+                            },
+                            _ => {}
+                        }
+                    }
+                    if files.is_empty() {
+                        return Ok(error(400, "No files were uploaded.")?);
+                    }
+                    let directory_name = PathStringBuilder::new(directory.clone())
+                        .root(true)
+                        .dir(true)
+                        .build_lossy();
+                    let message = message.unwrap_or_else(|| {
+                        format!("Uploaded {} files to {}", files.len(), directory_name)
+                    });
+
+                    let repo = GitRepository::new(&config.repo)?;
+                    let uploader = Uploader::new(&repo, &config, directory.clone(), policy);
+                    let results = uploader.upload(files, &message)?;
+
+                    let committed = results.iter().any(|file| {
+                        file.status == UploadStatus::Added || file.status == UploadStatus::Replaced
+                    });
+                    let aborted = policy == OverwritePolicy::Abort
+                        && results
+                            .iter()
+                            .any(|file| file.status == UploadStatus::Rejected);
+                    let data = UploadData {
+                        directory: directory_name,
+                        list_link: format!(
+                            "{}?list",
+                            PathStringBuilder::new(directory)
+                                .root(true)
+                                .build_percent_encode()
+                        ),
+                        committed,
+                        files: results
+                            .into_iter()
+                            .map(|file| {
+                                let stored = match file.status {
+                                    UploadStatus::Rejected => false,
+                                    UploadStatus::Skipped => !aborted,
+                                    _ => true,
+                                };
+                                UploadFileData {
+                                    path: file.path.to_string(),
+                                    link: if stored {
+                                        Some(
+                                            PathStringBuilder::new(file.path)
+                                                .root(true)
+                                                .build_percent_encode(),
+                                        )
+                                    } else {
+                                        None
+                                    },
+                                    status: file.status,
+                                    error: file.error,
+                                }
+                            })
+                            .collect(),
+                    };
+                    let status = if aborted { 409 } else { 200 };
+                    if json {
+                        Ok(ResponseBuilder::new().status(status).body_json(&data)?)
+                    } else {
+                        Ok(ResponseBuilder::new().status(status).body_template(
+                            &templates,
+                            "upload.html",
+                            &data,
+                        )?)
+                    }
+                },
+            )
+    }
+
     /// Edits or creates a file in the repository. Matches any URL.
     ///
-    /// Requires a query paramater `commit_message`. File content is in request body.
-    fn post(&self) -> impl Filter<Extract = impl Reply, Error = warp::Rejection> + Clone {
+    /// Requires a query paramater `commit_message`. File content is in request body. It is
+    /// streamed into the repository, so large files are never held in memory.
+    ///
+    /// Structured data files (JSON, TOML, YAML) are validated first. If they are invalid nothing
+    /// is committed and the location of the error is returned.
+    ///
+    /// An optional `revision` query parameter contains the blob id the edit is based on (empty
+    /// for new files). If the file has been changed since, nothing is committed. With a `section`
+    /// query parameter the body only replaces that section of a markdown page. Then the edit is
+    /// still accepted if only other parts of the page have been changed.
+    fn post(&self) -> impl Filter<Extract = (Response<Vec<u8>>,), Error = warp::Rejection> + Clone {
         /// Query paramter matcher.
         #[derive(Deserialize)]
         struct QueryParameters {
             commit_message: String,
+            revision: Option<String>,
+            /// Number of the edited section, starting at 1.
+            section: Option<usize>,
         }
         /// Data returned if the edit was successful.
         ///
@@ -367,6 +1540,123 @@ impl Smeagol {
         struct EditErrorData {
             error: String,
         }
+        /// Data returned if the file content is invalid for its filetype.
+        ///
+        /// Contains an error message and the location (starting at 1) of the error if it is known.
+        #[derive(Serialize)]
+        struct ValidationErrorData {
+            error: String,
+            line: Option<usize>,
+            column: Option<usize>,
+        }
+        fn error(status: u16, error: &str) -> Result<Response<Vec<u8>>, SmeagolError> {
+            ResponseBuilder::new()
+                .status(status)
+                .body_json(&EditErrorData {
+                    error: error.to_string(),
+                })
+        }
+        fn conflict() -> Result<Response<Vec<u8>>, SmeagolError> {
+            error(409, "The file has been changed. Reload it and try again.")
+        }
+        /// Commits the streamed body after checking it. Blocks.
+        fn edit(
+            path: Path,
+            query: QueryParameters,
+            config: &Config,
+            blob: BlobStream,
+        ) -> Result<Response<Vec<u8>>, Rejection> {
+            let repo = GitRepository::new(&config.repo)?;
+            let item = repo.item(path.clone())?;
+            let current = match item.content() {
+                Ok(content) => Some((item.oid()?.to_string(), content)),
+                Err(GitError::NotFound) | Err(GitError::IsDir) => None,
+                Err(err) => return Err(err.into()),
+            };
+            let current_revision = current.as_ref().map_or("", |(oid, _)| oid.as_str());
+
+            // The page with the replaced section, if only a section is edited.
+            let mut spliced = None;
+            if let Some(index) = query.section {
+                // Sections are small, they can be read back.
+                let replacement = blob.content()?;
+                let (source, replacement) = match (
+                    current
+                        .as_ref()
+                        .map(|(_, content)| std::str::from_utf8(content)),
+                    std::str::from_utf8(&replacement),
+                ) {
+                    (Some(Ok(source)), Ok(replacement)) => (source, replacement),
+                    _ => return Ok(error(400, "Only sections of text files can be edited.")?),
+                };
+                let section = match sections::section(source, index) {
+                    Some(section) => section,
+                    None => return Ok(conflict()?),
+                };
+                // Edits of other sections do not conflict. The section is compared by its
+                // number, so added or removed headings before it are conflicts too.
+                if let Some(ref revision) = query.revision {
+                    if revision != current_revision {
+                        let base = repo.read_blob(revision).ok().and_then(|base| {
+                            let base = String::from_utf8(base).ok()?;
+                            let section = sections::section(&base, index)?;
+                            Some(base[section.start..section.end].to_string())
+                        });
+                        if base.as_deref() != Some(&source[section.start..section.end]) {
+                            return Ok(conflict()?);
+                        }
+                    }
+                }
+                spliced = Some(sections::splice(source, &section, replacement));
+            } else if let Some(ref revision) = query.revision {
+                // The file may have changed while the body was streamed.
+                if revision != current_revision {
+                    return Ok(conflict()?);
+                }
+            }
+
+            let filetype = Filetype::for_path(&path, config);
+            let validation = match spliced {
+                Some(ref spliced) if filetype.is_data() => filetype.validate(spliced.as_bytes()),
+                None if filetype.is_data() => filetype.validate(&blob.content()?),
+                _ => Ok(()),
+            };
+            if let Err(err) = validation {
+                let location = err.location();
+                let error = match location {
+                    Some((line, column)) => {
+                        format!("Invalid file at line {}, column {}: {}", line, column, err)
+                    }
+                    None => format!("Invalid file: {}", err),
+                };
+                return Ok(ResponseBuilder::new()
+                    .status(400)
+                    .body_json(&ValidationErrorData {
+                        error,
+                        line: location.map(|(line, _)| line),
+                        column: location.map(|(_, column)| column),
+                    })?);
+            }
+
+            // The blob is only created once the content was accepted.
+            let blob = match spliced {
+                Some(spliced) => repo.write_blob(spliced.as_bytes())?,
+                None => blob.commit(&repo)?,
+            };
+            match item.edit_blob(blob, &query.commit_message) {
+                Ok(()) | Err(GitError::NoChange) => Ok(ResponseBuilder::new()
+                    .status(200)
+                    .body_json(&EditSuccessData {
+                        path: PathStringBuilder::new(path)
+                            .root(true)
+                            .build_percent_encode(),
+                    })?),
+                Err(GitError::CannotCreate) => {
+                    Ok(error(400, "Could not create file at that location.")?)
+                }
+                Err(err) => Err(err.into()),
+            }
+        }
         warp::post2()
             .and(
                 warp::path::full().map(|fullpath: warp::filters::path::FullPath| {
@@ -375,48 +1665,148 @@ impl Smeagol {
             )
             .and(warp::query::<QueryParameters>())
             .and(self.config())
+            .and(warp::body::content_length_limit(
+                self.config.max_upload_size,
+            ))
+            .and(warp::body::stream())
+            .and_then(
+                |path: Path, query: QueryParameters, config: Arc<Config>, body: BodyStream| {
+                    // Edits of whole files that conflict are rejected before the body is read.
+                    if let (None, Some(ref revision)) = (query.section, &query.revision) {
+                        let current_revision = GitRepository::new(&config.repo).and_then(|repo| {
+                            let item = repo.item(path.clone())?;
+                            match item.is_file() {
+                                Ok(true) => Ok(item.oid()?.to_string()),
+                                Ok(false) | Err(GitError::NotFound) => Ok(String::new()),
+                                Err(err) => Err(err),
+                            }
+                        });
+                        match current_revision {
+                            Ok(ref current_revision) if current_revision == revision => {}
+                            Ok(_) => {
+                                return Either::A(future::result(
+                                    conflict().map_err(Rejection::from),
+                                ))
+                            }
+                            Err(err) => return Either::A(future::err(err.into())),
+                        }
+                    }
+                    let dir = config.repo.clone();
+                    Either::B(
+                        stream_body(body, dir).and_then(move |blob| {
+                            blocking(move || edit(path, query, &config, blob))
+                        }),
+                    )
+                },
+            )
+    }
+    /// Checks or unchecks a task of a markdown page. Matches any URL with `task`, `checked` and
+    /// `revision` query parameters.
+    ///
+    /// `task` is the index of the task on the page and `revision` the blob id of the page the task
+    /// was read from. If the page has changed since or the task already has the requested state,
+    /// nothing is committed. Returns the new blob id of the page.
+    fn toggle_task(
+        &self,
+    ) -> impl Filter<Extract = (Response<Vec<u8>>,), Error = warp::Rejection> + Clone {
+        /// Query parameter matcher.
+        #[derive(Deserialize)]
+        struct QueryParameters {
+            task: usize,
+            checked: bool,
+            revision: String,
+        }
+        /// Data returned if the task was changed.
+        #[derive(Serialize)]
+        struct ToggleSuccessData {
+            revision: String,
+        }
+        /// Data returned if the task could not be changed.
+        #[derive(Serialize)]
+        struct ToggleErrorData {
+            error: String,
+        }
+
+        warp::post2()
             .and(
-                warp::body::content_length_limit(self.config.max_upload_size)
-                    .and(warp::body::concat()),
+                warp::path::full().map(|fullpath: warp::filters::path::FullPath| {
+                    Path::from_percent_encoded(fullpath.as_str().to_string().as_bytes())
+                }),
             )
+            .and(warp::query::<QueryParameters>())
+            .and(self.config())
             .and_then(
                 |path: Path,
                  query: QueryParameters,
-                 config: Arc<Config>,
-                 mut body: warp::body::FullBody|
+                 config: Arc<Config>|
                  -> Result<Response<Vec<u8>>, Rejection> {
-                    let mut buffer = vec![0; body.remaining()];
-                    body.copy_to_slice(&mut buffer[..]);
+                    let error = |status: u16, error: &str| {
+                        ResponseBuilder::new()
+                            .status(status)
+                            .body_json(&ToggleErrorData {
+                                error: error.to_string(),
+                            })
+                    };
 
                     let repo = GitRepository::new(&config.repo)?;
                     let item = repo.item(path.clone())?;
+                    let source = match item.content() {
+                        Ok(content) => match String::from_utf8(content) {
+                            Ok(source) => source,
+                            Err(_) => return Ok(error(400, "The page is not a text file.")?),
+                        },
+                        Err(GitError::NotFound) | Err(GitError::IsDir) => {
+                            return Ok(error(404, "The page does not exist.")?);
+                        }
+                        Err(err) => return Err(err.into()),
+                    };
+                    if item.oid()?.to_string() != query.revision {
+                        return Ok(error(
+                            409,
+                            "The page has been changed. Reload it and try again.",
+                        )?);
+                    }
 
-                    match item.edit(&buffer[..], &query.commit_message) {
-                        Ok(()) | Err(GitError::NoChange) => Ok(ResponseBuilder::new()
-                            .status(200)
-                            .body_json(&EditSuccessData {
-                                path: PathStringBuilder::new(path)
-                                    .root(true)
-                                    .build_percent_encode(),
-                            })?),
-                        Err(GitError::CannotCreate) => Ok(ResponseBuilder::new()
-                            .status(400)
-                            .body_json(&EditErrorData {
-                                error: "Could not create file at that location.".to_string(),
-                            })?),
-                        Err(err) => Err(err.into()),
+                    let task = match tasks::tasks(&source).into_iter().nth(query.task) {
+                        Some(task) => task,
+                        None => return Ok(error(404, "The task does not exist.")?),
+                    };
+                    if task.checked == query.checked {
+                        return Ok(error(409, "The task has already been changed.")?);
                     }
+
+                    let message = format!(
+                        "{} task in {}: {}",
+                        if query.checked {
+                            "Checked"
+                        } else {
+                            "Unchecked"
+                        },
+                        path,
+                        task.text
+                    );
+                    item.edit(
+                        tasks::set_checked(&source, &task, query.checked).as_bytes(),
+                        &message,
+                    )?;
+
+                    Ok(ResponseBuilder::new()
+                        .status(200)
+                        .body_json(&ToggleSuccessData {
+                            revision: item.oid()?.to_string(),
+                        })?)
                 },
             )
     }
+
     /// Serves a page listing all files in a directory. Matches any URL with a `list` query
     /// paramter.
     ///
     /// If the directory does not exist a 404 page is served. If the path points to a file a
     /// redirect is added.
     ///
-    /// Directories are sorted first.
-    fn list(&self) -> impl Filter<Extract = impl Reply, Error = warp::Rejection> + Clone {
+    /// Directories are sorted first. Images are additionally shown as thumbnail gallery.
+    fn list(&self) -> impl Filter<Extract = (Response<Vec<u8>>,), Error = warp::Rejection> + Clone {
         /// Query parameter matcher.
         ///
         /// The actual value of the query parameter does not matter and is never accessed. It only
@@ -432,6 +1822,8 @@ impl Smeagol {
         struct TemplateListData {
             /// Path of the listed directory.
             path: String,
+            /// Path of the listed directory for the upload form.
+            directory: String,
             /// Link to the parent directory if it exist (the listed directory is not the root).
             parent_list_link: Option<String>,
             /// List of all the items in the directory.
@@ -444,6 +1836,8 @@ impl Smeagol {
             link: String,
             /// Name of the item.
             name: String,
+            /// Link to a thumbnail if the item is an image.
+            thumbnail: Option<String>,
         }
         /// Data for `list_not_found.html.hbs`.
         #[derive(Serialize)]
@@ -457,79 +1851,127 @@ impl Smeagol {
                 }),
             )
             .and(warp::query::<QueryParameters>())
+            .and(warp::header::headers_cloned())
             .and(self.templates())
             .and(self.config())
+            .and(self.caches())
             .and_then(
                 |path: Path,
                  _: QueryParameters,
+                 request: HeaderMap,
                  templates: Arc<Handlebars>,
-                 config: Arc<Config>|
+                 config: Arc<Config>,
+                 caches: Arc<Caches>|
                  -> Result<Response<Vec<u8>>, Rejection> {
-                    let repo = GitRepository::new(&config.repo)?;
+                    let repo = GitRepository::with_caches(&config.repo, caches.clone())?;
                     let item = repo.item(path.clone())?;
 
                     match item.list() {
-                        Ok(items) => Ok(ResponseBuilder::new().status(200).body_template(
-                            &templates,
-                            "list.html",
-                            &TemplateListData {
-                                path: PathStringBuilder::new(path.clone()).dir(true).build_lossy(),
-                                parent_list_link: path.clone().parent().map(|path| {
-                                    format!(
-                                        "{}?list",
-                                        PathStringBuilder::new(path)
+                        Ok(items) => {
+                            // The tree id changes whenever a file below the directory changes.
+                            let validators = Validators::weak(
+                                format!("{}-{}", item.oid()?, *INSTANCE),
+                                Some(item.last_modified()?),
+                            );
+                            if validators.is_fresh(&request) {
+                                return Ok(ResponseBuilder::new().not_modified(&validators));
+                            }
+                            Ok(ResponseBuilder::new()
+                                .validators(&validators)
+                                .status(200)
+                                .body_template(
+                                    &templates,
+                                    "list.html",
+                                    &TemplateListData {
+                                        path: PathStringBuilder::new(path.clone())
+                                            .dir(true)
+                                            .build_lossy(),
+                                        directory: PathStringBuilder::new(path.clone())
                                             .root(true)
-                                            .build_percent_encode()
-                                    )
-                                }),
-                                children: items
-                                    .iter()
-                                    .sorted_by(|a, b| {
-                                        // Error handling instead of unwrap would be preferable but
-                                        // horrible in this situation. There should be no errors
-                                        // anyways; the files have to exist (the only reasonable
-                                        // error source).
-                                        if a.is_dir().unwrap() && b.is_file().unwrap() {
-                                            Ordering::Less
-                                        } else if a.is_file().unwrap() && b.is_dir().unwrap() {
-                                            Ordering::Greater
-                                        } else {
-                                            // Unwrap because the root cannot be one of the listed
-                                            // items.
-                                            a.path()
-                                                .filename()
-                                                .unwrap()
-                                                .bytes()
-                                                .cmp(b.path().filename().unwrap().bytes())
-                                        }
-                                    })
-                                    .map(|item| -> Result<TemplateListChildData, GitError> {
-                                        let link = if item.is_dir()? {
+                                            .build_lossy(),
+                                        parent_list_link: path.clone().parent().map(|path| {
                                             format!(
                                                 "{}?list",
-                                                PathStringBuilder::new(item.path().clone())
+                                                PathStringBuilder::new(path)
                                                     .root(true)
                                                     .build_percent_encode()
                                             )
-                                        } else {
-                                            PathStringBuilder::new(item.path().clone())
-                                                .root(true)
-                                                .build_percent_encode()
-                                        };
-                                        Ok(TemplateListChildData {
-                                            link: link,
-                                            name: PathStringBuilder::new(
-                                                // A child of something has to have a filename
-                                                item.path().filename().unwrap(),
+                                        }),
+                                        children: items
+                                            .iter()
+                                            .sorted_by(|a, b| {
+                                                // Error handling instead of unwrap would be
+                                                // preferable but horrible in this situation. There
+                                                // should be no errors anyways; the files have to
+                                                // exist (the only reasonable error source).
+                                                if a.is_dir().unwrap() && b.is_file().unwrap() {
+                                                    Ordering::Less
+                                                } else if a.is_file().unwrap()
+                                                    && b.is_dir().unwrap()
+                                                {
+                                                    Ordering::Greater
+                                                } else {
+                                                    // Unwrap because the root cannot be one of the
+                                                    // listed items.
+                                                    a.path()
+                                                        .filename()
+                                                        .unwrap()
+                                                        .bytes()
+                                                        .cmp(b.path().filename().unwrap().bytes())
+                                                }
+                                            })
+                                            .map(
+                                                |item| -> Result<TemplateListChildData, GitError> {
+                                                    let link = if item.is_dir()? {
+                                                        format!(
+                                                            "{}?list",
+                                                            PathStringBuilder::new(
+                                                                item.path().clone()
+                                                            )
+                                                            .root(true)
+                                                            .build_percent_encode()
+                                                        )
+                                                    } else {
+                                                        PathStringBuilder::new(item.path().clone())
+                                                            .root(true)
+                                                            .build_percent_encode()
+                                                    };
+                                                    // Only files with an image extension are read
+                                                    // to detect their filetype, reading all files
+                                                    // would be too slow.
+                                                    let thumbnail = if item.is_file()?
+                                                        && Filetype::for_path(item.path(), &config)
+                                                            .is_image()
+                                                        && Filetype::detect(
+                                                            item.path(),
+                                                            &item.content()?,
+                                                            &config,
+                                                        )
+                                                        .is_image()
+                                                    {
+                                                        Some(format!("{}?thumb", link))
+                                                    } else {
+                                                        None
+                                                    };
+                                                    Ok(TemplateListChildData {
+                                                        link,
+                                                        thumbnail,
+                                                        name: PathStringBuilder::new(
+                                                            // A child of something has to have a
+                                                            // filename
+                                                            item.path().filename().unwrap(),
+                                                        )
+                                                        .dir(item.is_dir()?)
+                                                        .build_lossy(),
+                                                    })
+                                                },
                                             )
-                                            .dir(item.is_dir()?)
-                                            .build_lossy(),
-                                        })
-                                    })
-                                    // A list of results can be collected to a result of a list.
-                                    .collect::<Result<Vec<_>, _>>()?,
-                            },
-                        )?),
+                                            // A list of results can be collected to a result of a
+                                            // list.
+                                            .collect::<Result<Vec<_>, _>>()?,
+                                    },
+                                )?)
+                        }
                         Err(GitError::NotFound) => {
                             Ok(ResponseBuilder::new().status(200).body_template(
                                 &templates,
@@ -557,4 +1999,100 @@ impl Smeagol {
         let config = self.config.clone();
         warp::any().and_then(move || -> Result<Arc<Config>, Rejection> { Ok(config.clone()) })
     }
+    /// Returns a filter that returns the caches for use with `.and`.
+    fn caches(&self) -> impl Filter<Extract = (Arc<Caches>,), Error = Rejection> + Clone {
+        let caches = self.caches.clone();
+        warp::any().and_then(move || -> Result<Arc<Caches>, Rejection> { Ok(caches.clone()) })
+    }
+}
+
+/// Runs blocking work like file IO on a runtime worker (see `tokio_threadpool::blocking`), so the
+/// other requests handled by that worker are not stalled.
+fn blocking<T, F>(work: F) -> impl Future<Item = T, Error = Rejection>
+where
+    F: FnOnce() -> Result<T, Rejection>,
+{
+    let mut work = Some(work);
+    future::poll_fn(move || {
+        // The work is only taken if it can be run.
+        let result = match tokio_threadpool::blocking(|| (work.take().unwrap())()) {
+            Ok(Async::Ready(result)) => result,
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            // Outside of a thread pool the work is done right away.
+            Err(_) => (work.take().unwrap())(),
+        };
+        result.map(Async::Ready)
+    })
+}
+
+/// Streams a request body into a `BlobStream` in the repository at `dir`.
+fn stream_body(body: BodyStream, dir: String) -> impl Future<Item = BlobStream, Error = Rejection> {
+    blocking(move || Ok(BlobStream::new(dir)?)).and_then(|blob| {
+        body.map_err(warp::reject::custom)
+            .fold(blob, |mut blob, chunk| {
+                blocking(move || {
+                    blob.write(chunk.bytes())?;
+                    Ok(blob)
+                })
+            })
+    })
+}
+
+/// Returns the unmodified content of a file, or the range of it requested by `request`. It is
+/// offered for download if its `[filetypes]` rule disables inline display.
+fn raw_response(
+    response: &mut ResponseBuilder,
+    request: &HeaderMap,
+    filetype: &Filetype,
+    rule: Option<&FiletypeRule>,
+    validators: &Validators,
+    content: Vec<u8>,
+) -> Response<Vec<u8>> {
+    response.filetype(filetype, rule).validators(validators);
+    if rule.and_then(|rule| rule.inline) == Some(false) {
+        response.download();
+    }
+    response.body_range(request, validators, content)
+}
+
+/// Returns a filter matching the API endpoint `name` and extracting the path following it.
+fn api_path(name: &'static str) -> impl Filter<Extract = (Path,), Error = Rejection> + Clone {
+    warp::path(name)
+        .and(warp::path::tail())
+        .map(|tail: warp::filters::path::Tail| Path::from_percent_encoded(tail.as_str().as_bytes()))
+}
+
+/// Returns whether a file is still at the expected revision (an empty revision means the file
+/// must not exist). Without a revision every change is accepted.
+fn is_at_revision(item: &GitItem, revision: Option<&str>) -> Result<bool, GitError> {
+    let revision = match revision {
+        Some(revision) => revision,
+        None => return Ok(true),
+    };
+    if item.exists()? {
+        Ok(item.oid()?.to_string() == revision)
+    } else {
+        Ok(revision.is_empty())
+    }
+}
+
+/// Response of API requests based on an outdated revision.
+fn api_conflict() -> Result<Response<Vec<u8>>, Rejection> {
+    Ok(ResponseBuilder::new().status(409).body_json(&ErrorData {
+        error: "The file has been changed since the given revision.".to_string(),
+    })?)
+}
+
+/// Turns errors caused by an API request into JSON responses (see `api::error_status`).
+fn api_error(err: GitError) -> Result<Response<Vec<u8>>, Rejection> {
+    match api::error_status(&err) {
+        Some((status, error)) => {
+            Ok(ResponseBuilder::new()
+                .status(status)
+                .body_json(&ErrorData {
+                    error: error.to_string(),
+                })?)
+        }
+        None => Err(err.into()),
+    }
 }
@@ -0,0 +1,177 @@
+//! Static assets served under `/static/`.
+//!
+//! The files of the `static/` directory are read once at startup. Compressible files are
+//! compressed with every supported coding right away, so requests only pick the prepared variant.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use warp::http::{header, HeaderMap, Response};
+
+use crate::warp_helper::{is_compressible, ContentType, Encoding, ResponseBuilder, Validators};
+use crate::{Filetype, Path, SmeagolError};
+
+/// `Cache-Control` of static assets. They only change when the server is updated.
+const CACHE_CONTROL: &str = "public, max-age=3600";
+
+struct StaticFile {
+    content_type: String,
+    /// Hash of the uncompressed content.
+    hash: String,
+    /// The content and its compressed variants that are smaller.
+    variants: Vec<(Encoding, Vec<u8>)>,
+}
+
+pub struct StaticFiles {
+    /// Files by their path relative to the directory.
+    files: HashMap<String, StaticFile>,
+}
+impl StaticFiles {
+    /// Reads all files in `dir` and its subdirectories.
+    pub fn load(dir: &str) -> Result<StaticFiles, SmeagolError> {
+        let mut files = HashMap::new();
+        let mut dirs = vec![std::path::PathBuf::from(dir)];
+        while let Some(current) = dirs.pop() {
+            for entry in std::fs::read_dir(&current)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                // Paths in the directory always have it as their prefix.
+                let name = path
+                    .strip_prefix(dir)
+                    .unwrap()
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                let content = std::fs::read(&path)?;
+                files.insert(name.clone(), StaticFile::new(&name, content));
+            }
+        }
+        Ok(StaticFiles { files })
+    }
+
+    /// Returns the response for the file `name` in the coding preferred by the client, or `None`
+    /// if there is no such file.
+    pub fn response(&self, name: &str, request: &HeaderMap) -> Option<Response<Vec<u8>>> {
+        let file = self.files.get(name)?;
+        let accept_encoding = request
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|value| value.to_str().ok());
+        let preferred = Encoding::negotiate(accept_encoding);
+        let (encoding, content) = file
+            .variants
+            .iter()
+            .find(|(encoding, _)| *encoding == preferred)
+            .unwrap_or(&file.variants[0]);
+
+        // The variants are different representations and need their own entity tags.
+        let validators = match encoding.name() {
+            Some(name) => Validators::strong(format!("{}-{}", file.hash, name), None),
+            None => Validators::strong(&file.hash, None),
+        };
+        let mut response = ResponseBuilder::new();
+        response
+            .header(header::CONTENT_TYPE, &file.content_type[..])
+            .header(header::CACHE_CONTROL, CACHE_CONTROL);
+        if is_compressible(&file.content_type) {
+            response.header(header::VARY, "Accept-Encoding");
+        }
+        if let Some(name) = encoding.name() {
+            response.header(header::CONTENT_ENCODING, name);
+        }
+        if validators.is_fresh(request) {
+            return Some(response.not_modified(&validators));
+        }
+        Some(
+            response
+                .validators(&validators)
+                .status(200)
+                .body(content.clone()),
+        )
+    }
+}
+
+impl StaticFile {
+    fn new(name: &str, content: Vec<u8>) -> StaticFile {
+        let content_type = match name.rsplit('.').next() {
+            Some("css") => ContentType::Css.to_string(),
+            Some("js") => ContentType::JavaScript.to_string(),
+            _ => Filetype::from(&Path::from(name.to_string()))
+                .content_type()
+                .to_string(),
+        };
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+
+        let mut variants = vec![];
+        if is_compressible(&content_type) {
+            for encoding in &[Encoding::Brotli, Encoding::Gzip] {
+                let compressed = encoding.compress(&content, true);
+                if compressed.len() < content.len() {
+                    variants.push((*encoding, compressed));
+                }
+            }
+        }
+        // The uncompressed content comes first, it is the fallback.
+        variants.insert(0, (Encoding::Identity, content));
+        StaticFile {
+            content_type,
+            hash: format!("{:x}", hasher.finish()),
+            variants,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use warp::http::header::{self, HeaderMap};
+
+    use crate::statics::StaticFiles;
+
+    #[test]
+    fn static_files() {
+        let statics = StaticFiles::load("static/").unwrap();
+        let request = |accept_encoding: Option<&str>| {
+            let mut request = HeaderMap::new();
+            if let Some(accept_encoding) = accept_encoding {
+                request.insert(header::ACCEPT_ENCODING, accept_encoding.parse().unwrap());
+            }
+            request
+        };
+
+        let plain = statics.response("style.css", &request(None)).unwrap();
+        assert_eq!(
+            plain.headers()[header::CONTENT_TYPE],
+            "text/css; charset=utf-8"
+        );
+        assert!(plain.headers().get(header::CONTENT_ENCODING).is_none());
+
+        let brotli = statics
+            .response("style.css", &request(Some("gzip, br")))
+            .unwrap();
+        assert_eq!(brotli.headers()[header::CONTENT_ENCODING], "br");
+        assert!(brotli.body().len() < plain.body().len());
+        assert_ne!(
+            brotli.headers()[header::ETAG],
+            plain.headers()[header::ETAG]
+        );
+
+        let mut revalidate = request(Some("gzip"));
+        revalidate.insert(
+            header::IF_NONE_MATCH,
+            statics
+                .response("style.css", &revalidate)
+                .unwrap()
+                .headers()[header::ETAG]
+                .clone(),
+        );
+        let not_modified = statics.response("style.css", &revalidate).unwrap();
+        assert_eq!(not_modified.status(), 304);
+
+        assert!(statics.response("missing.css", &request(None)).is_none());
+    }
+}
@@ -0,0 +1,248 @@
+//! Task lists in markdown pages.
+//!
+//! Tasks are list items starting with `[ ]` or `[x]`, which the tasklist extension renders as
+//! checkboxes. They are found in the parsed document, so list items in code blocks, in HTML or in
+//! the front matter are not tasks. Tasks are numbered in document order and their rendered
+//! checkboxes carry that number in a `data-task` attribute (see `mark_checkboxes`).
+//!
+//! A task is assigned to everybody mentioned in it with `@name`.
+
+use comrak::nodes::{AstNode, NodeValue};
+use comrak::{Arena, ComrakOptions};
+use lazy_static::lazy_static;
+
+use regex::{Captures, Regex};
+
+use crate::filetype::split_front_matter;
+
+/// Start of the marker of an unchecked checkbox. It is followed by the task number and `MARK_END`.
+const MARK_UNCHECKED: char = '\u{F0020}';
+/// Start of the marker of a checked checkbox.
+const MARK_CHECKED: char = '\u{F0021}';
+const MARK_END: char = '\u{F0022}';
+
+lazy_static! {
+    /// The checkbox on the line of a task item. The parser guarantees that it is there.
+    static ref TASK: Regex =
+        Regex::new(r"\[(?P<state>[ xX])\](?:$|\s+(?P<text>.*)$)").unwrap();
+    static ref CHECKBOX: Regex =
+        Regex::new("(?P<mark>[\u{F0020}\u{F0021}])(?P<task>[0-9]+)\u{F0022}").unwrap();
+    static ref MENTION: Regex = Regex::new(r"(?:^|[^\w@])@(?P<name>[\w-]+)").unwrap();
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Task {
+    /// Line of the task, starting at 1.
+    pub line: usize,
+    pub checked: bool,
+    pub text: String,
+    /// Byte offset of the state character (` ` or `x`) in the page.
+    offset: usize,
+}
+impl Task {
+    /// Returns the names mentioned in the task (without `@`).
+    pub fn mentions(&self) -> Vec<String> {
+        MENTION
+            .captures_iter(&self.text)
+            .map(|captures| captures["name"].to_string())
+            .collect()
+    }
+
+    /// Whether `name` (with or without `@`) is mentioned in the task. Case is ignored.
+    pub fn is_assigned_to(&self, name: &str) -> bool {
+        let name = name.trim_start_matches('@').to_lowercase();
+        self.mentions()
+            .iter()
+            .any(|mention| mention.to_lowercase() == name)
+    }
+}
+
+/// Returns all tasks of a markdown page in document order.
+pub fn tasks(source: &str) -> Vec<Task> {
+    let body = split_front_matter(source).1;
+    let body_offset = source.len() - body.len();
+    let first_line = source[..body_offset].matches('\n').count() + 1;
+    let mut lines = vec![];
+    let mut offset = body_offset;
+    for line in body.split_inclusive('\n') {
+        lines.push((offset, line));
+        offset += line.len();
+    }
+
+    let arena = Arena::new();
+    let options = ComrakOptions {
+        ext_tasklist: true,
+        ..ComrakOptions::default()
+    };
+    let root = comrak::parse_document(&arena, body, &options);
+    let mut tasks = vec![];
+    for node in root.descendants() {
+        if let NodeValue::TaskItem(_) = node.data.borrow().value {
+            // The checkbox starts the paragraph containing the task item.
+            let line =
+                node.parent()
+                    .map_or(1, |parent| parent.data.borrow().start_line) as usize;
+            let (offset, text) = lines[line - 1];
+            if let Some(captures) = TASK.captures(text.trim_end()) {
+                let state = captures.name("state").unwrap();
+                tasks.push(Task {
+                    line: first_line + line - 1,
+                    checked: state.as_str() != " ",
+                    text: captures
+                        .name("text")
+                        .map_or("", |text| text.as_str())
+                        .to_string(),
+                    offset: offset + state.start(),
+                });
+            }
+        }
+    }
+    tasks
+}
+
+/// Replaces the task items of a parsed page by markers with their number, which
+/// `link_checkboxes` turns into checkboxes after the page is rendered and sanitized.
+pub fn mark_checkboxes<'a>(root: &'a AstNode<'a>) {
+    let mut number = 0;
+    for node in root.descendants() {
+        let mut data = node.data.borrow_mut();
+        if let NodeValue::TaskItem(checked) = data.value {
+            let mark = if checked {
+                MARK_CHECKED
+            } else {
+                MARK_UNCHECKED
+            };
+            let marker = format!("{}{}{}", mark, number, MARK_END);
+            data.value = NodeValue::Text(marker.into_bytes());
+            number += 1;
+        }
+    }
+}
+
+/// Replaces the markers of `mark_checkboxes` by checkboxes with a `data-task` attribute.
+pub fn link_checkboxes(html: &str) -> String {
+    CHECKBOX
+        .replace_all(html, |captures: &Captures| {
+            let checked = if captures["mark"].starts_with(MARK_CHECKED) {
+                " checked"
+            } else {
+                ""
+            };
+            format!(
+                "<input type=\"checkbox\" data-task=\"{}\" disabled{}> ",
+                &captures["task"], checked
+            )
+        })
+        .into_owned()
+}
+
+/// Checks or unchecks `task` in `source`. The task has to be taken from `tasks(source)`.
+pub fn set_checked(source: &str, task: &Task, checked: bool) -> String {
+    let state = if checked { "x" } else { " " };
+    let mut changed = source.to_string();
+    changed.replace_range(task.offset..task.offset + 1, state);
+    changed
+}
+
+/// Replaces the checkboxes of all tasks by symbols so that they are not rendered as checkboxes.
+///
+/// This is used for included pages: only the tasks of the page itself can be toggled.
+pub fn freeze(source: &str) -> String {
+    let mut frozen = source.to_string();
+    for task in tasks(source).iter().rev() {
+        let symbol = if task.checked { "\u{2611}" } else { "\u{2610}" };
+        // The brackets surround the state character.
+        frozen.replace_range(task.offset - 1..task.offset + 2, symbol);
+    }
+    frozen
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tasks::{freeze, link_checkboxes, mark_checkboxes, set_checked, tasks};
+
+    #[test]
+    fn find_tasks() {
+        let source = "---\ntitle: Tasks\n---\n\
+                      - [ ] Open @alice\n\
+                      - [x] Done\n\
+                      \n\
+                      ```\n\
+                      - [ ] In code\n\
+                      ```\n\
+                      1. [X] Numbered\n  \
+                      * [ ] Nested for @Bob and @carol-2\n\
+                      > - [ ]\n\
+                      - [ ]no space\n\
+                      - [] not a task\n";
+        let found = tasks(source)
+            .into_iter()
+            .map(|task| (task.line, task.checked, task.text))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                (4, false, "Open @alice".to_string()),
+                (5, true, "Done".to_string()),
+                (10, true, "Numbered".to_string()),
+                (11, false, "Nested for @Bob and @carol-2".to_string()),
+                (12, false, "".to_string()),
+            ]
+        );
+
+        let tasks = tasks(source);
+        assert_eq!(tasks[0].mentions(), vec!["alice".to_string()]);
+        assert!(tasks[3].is_assigned_to("@bob"));
+        assert!(tasks[3].is_assigned_to("carol-2"));
+        assert!(!tasks[3].is_assigned_to("carol"));
+        assert!(!tasks[1].is_assigned_to("alice"));
+    }
+
+    #[test]
+    fn skip_code_and_html() {
+        let source = "Intro\n\n    - [ ] In an indented code block\n\n\
+                      <div>\n- [ ] In HTML\n</div>\n\n\
+                      - [x] Real\n";
+        let found = tasks(source);
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].line, found[0].checked), (9, true));
+    }
+
+    #[test]
+    fn number_checkboxes() {
+        let source = "<input type=\"checkbox\">\n\n- [ ] a\n- [x] b\n";
+        let arena = comrak::Arena::new();
+        let options = comrak::ComrakOptions {
+            ext_tasklist: true,
+            unsafe_: true,
+            ..comrak::ComrakOptions::default()
+        };
+        let root = comrak::parse_document(&arena, source, &options);
+        mark_checkboxes(root);
+        let mut html = vec![];
+        comrak::format_html(root, &options, &mut html).unwrap();
+        let html = link_checkboxes(&String::from_utf8(html).unwrap());
+        assert!(html.starts_with("<input type=\"checkbox\">\n"));
+        assert!(html.contains("<li><input type=\"checkbox\" data-task=\"0\" disabled> a</li>"));
+        assert!(
+            html.contains("<li><input type=\"checkbox\" data-task=\"1\" disabled checked> b</li>")
+        );
+    }
+
+    #[test]
+    fn toggle_tasks() {
+        let source = "# Todo\n\n- [ ] a\r\n- [x] b\n";
+        let found = tasks(source);
+        assert_eq!(
+            set_checked(source, &found[0], true),
+            "# Todo\n\n- [x] a\r\n- [x] b\n"
+        );
+        assert_eq!(
+            set_checked(source, &found[1], false),
+            "# Todo\n\n- [ ] a\r\n- [ ] b\n"
+        );
+
+        assert_eq!(freeze(source), "# Todo\n\n- \u{2610} a\r\n- \u{2611} b\n");
+        assert!(tasks(&freeze(source)).is_empty());
+    }
+}
@@ -0,0 +1,186 @@
+//! Resizing of images.
+//!
+//! Resized images are cached on disk. The cache key consists of the blob id and the requested
+//! size; cache entries therefore never have to be invalidated.
+
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use git2::Oid;
+
+use image::imageops::FilterType;
+use image::{ImageError, ImageFormat, ImageOutputFormat};
+
+use log::warn;
+
+use crate::command::write_cache_file;
+use crate::config::ImageConfig;
+use crate::Filetype;
+
+/// A requested image size. Missing dimensions are not restricted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageSize {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+impl ImageSize {
+    /// Limits both dimensions to `max`.
+    pub fn clamp(self, max: u32) -> ImageSize {
+        ImageSize {
+            width: self.width.map(|width| width.min(max).max(1)),
+            height: self.height.map(|height| height.min(max).max(1)),
+        }
+    }
+}
+
+pub struct Thumbnailer<'a> {
+    config: &'a ImageConfig,
+}
+impl<'a> Thumbnailer<'a> {
+    pub fn new(config: &'a ImageConfig) -> Thumbnailer<'a> {
+        Thumbnailer { config }
+    }
+
+    /// Returns the filetype of resized images. JPEG images stay JPEG, everything else is
+    /// converted to PNG.
+    pub fn output_filetype(filetype: &Filetype) -> Filetype {
+        match *filetype {
+            Filetype::Jpg => Filetype::Jpg,
+            _ => Filetype::Png,
+        }
+    }
+
+    /// Resizes an image to fit within the given size while keeping its aspect ratio.
+    ///
+    /// Returns `None` if the image already fits; images are never enlarged. `oid` has to be the
+    /// id of the blob containing `content`.
+    pub fn resize(
+        &self,
+        oid: Oid,
+        content: &[u8],
+        filetype: &Filetype,
+        size: ImageSize,
+    ) -> Result<Option<Vec<u8>>, ImageError> {
+        let size = size.clamp(self.config.max_size);
+        let output_filetype = Self::output_filetype(filetype);
+
+        let cache_file = self.cache_file(oid, size, &output_filetype);
+        if let Ok(cached) = std::fs::read(&cache_file) {
+            return Ok(Some(cached));
+        }
+
+        let format = match *filetype {
+            Filetype::Png => ImageFormat::Png,
+            Filetype::Jpg => ImageFormat::Jpeg,
+            Filetype::Gif => ImageFormat::Gif,
+            Filetype::WebP => ImageFormat::WebP,
+            _ => panic!("Attempted to resize non-image filetype"),
+        };
+        let image = image::load_from_memory_with_format(content, format)?;
+
+        let width = size.width.unwrap_or(u32::MAX);
+        let height = size.height.unwrap_or(u32::MAX);
+        if image.width() <= width && image.height() <= height {
+            return Ok(None);
+        }
+
+        let resized = image.resize(width, height, FilterType::Triangle);
+        let mut data = vec![];
+        let output_format = match output_filetype {
+            Filetype::Jpg => ImageOutputFormat::Jpeg(85),
+            _ => ImageOutputFormat::Png,
+        };
+        resized.write_to(&mut Cursor::new(&mut data), output_format)?;
+
+        // The resized image is still usable if it cannot be cached.
+        if let Err(err) = write_cache_file(&cache_file, &data) {
+            warn!("Could not cache resized image {:?}: {}", cache_file, err);
+        }
+
+        Ok(Some(data))
+    }
+
+    fn cache_file(&self, oid: Oid, size: ImageSize, output_filetype: &Filetype) -> PathBuf {
+        let extension = match *output_filetype {
+            Filetype::Jpg => "jpg",
+            _ => "png",
+        };
+        PathBuf::from(&self.config.cache).join(format!(
+            "{}-{}x{}.{}",
+            oid,
+            size.width.unwrap_or(0),
+            size.height.unwrap_or(0),
+            extension
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use git2::Oid;
+    use image::{GenericImageView, ImageOutputFormat, RgbImage};
+    use tempdir::TempDir;
+
+    use crate::config::ImageConfig;
+    use crate::thumbnail::{ImageSize, Thumbnailer};
+    use crate::Filetype;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = vec![];
+        image::DynamicImage::ImageRgb8(RgbImage::new(width, height))
+            .write_to(&mut Cursor::new(&mut data), ImageOutputFormat::Png)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn resize_and_cache() {
+        let tmp = TempDir::new("smeagol").unwrap();
+        let config = ImageConfig {
+            cache: tmp.path().to_str().unwrap().to_string(),
+            ..ImageConfig::default()
+        };
+        let thumbnailer = Thumbnailer::new(&config);
+        let oid = Oid::from_str("0123456789012345678901234567890123456789").unwrap();
+        let size = ImageSize {
+            width: Some(50),
+            height: None,
+        };
+
+        let resized = thumbnailer
+            .resize(oid, &png(200, 100), &Filetype::Png, size)
+            .unwrap()
+            .unwrap();
+        let image = image::load_from_memory(&resized).unwrap();
+        assert_eq!(image.dimensions(), (50, 25));
+        assert!(tmp.path().join(format!("{}-50x0.png", oid)).exists());
+
+        // The cached image is used even if the content differs.
+        let cached = thumbnailer
+            .resize(oid, &[], &Filetype::Png, size)
+            .unwrap()
+            .unwrap();
+        assert_eq!(cached, resized);
+    }
+
+    #[test]
+    fn never_enlarge() {
+        let tmp = TempDir::new("smeagol").unwrap();
+        let config = ImageConfig {
+            cache: tmp.path().to_str().unwrap().to_string(),
+            ..ImageConfig::default()
+        };
+        let oid = Oid::from_str("0123456789012345678901234567890123456789").unwrap();
+        let size = ImageSize {
+            width: Some(500),
+            height: Some(500),
+        };
+
+        let resized = Thumbnailer::new(&config)
+            .resize(oid, &png(200, 100), &Filetype::Png, size)
+            .unwrap();
+        assert!(resized.is_none());
+    }
+}
@@ -0,0 +1,279 @@
+//! Upload of several files in a single commit.
+//!
+//! Files are uploaded into a directory under their own name. Existing files are handled according
+//! to an `OverwritePolicy`. Files that cannot be stored (invalid names, invalid content for their
+//! filetype, a directory at their path) are rejected; the remaining files are committed together.
+//! With `OverwritePolicy::Abort` nothing is committed if any file is rejected.
+
+use serde::Serialize;
+
+use crate::git::{BlobStream, GitError};
+use crate::{Config, Filetype, GitRepository, Path};
+
+/// How files that already exist are handled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverwritePolicy {
+    /// Existing files are kept and the uploaded ones skipped.
+    Skip,
+    /// Existing files are replaced.
+    Replace,
+    /// The upload fails if any file exists.
+    Abort,
+}
+impl OverwritePolicy {
+    /// Parses the value of the `overwrite` form field.
+    pub fn parse(value: &str) -> Option<OverwritePolicy> {
+        match value {
+            "skip" => Some(OverwritePolicy::Skip),
+            "replace" => Some(OverwritePolicy::Replace),
+            "abort" => Some(OverwritePolicy::Abort),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadStatus {
+    Added,
+    Replaced,
+    /// The file exists with the same content.
+    Unchanged,
+    /// The file exists and was kept, or the upload was aborted because of other files.
+    Skipped,
+    Rejected,
+}
+
+/// The result of uploading a file.
+#[derive(Debug)]
+pub struct UploadedFile {
+    /// Path in the repository. It is the name given by the client if that is invalid.
+    pub path: Path,
+    pub status: UploadStatus,
+    /// Why the file was skipped or rejected.
+    pub error: Option<String>,
+}
+
+pub struct Uploader<'a> {
+    repo: &'a GitRepository,
+    config: &'a Config,
+    /// Directory in the repository the files are uploaded to.
+    directory: Path,
+    policy: OverwritePolicy,
+}
+impl<'a> Uploader<'a> {
+    pub fn new(
+        repo: &'a GitRepository,
+        config: &'a Config,
+        directory: Path,
+        policy: OverwritePolicy,
+    ) -> Uploader<'a> {
+        Uploader {
+            repo,
+            config,
+            directory,
+            policy,
+        }
+    }
+
+    /// Commits the accepted files (given by name and content) in a single commit and returns the
+    /// result for every file, in the given order.
+    ///
+    /// Blobs are only created for accepted files.
+    pub fn upload(
+        &self,
+        files: Vec<(String, BlobStream)>,
+        message: &str,
+    ) -> Result<Vec<UploadedFile>, GitError> {
+        let mut results = vec![];
+        let mut accepted = vec![];
+        for (name, content) in files {
+            let (path, status) = match self.check(&name, &content, &results)? {
+                Ok(checked) => checked,
+                Err(rejected) => {
+                    results.push(rejected);
+                    continue;
+                }
+            };
+            if status != UploadStatus::Skipped {
+                accepted.push((path.clone(), content));
+            }
+            results.push(UploadedFile {
+                path,
+                status,
+                error: None,
+            });
+        }
+
+        let rejected = results
+            .iter()
+            .any(|file| file.status == UploadStatus::Rejected);
+        if self.policy == OverwritePolicy::Abort && rejected {
+            for file in results.iter_mut() {
+                if file.status != UploadStatus::Rejected {
+                    file.status = UploadStatus::Skipped;
+                    file.error = Some("Other files were rejected.".to_string());
+                }
+            }
+            return Ok(results);
+        }
+        if accepted.is_empty() {
+            return Ok(results);
+        }
+        let accepted = accepted
+            .into_iter()
+            .map(|(path, content)| Ok((path, content.commit(self.repo)?)))
+            .collect::<Result<Vec<_>, GitError>>()?;
+
+        let changed = match self.repo.add_files(&accepted, message) {
+            Ok(changed) => changed,
+            Err(GitError::NoChange) => vec![],
+            Err(err) => return Err(err),
+        };
+        for file in results.iter_mut() {
+            if file.status == UploadStatus::Replaced && !changed.contains(&file.path) {
+                file.status = UploadStatus::Unchanged;
+            }
+        }
+        Ok(results)
+    }
+
+    /// Returns the path and status of a file that can be uploaded, or the result of a rejected
+    /// file. `previous` are the results of the files before it.
+    fn check(
+        &self,
+        name: &str,
+        content: &BlobStream,
+        previous: &[UploadedFile],
+    ) -> Result<Result<(Path, UploadStatus), UploadedFile>, GitError> {
+        let rejected = |path: Path, error: &str| {
+            Ok(Err(UploadedFile {
+                path,
+                status: UploadStatus::Rejected,
+                error: Some(error.to_string()),
+            }))
+        };
+
+        // Browsers only send the name of a file, but other clients may include its directory.
+        let filename = name.rsplit(['/', '\\']).next().unwrap_or("");
+        if filename.is_empty() || filename == "." || filename == ".." {
+            return rejected(Path::from(name.to_string()), "Invalid file name.");
+        }
+        let mut path = self.directory.clone();
+        path.push(Path::from(filename.to_string()));
+
+        if previous.iter().any(|file| file.path == path) {
+            return rejected(path, "Another file has the same name.");
+        }
+        let item = self.repo.item(path.clone())?;
+        let status = if !item.exists()? {
+            if !item.can_exist()? {
+                return rejected(path, "A file cannot be created at that location.");
+            }
+            UploadStatus::Added
+        } else if item.is_dir()? {
+            return rejected(path, "A directory with this name exists.");
+        } else {
+            match self.policy {
+                OverwritePolicy::Skip => UploadStatus::Skipped,
+                OverwritePolicy::Replace => UploadStatus::Replaced,
+                OverwritePolicy::Abort => return rejected(path, "The file already exists."),
+            }
+        };
+        let filetype = Filetype::for_path(&path, self.config);
+        // Only data files are validated, other files are not read.
+        if status != UploadStatus::Skipped && filetype.is_data() {
+            if let Err(err) = filetype.validate(&content.content()?) {
+                return rejected(path, &format!("Invalid file: {}", err));
+            }
+        }
+        Ok(Ok((path, status)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use crate::git::BlobStream;
+    use crate::upload::{OverwritePolicy, UploadStatus, Uploader};
+    use crate::{Config, GitRepository, Path};
+
+    #[test]
+    fn upload() {
+        let tmp = TempDir::new("smeagol").unwrap();
+        let repo = GitRepository::new(tmp.path()).unwrap();
+        let config: Config =
+            toml::from_str("bind = \"\"\nindex = \"\"\nmax_upload_size = 0\nrepo = \"\"").unwrap();
+        for (path, content) in &[
+            ("docs/a.md", "A"),
+            ("docs/b.md", "B"),
+            ("docs/sub/c.md", "C"),
+        ] {
+            repo.item(Path::from(path.to_string()))
+                .unwrap()
+                .edit(content.as_bytes(), "Add")
+                .unwrap();
+        }
+        let files = || {
+            [
+                ("a.md", "A"),
+                ("b.md", "changed"),
+                ("new.md", "new"),
+                ("sub", "not a directory"),
+                ("data.json", "{"),
+                ("../outside.md", "x"),
+                ("..", "x"),
+            ]
+            .iter()
+            .map(|(name, content)| {
+                let mut stream = BlobStream::new(tmp.path()).unwrap();
+                stream.write(content.as_bytes()).unwrap();
+                (name.to_string(), stream)
+            })
+            .collect::<Vec<_>>()
+        };
+        let statuses = |policy| {
+            let uploader = Uploader::new(&repo, &config, Path::from("docs".to_string()), policy);
+            uploader
+                .upload(files(), "Upload")
+                .unwrap()
+                .iter()
+                .map(|file| (file.path.to_string(), file.status))
+                .collect::<Vec<_>>()
+        };
+        let content = |path: &str| {
+            repo.item(Path::from(path.to_string()))
+                .unwrap()
+                .content()
+                .ok()
+        };
+
+        assert_eq!(
+            statuses(OverwritePolicy::Abort),
+            vec![
+                ("docs/a.md".to_string(), UploadStatus::Rejected),
+                ("docs/b.md".to_string(), UploadStatus::Rejected),
+                ("docs/new.md".to_string(), UploadStatus::Skipped),
+                ("docs/sub".to_string(), UploadStatus::Rejected),
+                ("docs/data.json".to_string(), UploadStatus::Rejected),
+                ("docs/outside.md".to_string(), UploadStatus::Skipped),
+                ("..".to_string(), UploadStatus::Rejected),
+            ]
+        );
+        assert_eq!(content("docs/new.md"), None);
+
+        let skipped = statuses(OverwritePolicy::Skip);
+        assert_eq!(skipped[1].1, UploadStatus::Skipped);
+        assert_eq!(skipped[2].1, UploadStatus::Added);
+        assert_eq!(content("docs/b.md"), Some(b"B".to_vec()));
+        assert_eq!(content("docs/outside.md"), Some(b"x".to_vec()));
+
+        let replaced = statuses(OverwritePolicy::Replace);
+        assert_eq!(replaced[0].1, UploadStatus::Unchanged);
+        assert_eq!(replaced[1].1, UploadStatus::Replaced);
+        assert_eq!(replaced[2].1, UploadStatus::Unchanged);
+        assert_eq!(content("docs/b.md"), Some(b"changed".to_vec()));
+        assert_eq!(content("docs/data.json"), None);
+    }
+}
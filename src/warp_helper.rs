@@ -1,36 +1,69 @@
 use std::fmt;
+use std::io::Write;
+use std::ops::Bound;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use handlebars::Handlebars;
 
+use headers::{HeaderMapExt, IfModifiedSince, IfRange, LastModified};
+
 use serde::Serialize;
 
-use warp::http::header::{HeaderName, HeaderValue, InvalidHeaderValue};
+use warp::http::header::{self, HeaderMap, HeaderName, HeaderValue, InvalidHeaderValue};
 use warp::http::response::Builder as HttpResponseBuilder;
 use warp::http::status::StatusCode;
 use warp::http::{HttpTryFrom, Response};
 
+use crate::config::FiletypeRule;
 use crate::path::{Path, PathStringBuilder};
-use crate::SmeagolError;
+use crate::{Filetype, SmeagolError};
+
+/// Smaller bodies are not compressed; the savings would not be worth the time.
+const MIN_COMPRESS_SIZE: usize = 256;
 
 pub enum ContentType {
     Plain,
     Html,
     Markdown,
     Json,
+    Csv,
+    Tsv,
+    Css,
+    JavaScript,
     Png,
     Jpg,
+    Gif,
+    WebP,
+    Svg,
+    Pdf,
+    Mp3,
+    Ogg,
+    Mp4,
+    WebM,
     Binary,
 }
 impl fmt::Display for ContentType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            &ContentType::Plain => write!(f, "text/plain; charset=utf-8"),
-            &ContentType::Html => write!(f, "text/html; charset=utf-8"),
-            &ContentType::Markdown => write!(f, "application/markdown; charset=utf-8"),
-            &ContentType::Json => write!(f, "application/json"),
-            &ContentType::Png => write!(f, "image/png"),
-            &ContentType::Jpg => write!(f, "image/jpeg"),
-            &ContentType::Binary => write!(f, "application/octet-stream"),
+        match *self {
+            ContentType::Plain => write!(f, "text/plain; charset=utf-8"),
+            ContentType::Html => write!(f, "text/html; charset=utf-8"),
+            ContentType::Markdown => write!(f, "text/markdown; charset=utf-8"),
+            ContentType::Json => write!(f, "application/json"),
+            ContentType::Csv => write!(f, "text/csv; charset=utf-8"),
+            ContentType::Tsv => write!(f, "text/tab-separated-values; charset=utf-8"),
+            ContentType::Css => write!(f, "text/css; charset=utf-8"),
+            ContentType::JavaScript => write!(f, "application/javascript; charset=utf-8"),
+            ContentType::Png => write!(f, "image/png"),
+            ContentType::Jpg => write!(f, "image/jpeg"),
+            ContentType::Gif => write!(f, "image/gif"),
+            ContentType::WebP => write!(f, "image/webp"),
+            ContentType::Svg => write!(f, "image/svg+xml"),
+            ContentType::Pdf => write!(f, "application/pdf"),
+            ContentType::Mp3 => write!(f, "audio/mpeg"),
+            ContentType::Ogg => write!(f, "audio/ogg"),
+            ContentType::Mp4 => write!(f, "video/mp4"),
+            ContentType::WebM => write!(f, "video/webm"),
+            ContentType::Binary => write!(f, "application/octet-stream"),
         }
     }
 }
@@ -41,6 +74,113 @@ impl HttpTryFrom<ContentType> for HeaderValue {
     }
 }
 
+/// Entity tag and modification time of a response, used to answer conditional requests.
+pub struct Validators {
+    /// The entity tag including quotes and `W/` for weak tags.
+    etag: String,
+    last_modified: Option<SystemTime>,
+}
+impl Validators {
+    /// Validators of a response that is identical byte for byte as long as `tag` is the same.
+    pub fn strong<T: fmt::Display>(tag: T, last_modified: Option<i64>) -> Validators {
+        Validators {
+            etag: format!("\"{}\"", tag),
+            last_modified: last_modified.map(Self::time),
+        }
+    }
+
+    /// Validators of a response whose meaning stays the same as long as `tag` is the same, e.g.
+    /// a rendered page.
+    pub fn weak<T: fmt::Display>(tag: T, last_modified: Option<i64>) -> Validators {
+        Validators {
+            etag: format!("W/\"{}\"", tag),
+            last_modified: last_modified.map(Self::time),
+        }
+    }
+
+    fn time(seconds: i64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds.max(0) as u64)
+    }
+
+    /// Whether the copy cached by the client is still current, i.e. `304 Not Modified` can be
+    /// returned. `If-None-Match` takes precedence over `If-Modified-Since`.
+    pub fn is_fresh(&self, request: &HeaderMap) -> bool {
+        let mut if_none_match = request.get_all(header::IF_NONE_MATCH).iter().peekable();
+        if if_none_match.peek().is_some() {
+            // GET requests use the weak comparison.
+            let etag = self.etag.trim_start_matches("W/");
+            return if_none_match
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+        }
+        match (request.typed_get::<IfModifiedSince>(), self.last_modified) {
+            (Some(since), Some(last_modified)) => !since.is_modified(last_modified),
+            _ => false,
+        }
+    }
+}
+
+/// The part of a file requested by a `Range` header.
+#[derive(Debug, PartialEq)]
+enum ByteRange {
+    Full,
+    /// First and last byte (inclusive).
+    Partial(usize, usize),
+    Unsatisfiable,
+}
+impl ByteRange {
+    fn requested(request: &HeaderMap, validators: &Validators, length: usize) -> ByteRange {
+        let range = match request.typed_get::<headers::Range>() {
+            Some(range) => range,
+            None => return ByteRange::Full,
+        };
+        if let Some(if_range) = request.get(header::IF_RANGE) {
+            let if_range = if_range.to_str().unwrap_or("");
+            let unchanged = if if_range.starts_with('"') || if_range.starts_with("W/") {
+                // Entity tags are compared strongly.
+                !validators.etag.starts_with("W/") && if_range == validators.etag
+            } else {
+                match (request.typed_get::<IfRange>(), validators.last_modified) {
+                    (Some(if_range), Some(last_modified)) => {
+                        !if_range.is_modified(None, Some(&LastModified::from(last_modified)))
+                    }
+                    _ => false,
+                }
+            };
+            if !unchanged {
+                return ByteRange::Full;
+            }
+        }
+
+        let mut ranges = range.iter();
+        let (start, end) = match (ranges.next(), ranges.next()) {
+            (Some(bounds), None) => bounds,
+            _ => return ByteRange::Full,
+        };
+        // No range of an empty file can be satisfied, and the bounds below need a last byte.
+        if length == 0 {
+            return ByteRange::Unsatisfiable;
+        }
+        let length = length as u64;
+        let (start, end) = match (start, end) {
+            (Bound::Included(start), Bound::Included(end)) => (start, end.min(length - 1)),
+            (Bound::Included(start), Bound::Unbounded) => (start, length - 1),
+            // Suffix ranges contain the last bytes.
+            (Bound::Unbounded, Bound::Included(suffix)) if suffix > 0 => {
+                (length.saturating_sub(suffix), length - 1)
+            }
+            _ => return ByteRange::Unsatisfiable,
+        };
+        if start >= length || start > end {
+            ByteRange::Unsatisfiable
+        } else {
+            ByteRange::Partial(start as usize, end as usize)
+        }
+    }
+}
+
 pub struct ResponseBuilder {
     builder: HttpResponseBuilder,
 }
@@ -68,6 +208,47 @@ impl ResponseBuilder {
         self
     }
 
+    /// Adds the `ETag` and `Last-Modified` headers.
+    pub fn validators(&mut self, validators: &Validators) -> &mut ResponseBuilder {
+        self.header(header::ETAG, &validators.etag[..]);
+        if let Some(last_modified) = validators.last_modified {
+            let mut headers = HeaderMap::new();
+            headers.typed_insert(LastModified::from(last_modified));
+            // The header was just inserted.
+            self.header(
+                header::LAST_MODIFIED,
+                headers[header::LAST_MODIFIED].clone(),
+            );
+        }
+        self
+    }
+
+    /// Returns `304 Not Modified` for a conditional request whose cached copy is still current.
+    pub fn not_modified(&mut self, validators: &Validators) -> Response<Vec<u8>> {
+        self.status(304).validators(validators).body(vec![])
+    }
+
+    /// Sets the content type of a file served directly.
+    ///
+    /// Content sniffing by the browser is disabled and a restrictive content security policy is
+    /// added if the filetype requires one. The content type of a `[filetypes]` rule takes
+    /// precedence.
+    pub fn filetype(
+        &mut self,
+        filetype: &Filetype,
+        rule: Option<&FiletypeRule>,
+    ) -> &mut ResponseBuilder {
+        match rule.and_then(|rule| rule.content_type.as_ref()) {
+            Some(content_type) => self.header(warp::http::header::CONTENT_TYPE, &content_type[..]),
+            None => self.header(warp::http::header::CONTENT_TYPE, filetype.content_type()),
+        }
+        .header(warp::http::header::X_CONTENT_TYPE_OPTIONS, "nosniff");
+        if let Some(policy) = filetype.content_security_policy() {
+            self.header(warp::http::header::CONTENT_SECURITY_POLICY, policy);
+        }
+        self
+    }
+
     pub fn body<T>(&mut self, body: T) -> Response<T> {
         // ResponseBuilder.body() cannot return Err(...) currently (checked in code). This may
         // change in the future though.
@@ -96,9 +277,38 @@ impl ResponseBuilder {
             .body(serde_json::to_string(data)?.into_bytes()))
     }
 
-    pub fn body_download(&mut self, data: Vec<u8>) -> Response<Vec<u8>> {
+    /// Offers the body for download instead of displaying it.
+    pub fn download(&mut self) -> &mut ResponseBuilder {
         self.header(warp::http::header::CONTENT_DISPOSITION, "attachment")
-            .body(data)
+    }
+
+    /// Sends the content of a file, or only the part of it requested by a `Range` header
+    /// (`206 Partial Content`).
+    ///
+    /// Only single byte ranges are supported, other range requests get the whole content. With
+    /// `If-Range` the range is only sent if the content still matches `validators`.
+    pub fn body_range(
+        &mut self,
+        request: &HeaderMap,
+        validators: &Validators,
+        content: Vec<u8>,
+    ) -> Response<Vec<u8>> {
+        self.header(header::ACCEPT_RANGES, "bytes");
+        let length = content.len();
+        match ByteRange::requested(request, validators, length) {
+            ByteRange::Full => self.status(200).body(content),
+            ByteRange::Partial(start, end) => self
+                .status(206)
+                .header(
+                    header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", start, end, length),
+                )
+                .body(content[start..=end].to_vec()),
+            ByteRange::Unsatisfiable => self
+                .status(416)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", length))
+                .body(vec![]),
+        }
     }
 
     pub fn redirect(&mut self, destination: Path) -> Response<Vec<u8>> {
@@ -111,4 +321,401 @@ impl ResponseBuilder {
             )
             .body(vec![])
     }
+
+    /// Redirects to `destination` with the given query string (without `?`).
+    pub fn redirect_query(&mut self, destination: Path, query: &str) -> Response<Vec<u8>> {
+        self.status(302)
+            .header(
+                warp::http::header::LOCATION,
+                format!(
+                    "{}?{}",
+                    PathStringBuilder::new(destination)
+                        .root(true)
+                        .build_percent_encode(),
+                    query
+                ),
+            )
+            .body(vec![])
+    }
+}
+
+/// Returns the media type of `offered` the client prefers according to its `Accept` header.
+///
+/// Quality values and wildcards (`text/*`, `*/*`) are supported; the most specific matching range
+/// determines the quality of a type. Ties are resolved by the order of `offered`. If the header
+/// is missing or accepts none of the types, the first type is returned.
+pub fn negotiate<'a>(accept: Option<&str>, offered: &[&'a str]) -> &'a str {
+    let ranges = match accept {
+        Some(accept) => quality_values(accept),
+        None => return offered[0],
+    };
+
+    let mut best = (offered[0], 0.0);
+    for media_type in offered {
+        // The specificity is 2 for exact matches, 1 for `type/*` and 0 for `*/*`.
+        let quality = ranges
+            .iter()
+            .filter_map(|(range, quality)| {
+                if range == media_type {
+                    Some((2, *quality))
+                } else if range
+                    .strip_suffix('*')
+                    .is_some_and(|prefix| prefix.ends_with('/') && media_type.starts_with(prefix))
+                {
+                    Some((1, *quality))
+                } else if range == "*/*" {
+                    Some((0, *quality))
+                } else {
+                    None
+                }
+            })
+            .max_by(|a, b| a.0.cmp(&b.0))
+            .map_or(0.0, |(_, quality)| quality);
+        if quality > best.1 {
+            best = (media_type, quality);
+        }
+    }
+    best.0
+}
+
+/// Parses a header like `Accept` into its values (in lowercase) and their quality.
+fn quality_values(header: &str) -> Vec<(String, f32)> {
+    header
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let value = parts.next()?.trim().to_lowercase();
+            let quality = parts
+                .filter_map(|parameter| {
+                    let (key, value) = parameter.split_once('=')?;
+                    if key.trim() == "q" {
+                        value.trim().parse::<f32>().ok()
+                    } else {
+                        None
+                    }
+                })
+                .next()
+                .unwrap_or(1.0);
+            Some((value, quality))
+        })
+        .collect()
+}
+
+/// Content coding of a response body.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+    Identity,
+}
+impl Encoding {
+    /// Returns the coding the client prefers according to its `Accept-Encoding` header. Brotli is
+    /// preferred over gzip if both are accepted equally.
+    pub fn negotiate(accept_encoding: Option<&str>) -> Encoding {
+        let codings = quality_values(accept_encoding.unwrap_or(""));
+        let mut best = (Encoding::Identity, 0.0);
+        for encoding in &[Encoding::Brotli, Encoding::Gzip] {
+            let quality = codings
+                .iter()
+                .find(|(coding, _)| Some(&coding[..]) == encoding.name())
+                .or_else(|| codings.iter().find(|(coding, _)| coding == "*"))
+                .map_or(0.0, |(_, quality)| *quality);
+            if quality > best.1 {
+                best = (*encoding, quality);
+            }
+        }
+        best.0
+    }
+
+    /// Returns the value of the `Content-Encoding` header.
+    pub fn name(&self) -> Option<&'static str> {
+        match *self {
+            Encoding::Brotli => Some("br"),
+            Encoding::Gzip => Some("gzip"),
+            Encoding::Identity => None,
+        }
+    }
+
+    /// Compresses `data`. `best` trades speed for a smaller result, e.g. for content that is
+    /// compressed only once.
+    pub fn compress(&self, data: &[u8], best: bool) -> Vec<u8> {
+        // Writing to a Vec cannot fail.
+        match *self {
+            Encoding::Brotli => {
+                let mut compressed = vec![];
+                {
+                    let quality = if best { 11 } else { 5 };
+                    let mut writer =
+                        brotli::CompressorWriter::new(&mut compressed, 4096, quality, 22);
+                    writer.write_all(data).unwrap();
+                }
+                compressed
+            }
+            Encoding::Gzip => {
+                let level = if best {
+                    flate2::Compression::best()
+                } else {
+                    flate2::Compression::default()
+                };
+                let mut writer = flate2::write::GzEncoder::new(vec![], level);
+                writer.write_all(data).unwrap();
+                writer.finish().unwrap()
+            }
+            Encoding::Identity => data.to_vec(),
+        }
+    }
+}
+
+/// Whether responses of a media type are worth compressing. Images, audio, video and archives
+/// are mostly compressed already.
+pub fn is_compressible(content_type: &str) -> bool {
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_lowercase();
+    media_type.starts_with("text/")
+        || media_type == "application/json"
+        || media_type == "application/javascript"
+        || media_type == "application/xml"
+        || media_type == "image/svg+xml"
+}
+
+/// Compresses a response with the coding the client accepts, if its content type is
+/// compressible.
+///
+/// Partial and empty responses are left alone. The entity tag of a compressed response is made
+/// weak, since its bytes differ from the uncompressed ones, and ranges are only offered for the
+/// uncompressed content.
+pub fn compress(
+    accept_encoding: Option<&str>,
+    mut response: Response<Vec<u8>>,
+) -> Response<Vec<u8>> {
+    let compressible = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(is_compressible);
+    if !compressible {
+        return response;
+    }
+    response
+        .headers_mut()
+        .append(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+
+    let encoding = Encoding::negotiate(accept_encoding);
+    let name = match encoding.name() {
+        Some(name) => name,
+        None => return response,
+    };
+    if response.status() != StatusCode::OK
+        || response.body().len() < MIN_COMPRESS_SIZE
+        || response.headers().contains_key(header::CONTENT_ENCODING)
+    {
+        return response;
+    }
+
+    let compressed = encoding.compress(response.body(), false);
+    *response.body_mut() = compressed;
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(name));
+    headers.remove(header::ACCEPT_RANGES);
+    let etag = headers
+        .get(header::ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .filter(|etag| !etag.starts_with("W/"))
+        .map(|etag| format!("W/{}", etag));
+    if let Some(etag) = etag {
+        // Entity tags are valid header values.
+        headers.insert(header::ETAG, HeaderValue::from_str(&etag).unwrap());
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use warp::http::header::{self, HeaderMap};
+
+    use std::io::Read;
+
+    use crate::warp_helper::{
+        compress, negotiate, ByteRange, Encoding, ResponseBuilder, Validators,
+    };
+
+    #[test]
+    fn negotiation() {
+        let offered = ["text/html", "text/markdown", "application/json"];
+        assert_eq!(negotiate(None, &offered), "text/html");
+        assert_eq!(negotiate(Some("*/*"), &offered), "text/html");
+        assert_eq!(
+            negotiate(
+                Some("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"),
+                &offered
+            ),
+            "text/html"
+        );
+        assert_eq!(negotiate(Some("text/markdown"), &offered), "text/markdown");
+        assert_eq!(
+            negotiate(Some("application/json, text/*;q=0.5"), &offered),
+            "application/json"
+        );
+        assert_eq!(
+            negotiate(Some("text/*;q=0.5, text/html;q=0.1"), &offered),
+            "text/markdown"
+        );
+        assert_eq!(negotiate(Some("image/png"), &offered), "text/html");
+    }
+
+    #[test]
+    fn conditional_requests() {
+        let validators = Validators::weak("abc", Some(1_000_000_000));
+        let request = |name, value: &str| {
+            let mut request = HeaderMap::new();
+            request.insert(name, value.parse().unwrap());
+            request
+        };
+        assert!(!validators.is_fresh(&HeaderMap::new()));
+        assert!(validators.is_fresh(&request(header::IF_NONE_MATCH, "\"x\", W/\"abc\"")));
+        assert!(validators.is_fresh(&request(header::IF_NONE_MATCH, "*")));
+        assert!(!validators.is_fresh(&request(header::IF_NONE_MATCH, "\"abcd\"")));
+        assert!(validators.is_fresh(&request(
+            header::IF_MODIFIED_SINCE,
+            "Sun, 09 Sep 2001 01:46:40 GMT"
+        )));
+        assert!(!validators.is_fresh(&request(
+            header::IF_MODIFIED_SINCE,
+            "Sun, 09 Sep 2001 01:46:39 GMT"
+        )));
+        assert!(!Validators::strong("abc", None).is_fresh(&request(
+            header::IF_MODIFIED_SINCE,
+            "Sun, 09 Sep 2001 01:46:40 GMT"
+        )));
+    }
+
+    #[test]
+    fn byte_ranges() {
+        let validators = Validators::strong("abc", Some(1_000_000_000));
+        let requested = |headers: &[(header::HeaderName, &str)]| {
+            let mut request = HeaderMap::new();
+            for (name, value) in headers {
+                request.insert(name.clone(), value.parse().unwrap());
+            }
+            ByteRange::requested(&request, &validators, 100)
+        };
+        assert_eq!(requested(&[]), ByteRange::Full);
+        assert_eq!(
+            requested(&[(header::RANGE, "bytes=10-19")]),
+            ByteRange::Partial(10, 19)
+        );
+        assert_eq!(
+            requested(&[(header::RANGE, "bytes=90-200")]),
+            ByteRange::Partial(90, 99)
+        );
+        assert_eq!(
+            requested(&[(header::RANGE, "bytes=50-")]),
+            ByteRange::Partial(50, 99)
+        );
+        assert_eq!(
+            requested(&[(header::RANGE, "bytes=-10")]),
+            ByteRange::Partial(90, 99)
+        );
+        assert_eq!(
+            requested(&[(header::RANGE, "bytes=100-")]),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(
+            requested(&[(header::RANGE, "bytes=0-1,5-6")]),
+            ByteRange::Full
+        );
+        assert_eq!(
+            requested(&[(header::RANGE, "bytes=0-9"), (header::IF_RANGE, "\"abc\"")]),
+            ByteRange::Partial(0, 9)
+        );
+        assert_eq!(
+            requested(&[(header::RANGE, "bytes=0-9"), (header::IF_RANGE, "\"old\"")]),
+            ByteRange::Full
+        );
+        assert_eq!(
+            requested(&[
+                (header::RANGE, "bytes=0-9"),
+                (header::IF_RANGE, "Sun, 09 Sep 2001 01:46:40 GMT")
+            ]),
+            ByteRange::Partial(0, 9)
+        );
+        assert_eq!(
+            requested(&[
+                (header::RANGE, "bytes=0-9"),
+                (header::IF_RANGE, "Sun, 09 Sep 2001 01:46:39 GMT")
+            ]),
+            ByteRange::Full
+        );
+
+        let mut request = HeaderMap::new();
+        for range in &["bytes=0-0", "bytes=0-", "bytes=-1"] {
+            request.insert(header::RANGE, range.parse().unwrap());
+            assert_eq!(
+                ByteRange::requested(&request, &validators, 0),
+                ByteRange::Unsatisfiable
+            );
+        }
+    }
+
+    #[test]
+    fn encodings() {
+        assert_eq!(Encoding::negotiate(None), Encoding::Identity);
+        assert_eq!(
+            Encoding::negotiate(Some("gzip, deflate, br")),
+            Encoding::Brotli
+        );
+        assert_eq!(Encoding::negotiate(Some("gzip, br;q=0.5")), Encoding::Gzip);
+        assert_eq!(Encoding::negotiate(Some("*")), Encoding::Brotli);
+        assert_eq!(Encoding::negotiate(Some("br;q=0, *;q=0.1")), Encoding::Gzip);
+        assert_eq!(Encoding::negotiate(Some("deflate")), Encoding::Identity);
+
+        let text = "Some text. ".repeat(100).into_bytes();
+        let mut decoded = vec![];
+        flate2::read::GzDecoder::new(&Encoding::Gzip.compress(&text, false)[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, text);
+        let mut decoded = vec![];
+        brotli::Decompressor::new(&Encoding::Brotli.compress(&text, true)[..], 4096)
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, text);
+    }
+
+    #[test]
+    fn compression() {
+        let text = "Some text. ".repeat(100).into_bytes();
+        let response = |content_type: &str, body: &[u8]| {
+            ResponseBuilder::new()
+                .header(header::CONTENT_TYPE, content_type)
+                .header(header::ETAG, "\"abc\"")
+                .status(200)
+                .body(body.to_vec())
+        };
+
+        let compressed = compress(Some("gzip"), response("text/html; charset=utf-8", &text));
+        assert_eq!(compressed.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(compressed.headers()[header::VARY], "Accept-Encoding");
+        assert_eq!(compressed.headers()[header::ETAG], "W/\"abc\"");
+        assert!(compressed.body().len() < text.len());
+
+        let uncompressed = compress(None, response("application/json", &text));
+        assert!(uncompressed
+            .headers()
+            .get(header::CONTENT_ENCODING)
+            .is_none());
+        assert_eq!(uncompressed.headers()[header::VARY], "Accept-Encoding");
+        assert_eq!(uncompressed.body(), &text);
+
+        let image = compress(Some("br"), response("image/png", &text));
+        assert!(image.headers().get(header::CONTENT_ENCODING).is_none());
+        assert!(image.headers().get(header::VARY).is_none());
+
+        let small = compress(Some("br"), response("text/plain", b"small"));
+        assert!(small.headers().get(header::CONTENT_ENCODING).is_none());
+    }
 }
@@ -0,0 +1,455 @@
+//! Conversion of MediaWiki wikitext to markdown.
+//!
+//! The common block syntax (headings, lists, tables, preformatted text and rules) and inline
+//! syntax (bold, italic, internal and external links) is converted. Everything else, e.g. templates
+//! and HTML tags, is kept as is.
+
+use lazy_static::lazy_static;
+
+use regex::{Captures, Regex};
+
+use crate::directive::slug;
+
+lazy_static! {
+    static ref HEADING: Regex = Regex::new(r"^(={1,6})\s*(.+?)\s*(={1,6})\s*$").unwrap();
+    static ref REDIRECT: Regex = Regex::new(r"(?i)^#redirect\s*:?\s*(\[\[.*?\]\])").unwrap();
+    static ref INTERNAL_LINK: Regex =
+        Regex::new(r"\[\[([^\[\]|]*)(?:\|([^\[\]]*))?\]\]([a-z]*)").unwrap();
+    static ref EXTERNAL_LINK: Regex =
+        Regex::new(r"\[((?:https?://|ftp://|mailto:|//)[^\s\]]+)(?:\s+([^\]]*))?\]").unwrap();
+    static ref BOLD_ITALIC: Regex = Regex::new(r"'''''(.+?)'''''").unwrap();
+    static ref BOLD: Regex = Regex::new(r"'''(.+?)'''").unwrap();
+    static ref ITALIC: Regex = Regex::new(r"''(.+?)''").unwrap();
+    static ref MAGIC_WORD: Regex = Regex::new(r"__[A-Z]+__").unwrap();
+}
+
+/// Options of image links that are not the caption.
+const IMAGE_OPTIONS: &[&str] = &[
+    "thumb",
+    "thumbnail",
+    "frame",
+    "framed",
+    "frameless",
+    "border",
+    "left",
+    "right",
+    "center",
+    "centre",
+    "none",
+    "upright",
+    "baseline",
+    "middle",
+    "sub",
+    "super",
+    "top",
+    "text-top",
+    "bottom",
+    "text-bottom",
+];
+
+/// The target of an internal link.
+pub enum Link {
+    /// A page at the given URL.
+    Page(String),
+    /// A file (image) at the given URL.
+    File(String),
+}
+
+/// Kinds of blocks. Markdown needs empty lines between some blocks that are adjacent in wikitext.
+#[derive(Clone, Copy, PartialEq)]
+enum Block {
+    Empty,
+    Paragraph,
+    List,
+    Quote,
+    Other,
+}
+
+/// Converts wikitext to markdown. `link` resolves the title of an internal link (without the
+/// section) to its target.
+pub fn to_markdown(wikitext: &str, link: &dyn Fn(&str) -> Link) -> String {
+    let converter = Converter { link };
+    let mut output: Vec<String> = vec![];
+    let mut previous = Block::Empty;
+    let mut push = |output: &mut Vec<String>, block: Block, text: String| {
+        let separate = previous != block || block == Block::Other;
+        if separate && previous != Block::Empty && block != Block::Empty {
+            output.push(String::new());
+        }
+        previous = block;
+        output.push(text);
+    };
+
+    let mut lines = wikitext.lines().peekable();
+    if let Some(redirect) = lines.peek().and_then(|line| REDIRECT.captures(line)) {
+        let text = format!("Redirect to {}", converter.inline(&redirect[1]));
+        push(&mut output, Block::Paragraph, text);
+        lines.next();
+    }
+    while let Some(line) = lines.next() {
+        if line.trim_start().starts_with("{|") {
+            let mut table = vec![];
+            for line in lines.by_ref() {
+                if line.trim_start().starts_with("|}") {
+                    break;
+                }
+                table.push(line);
+            }
+            push(&mut output, Block::Other, converter.table(&table));
+        } else if line.starts_with(' ') && !line.trim().is_empty() {
+            let mut code = vec![&line[1..]];
+            while let Some(line) = lines.peek() {
+                if !line.starts_with(' ') || line.trim().is_empty() {
+                    break;
+                }
+                code.push(&line[1..]);
+                lines.next();
+            }
+            push(
+                &mut output,
+                Block::Other,
+                format!("```\n{}\n```", code.join("\n")),
+            );
+        } else if let Some(heading) = HEADING.captures(line) {
+            let level = heading[1].len().min(heading[3].len());
+            let text = format!("{} {}", "#".repeat(level), converter.inline(&heading[2]));
+            push(&mut output, Block::Other, text);
+        } else if line.starts_with("----") {
+            push(&mut output, Block::Other, "---".to_string());
+            let rest = line.trim_start_matches('-').trim();
+            if !rest.is_empty() {
+                push(&mut output, Block::Paragraph, converter.inline(rest));
+            }
+        } else if line.starts_with(|c| "*#:;".contains(c)) {
+            let (block, text) = converter.list_item(line);
+            push(&mut output, block, text);
+        } else if line.trim().is_empty() {
+            push(&mut output, Block::Empty, String::new());
+        } else {
+            push(&mut output, Block::Paragraph, converter.inline(line));
+        }
+    }
+
+    let mut markdown = output.join("\n");
+    markdown.push('\n');
+    markdown
+}
+
+struct Converter<'a> {
+    link: &'a dyn Fn(&str) -> Link,
+}
+impl<'a> Converter<'a> {
+    /// Converts a line starting with list markers (`*`, `#`, `:` and `;`).
+    fn list_item(&self, line: &str) -> (Block, String) {
+        let text = line.trim_start_matches(|c| "*#:;".contains(c));
+        let markers = &line[..line.len() - text.len()];
+        let text = text.trim();
+
+        // Indented text without a list is used for discussions. A quote is the closest match.
+        if markers.chars().all(|c| c == ':') {
+            let text = format!("{} {}", ">".repeat(markers.len()), self.inline(text));
+            return (Block::Quote, text);
+        }
+
+        // Nested items are indented by the width of their parents' markers.
+        let indent = markers[..markers.len() - 1]
+            .chars()
+            .map(|c| if c == '#' { 3 } else { 2 })
+            .sum::<usize>();
+        let item = match markers.chars().last().unwrap() {
+            '*' => format!("- {}", self.inline(text)),
+            '#' => format!("1. {}", self.inline(text)),
+            ';' => match split_definition(text) {
+                (term, Some(definition)) => {
+                    format!("**{}**: {}", self.inline(term), self.inline(definition))
+                }
+                (term, None) => format!("**{}**", self.inline(term)),
+            },
+            _ => self.inline(text),
+        };
+        let block = if indent == 0 && markers.starts_with(';') {
+            Block::Paragraph
+        } else {
+            Block::List
+        };
+        (block, format!("{}{}", " ".repeat(indent), item))
+    }
+
+    /// Converts the lines of a table (without `{|` and `|}`) to a GFM table.
+    fn table(&self, lines: &[&str]) -> String {
+        let mut caption = None;
+        // Cells and whether they are headers.
+        let mut rows: Vec<Vec<(bool, String)>> = vec![vec![]];
+        for line in lines {
+            let line = line.trim();
+            if let Some(caption_text) = line.strip_prefix("|+") {
+                caption = Some(cell_content(caption_text).trim().to_string());
+            } else if line.starts_with("|-") {
+                if !rows.last().unwrap().is_empty() {
+                    rows.push(vec![]);
+                }
+            } else if let Some(cells) = line.strip_prefix('!') {
+                for cell in cells.split("!!").flat_map(|cell| cell.split("||")) {
+                    rows.last_mut()
+                        .unwrap()
+                        .push((true, cell_content(cell).to_string()));
+                }
+            } else if let Some(cells) = line.strip_prefix('|') {
+                for cell in cells.split("||") {
+                    rows.last_mut()
+                        .unwrap()
+                        .push((false, cell_content(cell).to_string()));
+                }
+            } else if let Some(cell) = rows.last_mut().unwrap().last_mut() {
+                // Continuation of a multi-line cell.
+                cell.1.push(' ');
+                cell.1.push_str(line);
+            }
+        }
+        rows.retain(|row| !row.is_empty());
+
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0).max(1);
+        let has_header = rows
+            .first()
+            .is_some_and(|row| row.iter().all(|&(header, _)| header));
+        let header = if has_header { rows.remove(0) } else { vec![] };
+
+        let mut table = vec![];
+        if let Some(caption) = caption {
+            table.push(format!("**{}**", self.inline(&caption)));
+            table.push(String::new());
+        }
+        table.push(self.table_row(&header, columns));
+        table.push(format!("|{}", " --- |".repeat(columns)));
+        for row in &rows {
+            table.push(self.table_row(row, columns));
+        }
+        table.join("\n")
+    }
+
+    fn table_row(&self, cells: &[(bool, String)], columns: usize) -> String {
+        let mut row = "|".to_string();
+        for column in 0..columns {
+            let text = match cells.get(column) {
+                Some((_, text)) => self.inline(text.trim()).replace('|', "\\|"),
+                None => String::new(),
+            };
+            row += &format!(" {} |", text);
+        }
+        row
+    }
+
+    /// Converts inline markup.
+    fn inline(&self, text: &str) -> String {
+        let text = INTERNAL_LINK.replace_all(text, |captures: &Captures| {
+            self.internal_link(
+                &captures[1],
+                captures.get(2).map(|label| label.as_str()),
+                &captures[3],
+            )
+        });
+        let text = EXTERNAL_LINK.replace_all(&text, |captures: &Captures| match captures.get(2) {
+            Some(label) => format!("[{}]({})", label.as_str(), &captures[1]),
+            None => format!("<{}>", &captures[1]),
+        });
+        let text = BOLD_ITALIC.replace_all(&text, "***$1***");
+        let text = BOLD.replace_all(&text, "**$1**");
+        let text = ITALIC.replace_all(&text, "*$1*");
+        MAGIC_WORD.replace_all(&text, "").to_string()
+    }
+
+    fn internal_link(&self, target: &str, label: Option<&str>, trail: &str) -> String {
+        // A leading colon links to a file or category instead of embedding it.
+        let (embed, target) = match target.trim().strip_prefix(':') {
+            Some(target) => (false, target),
+            None => (true, target.trim()),
+        };
+        let (title, section) = match target.find('#') {
+            Some(index) => (&target[..index], Some(&target[index + 1..])),
+            None => (target, None),
+        };
+        let fragment = section
+            .map(|section| format!("#{}", slug(&section.replace('_', " "))))
+            .unwrap_or_default();
+
+        if title.is_empty() {
+            let text = label.or(section).unwrap_or("");
+            return format!("[{}{}](<{}>)", text, trail, fragment);
+        }
+        match (self.link)(title) {
+            Link::File(url) if embed => {
+                let caption = label
+                    .and_then(|label| {
+                        label
+                            .split('|')
+                            .rev()
+                            .find(|option| !is_image_option(option.trim()))
+                    })
+                    .unwrap_or("");
+                format!("![{}](<{}>){}", caption.trim(), url, trail)
+            }
+            Link::Page(url) | Link::File(url) => {
+                let text = label.filter(|label| !label.is_empty()).unwrap_or(target);
+                format!("[{}{}](<{}{}>)", text, trail, url, fragment)
+            }
+        }
+    }
+}
+
+/// Removes the attributes of a table cell (`style="..." | content`).
+fn cell_content(cell: &str) -> &str {
+    match cell.find('|') {
+        Some(index) if !cell[..index].contains("[[") && !cell[..index].contains("{{") => {
+            &cell[index + 1..]
+        }
+        _ => cell,
+    }
+}
+
+/// Splits `term : definition` at the first colon outside of links.
+fn split_definition(text: &str) -> (&str, Option<&str>) {
+    let mut depth = 0;
+    for (index, c) in text.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            ':' if depth == 0 => return (text[..index].trim(), Some(text[index + 1..].trim())),
+            _ => {}
+        }
+    }
+    (text, None)
+}
+
+fn is_image_option(option: &str) -> bool {
+    IMAGE_OPTIONS.contains(&option)
+        || option.ends_with("px")
+        || option.starts_with("alt=")
+        || option.starts_with("link=")
+        || option.starts_with("upright=")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wikitext::{to_markdown, Link};
+
+    fn link(title: &str) -> Link {
+        if let Some(file) = title.strip_prefix("File:") {
+            Link::File(format!("/File/{}", file))
+        } else {
+            Link::Page(format!("/{}.md", title.replace(' ', "_")))
+        }
+    }
+
+    #[test]
+    fn inline() {
+        assert_eq!(
+            to_markdown("'''bold''', ''italic'' and '''''both'''''", &link),
+            "**bold**, *italic* and ***both***\n"
+        );
+        assert_eq!(
+            to_markdown(
+                "[[Main Page]], [[Main Page#Some section|main]] and [[dog]]s",
+                &link
+            ),
+            "[Main Page](</Main_Page.md>), [main](</Main_Page.md#some-section>) and \
+             [dogs](</dog.md>)\n"
+        );
+        assert_eq!(
+            to_markdown(
+                "[[File:A.png|thumb|200px|A caption]] [[:File:A.png]]",
+                &link
+            ),
+            "![A caption](</File/A.png>) [File:A.png](</File/A.png>)\n"
+        );
+        assert_eq!(
+            to_markdown(
+                "[https://example.com Example] and [https://example.com]",
+                &link
+            ),
+            "[Example](https://example.com) and <https://example.com>\n"
+        );
+        assert_eq!(to_markdown("__TOC__Text", &link), "Text\n");
+        assert_eq!(
+            to_markdown("#REDIRECT [[Other page]]", &link),
+            "Redirect to [Other page](</Other_page.md>)\n"
+        );
+    }
+
+    #[test]
+    fn blocks() {
+        let wikitext = "\
+= Title =
+Intro text
+continued.
+== Section ==
+* one
+** nested
+*# numbered
+# first
+#* bullet
+: indented
+;Term : definition
+----
+ code line
+ more code
+Done.";
+        let markdown = "\
+# Title
+
+Intro text
+continued.
+
+## Section
+
+- one
+  - nested
+  1. numbered
+1. first
+   - bullet
+
+> indented
+
+**Term**: definition
+
+---
+
+```
+code line
+more code
+```
+
+Done.
+";
+        assert_eq!(to_markdown(wikitext, &link), markdown);
+    }
+
+    #[test]
+    fn tables() {
+        let wikitext = "\
+{| class=\"wikitable\"
+|+ Caption
+|-
+! Name !! Value
+|-
+| style=\"color: red\" | a || [[Main Page|main]]
+|-
+| b
+| multi
+line
+|}";
+        let markdown = "\
+**Caption**
+
+| Name | Value |
+| --- | --- |
+| a | [main](</Main_Page.md>) |
+| b | multi line |
+";
+        assert_eq!(to_markdown(wikitext, &link), markdown);
+
+        assert_eq!(
+            to_markdown("{|\n| a || b\n|}", &link),
+            "|  |  |\n| --- | --- |\n| a | b |\n"
+        );
+    }
+}